@@ -1,48 +1,35 @@
-use toml::Value;
+use anyhow::Result;
+use guest_sdk::Config;
+use std::time::Duration;
 
 pub struct Configuration {
-    config_value: Value,
+    // Http post url e.g. an endpoint on https://requestbin.com/
+    pub http_post_url: String,
+    pub http_post_interval: Duration,
+    // Maximum random delay added to each http post interval
+    pub http_post_jitter: Duration,
+    // Http post's expected return code
+    pub http_post_response_code: u16,
+    // Topic name to subscribe to
+    pub topic: String,
 }
 
 impl Configuration {
-    pub fn new(configfilecontents: String) -> Self {
-        Self {
-            config_value: configfilecontents.parse::<Value>().unwrap(),
-        }
+    pub fn load(config_file_path: &str) -> Result<Self> {
+        Self::new(&Config::load(config_file_path)?)
     }
 
-    // Returns Http post url e.g. an endpoint on https://requestbin.com/
-    pub fn http_post_url(&self) -> String {
-        self.config_value["http_post_url"]
-            .as_str()
-            .unwrap()
-            .to_string()
-    }
-
-    // Returns http post interval in milliseconds
-    pub fn http_post_interval_in_milliseconds(&self) -> u64 {
-        self.config_value["http_post_interval_in_milliseconds"]
-            .as_str()
-            .unwrap()
-            .parse::<u64>()
-            .unwrap()
-    }
-
-    // Returns http post's expected return code
-    pub fn http_post_response_code(&self) -> u16 {
-        self.config_value["http_post_response_code"]
-            .as_str()
-            .unwrap()
-            .parse::<u16>()
-            .unwrap()
-    }
-
-    // Returns topic name to subscribe to
-    pub fn topic(&self) -> String {
-        self.config_value["topic"]
-            .as_str()
-            .unwrap()
-            .parse::<String>()
-            .unwrap()
+    pub fn new(config: &Config) -> Result<Self> {
+        Ok(Self {
+            http_post_url: config.get("http_post_url")?,
+            http_post_interval: Duration::from_millis(
+                config.get("http_post_interval_in_milliseconds")?,
+            ),
+            http_post_jitter: Duration::from_millis(
+                config.get_or("http_post_jitter_in_milliseconds", 0)?,
+            ),
+            http_post_response_code: config.get("http_post_response_code")?,
+            topic: config.get("topic")?,
+        })
     }
 }
@@ -1,77 +1,193 @@
-wit_bindgen_rust::import!("../wits/hostobservability.wit");
-wit_bindgen_rust::export!("../wits/wasmgatewayfunctions.wit");
-
 mod config;
 
-use bytes::Bytes;
-use std::fs;
-use std::thread::sleep;
-use std::time::Duration;
-use wasi_experimental_http as outbound_http;
-
-pub type Request = http::Request<Option<bytes::Bytes>>;
-pub type Response = http::Response<Option<bytes::Bytes>>;
-
-const MODULE_NAME: &str = "Data Egress Gateway";
-
-struct Wasmgatewayfunctions;
-
-impl wasmgatewayfunctions::Wasmgatewayfunctions for Wasmgatewayfunctions {
-    // Initialise module with required configuration.
-    fn init(config_file_path: String) {
-        let config_file_contents = fs::read_to_string(config_file_path).unwrap();
-        let config = config::Configuration::new(config_file_contents);
-
-        hostobservability::loginfo(
-            MODULE_NAME,
-            &format!(
-                "Initialising module with http_post_url: {}, http_post_response_code: {}, http_post_interval_in_milliseconds: {}.",
-                config.http_post_url(),
-                config.http_post_response_code(),
-                config.http_post_interval_in_milliseconds(),
-            ),
+use anyhow::Result;
+use guest_sdk::{
+    debug, http, info, metrics, pubsub,
+    timers::{self, MissedTickPolicy},
+    warn, Lifecycle,
+};
+use std::time::Instant;
+
+guest_sdk::export_module!(GatewayModule);
+
+struct GatewayModule {
+    config: config::Configuration,
+    message_count: u32,
+}
+
+impl Lifecycle for GatewayModule {
+    // Initialise module with required configuration, module uses no sockets and schedules its work on timers.
+    fn init(config_file_path: &str, _socket_fds: &[u32]) -> Result<Self> {
+        let config = config::Configuration::load(config_file_path)?;
+
+        info!(
+            "Initialising module.";
+            "http_post_url" => config.http_post_url,
+            "http_post_response_code" => config.http_post_response_code,
+            "http_post_interval_in_milliseconds" => config.http_post_interval.as_millis(),
+        );
+
+        // Host calls on_tick at the configured interval, restarting the interval after each slow post.
+        timers::interval(
+            config.http_post_interval,
+            config.http_post_jitter,
+            MissedTickPolicy::Delay,
+        );
+
+        Ok(Self {
+            config,
+            message_count: 0,
+        })
+    }
+
+    fn on_tick(&mut self, _timer_id: u32) {
+        let message = match pubsub::read(&self.config.topic) {
+            Ok(Some(message)) => message,
+            Ok(None) => {
+                debug!("No message on topic '{}'.", self.config.topic);
+                return;
+            }
+            Err(e) => {
+                warn!("Failed to read from pubsub; error = {e}");
+                return;
+            }
+        };
+
+        debug!("Received message {} from pubsub.", message.payload);
+
+        self.message_count += 1;
+
+        // Post carries the message's trace, so the receiving service continues it.
+        let request_started = Instant::now();
+        let http_response = http::post(
+            &self.config.http_post_url,
+            &[("Content-Type", "application/text")],
+            message.payload.into_bytes(),
+        )
+        .expect("Could not make post request.");
+        let status_code = http_response.status_code.to_string();
+
+        metrics::counter_increment(
+            "gateway_messages_sent_total",
+            1,
+            &[("status_code", &status_code)],
+        );
+        metrics::histogram_record(
+            "gateway_request_duration_seconds",
+            request_started.elapsed().as_secs_f64(),
+            &[],
+        );
+
+        info!(
+            "Message sent.";
+            "message_count" => self.message_count,
+            "status_code" => status_code,
+        );
+
+        assert_eq!(
+            http_response.status_code,
+            self.config.http_post_response_code
+        );
+    }
+
+    // Host stops calling on_tick before shutdown, so no post is in flight here.
+    fn shutdown(&mut self, _deadline: std::time::Duration) {
+        info!("Shutting down module."; "message_count" => self.message_count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guest_sdk::logging::Level;
+    use guest_sdk::testing::{FakeHost, MetricKind, Schedule};
+    use std::time::Duration;
+
+    // Module's own config file, posts go to the fake host rather than the configured endpoint.
+    const CONFIG_FILE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/config.toml");
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn init_schedules_posts() {
+        let host = FakeHost::start();
+
+        GatewayModule::init(CONFIG_FILE_PATH, &[]).unwrap();
+
+        let timers = host.timers();
+        assert_eq!(timers.len(), 1);
+        assert_eq!(
+            timers[0].schedule,
+            Schedule::Interval(Duration::from_millis(10))
         );
+        assert_eq!(timers[0].policy, MissedTickPolicy::Delay);
+        assert!(host.logged(Level::Info, "Initialising module."));
+    }
+
+    #[test]
+    fn init_fails_without_config_file() {
+        let _host = FakeHost::start();
+
+        assert!(GatewayModule::init("missing.toml", &[]).is_err());
+    }
+
+    #[test]
+    fn tick_posts_message_with_its_trace() {
+        let host = FakeHost::start();
+        let mut module = GatewayModule::init(CONFIG_FILE_PATH, &[]).unwrap();
+        host.publish("telemetry", "temperature=21");
+        host.set_traceparent(Some(TRACEPARENT));
+
+        module.on_tick(1);
+
+        let requests = host.http_requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].url, module.config.http_post_url);
+        assert_eq!(requests[0].body.as_deref(), Some(&b"temperature=21"[..]));
+        assert!(requests[0]
+            .headers
+            .contains(&("traceparent".to_string(), TRACEPARENT.to_string())));
+
+        assert!(host.messages("telemetry").is_empty());
+        assert!(host.metrics().iter().any(|metric| {
+            metric.kind == MetricKind::Counter
+                && metric.name == "gateway_messages_sent_total"
+                && metric.labels == [("status_code".to_string(), "200".to_string())]
+        }));
+        assert_eq!(module.message_count, 1);
+    }
+
+    #[test]
+    fn tick_skips_post_when_topic_is_empty() {
+        let host = FakeHost::start();
+        let mut module = GatewayModule::init(CONFIG_FILE_PATH, &[]).unwrap();
+
+        module.on_tick(1);
+
+        assert!(host.http_requests().is_empty());
+        assert_eq!(module.message_count, 0);
+    }
+
+    #[test]
+    fn tick_warns_when_read_fails() {
+        let host = FakeHost::start();
+        let mut module = GatewayModule::init(CONFIG_FILE_PATH, &[]).unwrap();
+        host.fail_reads("telemetry", "not authorised to read topic 'telemetry'");
+
+        module.on_tick(1);
+
+        assert!(host.logged(Level::Warn, "not authorised to read topic 'telemetry'"));
+        assert!(host.http_requests().is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn tick_traps_on_unexpected_response_code() {
+        let host = FakeHost::start();
+        let mut module = GatewayModule::init(CONFIG_FILE_PATH, &[]).unwrap();
+        host.publish("telemetry", "temperature=21");
+        host.set_http_response(500, b"");
 
-        let mut message_count = 0;
-
-        loop {
-            let post_url = config.http_post_url();
-            let http_request = http::request::Builder::new()
-                .method(http::Method::POST)
-                .uri(post_url)
-                .header("Content-Type", "application/text");
-            let message = hostobservability::read(&config.topic());
-
-            hostobservability::loginfo(
-                MODULE_NAME,
-                &format!("Received message {message} from pubsub."),
-            );
-
-            message_count += 1;
-
-            let http_request = http_request.body(Some(Bytes::from(message))).unwrap();
-            let http_response =
-                outbound_http::request(http_request).expect("Could not make post request.");
-
-            hostobservability::loginfo(
-                MODULE_NAME,
-                &format!(
-                    "Message {} sent, http status returned: {}",
-                    message_count, http_response.status_code
-                ),
-            );
-
-            assert_eq!(http_response.status_code, config.http_post_response_code());
-
-            // Uncomment this if you need to read the response's body, in our case we have an empty response
-            // let http_response_body = std::str::from_utf8(&http_response.body_read_all().unwrap())
-            //                         .unwrap()
-            //                         .to_string();
-
-            // Wait for configured time
-            sleep(Duration::from_millis(
-                config.http_post_interval_in_milliseconds(),
-            ));
-        }
+        module.on_tick(1);
     }
 }
@@ -0,0 +1,110 @@
+// Module configuration, read from the config file the host maps into the module and passes to 'init'. Values are
+// strings parsed on access, lists are space separated, e.g. topics = 'alert telemetry'.
+use anyhow::{anyhow, bail, Context, Result};
+use std::{fmt::Display, fs, str::FromStr};
+use toml::{Table, Value};
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    value: Value,
+}
+
+impl Config {
+    pub fn load(config_file_path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(config_file_path)
+            .with_context(|| format!("Failed to read config file '{config_file_path}'."))?;
+
+        Self::parse(&contents)
+            .with_context(|| format!("Failed to parse config file '{config_file_path}'."))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        // Parsed as a document, newer toml versions parse a 'Value' as a single value.
+        let table = contents.parse::<Table>()?;
+
+        Ok(Self {
+            value: Value::Table(table),
+        })
+    }
+
+    // Returns a required value, failing when it is missing or does not parse.
+    pub fn get<T>(&self, key: &str) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.get_optional(key)?
+            .ok_or_else(|| anyhow!("Missing configuration value '{key}'."))
+    }
+
+    // Returns a value or the default when it is missing, failing when it does not parse.
+    pub fn get_or<T>(&self, key: &str, default: T) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        Ok(self.get_optional(key)?.unwrap_or(default))
+    }
+
+    pub fn get_optional<T>(&self, key: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = match self.value.get(key) {
+            Some(Value::String(value)) => value.clone(),
+            // Plain toml values are accepted too, e.g. interval = 10.
+            Some(value) if !value.is_table() && !value.is_array() => value.to_string(),
+            Some(_) => bail!("Configuration value '{key}' is not a string."),
+            None => return Ok(None),
+        };
+
+        value
+            .parse::<T>()
+            .map(Some)
+            .map_err(|e| anyhow!("Invalid configuration value '{key}' = '{value}': {e}."))
+    }
+
+    // Returns a space separated list, empty when missing.
+    pub fn list(&self, key: &str) -> Result<Vec<String>> {
+        Ok(self
+            .get_or(key, String::new())?
+            .split_whitespace()
+            .map(|item| item.to_string())
+            .collect())
+    }
+
+    // Returns a section e.g. [rpc], None when missing.
+    pub fn section(&self, key: &str) -> Option<Config> {
+        self.value
+            .get(key)
+            .filter(|value| value.is_table())
+            .map(|value| Config {
+                value: value.clone(),
+            })
+    }
+
+    // Returns the named sections under a key e.g. [identities.host] and [identities.operator], in name order.
+    pub fn sections(&self, key: &str) -> Vec<(String, Config)> {
+        match self.value.get(key).and_then(|value| value.as_table()) {
+            Some(table) => table
+                .iter()
+                .filter(|(_, value)| value.is_table())
+                .map(|(name, value)| {
+                    (
+                        name.to_string(),
+                        Config {
+                            value: value.clone(),
+                        },
+                    )
+                })
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    // Returns the raw toml, for configuration the accessors don't cover.
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+}
@@ -0,0 +1,42 @@
+// Host functions the SDK wraps, the only place it calls them. Components call the interfaces of wits/component behind
+// the same functions, and native builds call the in-memory fake in 'testing' instead, so module logic can be tested
+// without the host.
+#[cfg(all(target_arch = "wasm32", not(target_env = "p2")))]
+wit_bindgen_rust::import!("../wits/hostobservability.wit");
+
+#[cfg(all(target_arch = "wasm32", not(target_env = "p2")))]
+pub use hostobservability::*;
+
+#[cfg(all(target_arch = "wasm32", target_env = "p2"))]
+pub mod component;
+
+#[cfg(all(target_arch = "wasm32", target_env = "p2"))]
+pub use component::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::testing::host::*;
+
+// Outbound http goes through wasi-experimental-http rather than a WIT import. Returns the response's status code
+// and body.
+#[cfg(all(target_arch = "wasm32", not(target_env = "p2"), feature = "http"))]
+pub fn http_request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Option<Vec<u8>>,
+) -> anyhow::Result<(u16, Vec<u8>)> {
+    let mut request = http::request::Builder::new().method(method).uri(url);
+
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+
+    let request = request.body(body.map(bytes::Bytes::from))?;
+    let mut response = wasi_experimental_http::request(request)
+        .map_err(|e| anyhow::anyhow!("Failed to make {method} request to '{url}': {e}."))?;
+    let body = response
+        .body_read_all()
+        .map_err(|e| anyhow::anyhow!("Failed to read response from '{url}': {e}."))?;
+
+    Ok((response.status_code.as_u16(), body))
+}
@@ -0,0 +1,152 @@
+// Host functions for components, through the interfaces of wits/component behind the signatures of the core module
+// bindings the rest of the SDK calls. Host functions components cannot use yet fail.
+wit_bindgen::generate!({
+    path: "../wits/component",
+    world: "lifecycle-module",
+});
+
+use iot::host::{logging, metrics, pubsub, runtime, timers};
+use std::{cell::RefCell, collections::HashMap};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Loglevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Missedtickpolicy {
+    Skip,
+    Burst,
+    Delay,
+}
+
+impl From<Missedtickpolicy> for timers::MissedTickPolicy {
+    fn from(policy: Missedtickpolicy) -> Self {
+        match policy {
+            Missedtickpolicy::Skip => timers::MissedTickPolicy::Skip,
+            Missedtickpolicy::Burst => timers::MissedTickPolicy::Burst,
+            Missedtickpolicy::Delay => timers::MissedTickPolicy::Delay,
+        }
+    }
+}
+
+thread_local! {
+    // Timers by id, the host cancels a timer once it is dropped.
+    static TIMERS: RefCell<HashMap<u32, timers::Timer>> = RefCell::new(HashMap::new());
+}
+
+pub fn log(level: Loglevel, message: &str, fields: &[(&str, &str)]) {
+    let level = match level {
+        Loglevel::Trace => logging::LogLevel::Trace,
+        Loglevel::Debug => logging::LogLevel::Debug,
+        Loglevel::Info => logging::LogLevel::Info,
+        Loglevel::Warn => logging::LogLevel::Warn,
+        Loglevel::Error => logging::LogLevel::Error,
+    };
+
+    logging::log(level, message, &to_owned(fields));
+}
+
+pub fn publish(topic: &str, message: &str) {
+    pubsub::publish(topic, message);
+}
+
+pub fn publishwithoptions(
+    topic: &str,
+    message: &str,
+    ttlinmilliseconds: Option<u64>,
+    delayinmilliseconds: Option<u64>,
+) {
+    pubsub::publish_with_options(topic, message, ttlinmilliseconds, delayinmilliseconds);
+}
+
+pub fn read(topic: &str) -> Result<Option<String>, String> {
+    pubsub::read(topic)
+}
+
+pub fn subscribe(topic: &str) -> Result<Option<String>, String> {
+    Err(format!("components cannot subscribe to topic '{topic}'"))
+}
+
+pub fn traceparent() -> Option<String> {
+    pubsub::traceparent()
+}
+
+pub fn shutdownrequested() -> bool {
+    runtime::shutdown_requested()
+}
+
+pub fn scheduleinterval(
+    intervalinmilliseconds: u64,
+    jitterinmilliseconds: u64,
+    missedtickpolicy: Missedtickpolicy,
+) -> u32 {
+    keep(timers::Timer::interval(
+        intervalinmilliseconds,
+        jitterinmilliseconds,
+        missedtickpolicy.into(),
+    ))
+}
+
+pub fn schedulecron(
+    expression: &str,
+    jitterinmilliseconds: u64,
+    missedtickpolicy: Missedtickpolicy,
+) -> Result<u32, String> {
+    timers::Timer::cron(expression, jitterinmilliseconds, missedtickpolicy.into()).map(keep)
+}
+
+pub fn canceltimer(timerid: u32) {
+    TIMERS.with(|timers| timers.borrow_mut().remove(&timerid));
+}
+
+pub fn counterincrement(name: &str, value: u64, labels: &[(&str, &str)]) {
+    metrics::counter_increment(name, value, &to_owned(labels));
+}
+
+pub fn gaugeset(name: &str, value: f64, labels: &[(&str, &str)]) {
+    metrics::gauge_set(name, value, &to_owned(labels));
+}
+
+pub fn histogramrecord(name: &str, value: f64, labels: &[(&str, &str)]) {
+    metrics::histogram_record(name, value, &to_owned(labels));
+}
+
+pub fn callhandler(
+    modulename: &str,
+    handler: &str,
+    _payload: &str,
+    _timeoutinmilliseconds: u64,
+) -> Result<String, String> {
+    Err(format!("components cannot call '{modulename}.{handler}'"))
+}
+
+#[cfg(feature = "http")]
+pub fn http_request(
+    method: &str,
+    url: &str,
+    _headers: &[(&str, &str)],
+    _body: Option<Vec<u8>>,
+) -> anyhow::Result<(u16, Vec<u8>)> {
+    anyhow::bail!(
+        "Failed to make {method} request to '{url}': components cannot make outbound http requests."
+    )
+}
+
+// Keeps a timer until it is cancelled, returns its id.
+fn keep(timer: timers::Timer) -> u32 {
+    let id = timer.id();
+    TIMERS.with(|timers| timers.borrow_mut().insert(id, timer));
+    id
+}
+
+fn to_owned(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
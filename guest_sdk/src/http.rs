@@ -0,0 +1,36 @@
+// Outbound http requests made by the host on the module's behalf, to hosts allowed in the module's configuration.
+// Requests carry the module's current trace in a traceparent header, so receiving services continue it.
+use crate::{host, runtime};
+use anyhow::Result;
+
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub status_code: u16,
+    pub body: Vec<u8>,
+}
+
+pub fn get(url: &str, headers: &[(&str, &str)]) -> Result<Response> {
+    request("GET", url, headers, None)
+}
+
+pub fn post(url: &str, headers: &[(&str, &str)], body: Vec<u8>) -> Result<Response> {
+    request("POST", url, headers, Some(body))
+}
+
+pub fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Option<Vec<u8>>,
+) -> Result<Response> {
+    let traceparent = runtime::traceparent();
+    let mut headers = headers.to_vec();
+
+    if let Some(traceparent) = &traceparent {
+        headers.push(("traceparent", traceparent));
+    }
+
+    let (status_code, body) = host::http_request(method, url, &headers, body)?;
+
+    Ok(Response { status_code, body })
+}
@@ -0,0 +1,33 @@
+// Guest SDK for modules run by the host, core modules (wasm32-wasi) or components (wasm32-wasip2). Wraps the host
+// functions of wits/hostobservability.wit, or the interfaces of wits/component for components, in logging macros, a
+// typed pubsub client, timers, metrics and rpc calls, loads module configuration and drives a module through the
+// lifecycle exports with 'export_module!', so a module only implements the 'Lifecycle' trait. Native builds run
+// against the fake host in 'testing' instead, for testing modules with 'cargo test'.
+pub mod config;
+mod host;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod lifecycle;
+pub mod logging;
+pub mod metrics;
+pub mod pubsub;
+pub mod rpc;
+pub mod runtime;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod testing;
+pub mod timers;
+
+pub use config::Config;
+pub use lifecycle::Lifecycle;
+
+// Re-exported for modules and for the code 'export_module!' generates in them.
+pub use anyhow;
+
+// WIT contracts a module built with the SDK is built against, embedded in the module by 'export_module!' so the
+// host can check them against its own when loading the module.
+#[doc(hidden)]
+pub const HOST_CONTRACT: [u8; include_bytes!("../../wits/hostobservability.wit").len()] =
+    *include_bytes!("../../wits/hostobservability.wit");
+#[doc(hidden)]
+pub const LIFECYCLE_CONTRACT: [u8; include_bytes!("../../wits/wasmlifecyclefunctions.wit").len()] =
+    *include_bytes!("../../wits/wasmlifecyclefunctions.wit");
@@ -0,0 +1,147 @@
+// Lifecycle of a module as driven by the host: 'init' once instantiated, 'on_tick' for each timer firing and
+// 'shutdown' before the host stops. A module implements 'Lifecycle' and exports it with 'export_module!(Module)',
+// which also embeds the module's WIT contracts. The SDK exports the host's lifecycle functions itself, for core
+// modules (wasm32-wasi) or components (wasm32-wasip2), and drives the module through a 'Lifecycle' trait object
+// created by the module's 'init'. Native builds export nothing, tests call the module's 'Lifecycle' functions against
+// the fake host in 'testing'.
+use std::time::Duration;
+
+pub trait Lifecycle: Send + 'static {
+    // Called with the module's config file path and the sockets the host pre-opened for it, components are given no
+    // sockets. Failing here fails the module's start.
+    fn init(config_file_path: &str, socket_fds: &[u32]) -> anyhow::Result<Self>
+    where
+        Self: Sized;
+
+    // Called for each firing of the module's timers, ticks don't overlap.
+    fn on_tick(&mut self, _timer_id: u32) {}
+
+    // Called once the host stops calling 'on_tick', the module must be done within the deadline.
+    fn shutdown(&mut self, _deadline: Duration) {}
+}
+
+#[macro_export]
+macro_rules! export_module {
+    ($module:ty) => {
+        // Called by the SDK's lifecycle exports, see 'guest_sdk::lifecycle'.
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        fn guest_sdk_init_module(
+            config_file_path: &str,
+            socket_fds: &[u32],
+        ) -> $crate::anyhow::Result<Box<dyn $crate::Lifecycle>> {
+            Ok(Box::new(<$module as $crate::Lifecycle>::init(
+                config_file_path,
+                socket_fds,
+            )?))
+        }
+
+        // Components carry their contract's version in their interface names instead.
+        #[cfg(all(target_arch = "wasm32", not(target_env = "p2")))]
+        mod guest_sdk_contracts {
+            #[link_section = "iot-host-contract"]
+            #[used]
+            static HOST_CONTRACT: [u8; $crate::HOST_CONTRACT.len()] = $crate::HOST_CONTRACT;
+            #[link_section = "iot-host-contract"]
+            #[used]
+            static LIFECYCLE_CONTRACT: [u8; $crate::LIFECYCLE_CONTRACT.len()] =
+                $crate::LIFECYCLE_CONTRACT;
+        }
+
+        // Natively the module is driven by its tests rather than the host, see 'guest_sdk::testing'.
+        #[cfg(not(target_arch = "wasm32"))]
+        #[allow(dead_code)]
+        fn guest_sdk_lifecycle() {
+            let _ = <$module as $crate::Lifecycle>::init;
+        }
+    };
+}
+
+#[cfg(target_arch = "wasm32")]
+extern "Rust" {
+    // Defined by 'export_module!' in the module's crate, creates the module with its 'Lifecycle::init'.
+    fn guest_sdk_init_module(
+        config_file_path: &str,
+        socket_fds: &[u32],
+    ) -> anyhow::Result<Box<dyn Lifecycle>>;
+}
+
+// Module created by 'init', used by later calls from the host.
+#[cfg(target_arch = "wasm32")]
+static MODULE: std::sync::Mutex<Option<Box<dyn Lifecycle>>> = std::sync::Mutex::new(None);
+
+#[cfg(target_arch = "wasm32")]
+fn init(config_file_path: &str, socket_fds: &[u32]) -> anyhow::Result<()> {
+    // Symbol is defined by the module's 'export_module!', a module built without it fails to link.
+    let module = unsafe { guest_sdk_init_module(config_file_path, socket_fds) }.map_err(|e| {
+        crate::error!("Failed to initialise module; error = {e:#}");
+        e
+    })?;
+    *MODULE.lock().unwrap() = Some(module);
+
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn on_tick(timer_id: u32) {
+    if let Some(module) = MODULE.lock().unwrap().as_mut() {
+        module.on_tick(timer_id);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn shutdown(deadline_in_milliseconds: u64) {
+    if let Some(module) = MODULE.lock().unwrap().as_mut() {
+        module.shutdown(Duration::from_millis(deadline_in_milliseconds));
+    }
+}
+
+// Exports of wits/wasmlifecyclefunctions.wit, for core modules.
+#[cfg(all(target_arch = "wasm32", not(target_env = "p2")))]
+mod core_module {
+    wit_bindgen_rust::export!("../wits/wasmlifecyclefunctions.wit");
+
+    struct Wasmlifecyclefunctions;
+
+    impl wasmlifecyclefunctions::Wasmlifecyclefunctions for Wasmlifecyclefunctions {
+        fn init(config_file_path: String, socket_fds: Vec<u32>) {
+            // Trap so the host sees the failed start.
+            if let Err(e) = super::init(&config_file_path, &socket_fds) {
+                panic!("Failed to initialise module: {e:#}");
+            }
+        }
+
+        fn ontick(timer_id: u32) {
+            super::on_tick(timer_id);
+        }
+
+        fn shutdown(deadline_in_milliseconds: u64) {
+            super::shutdown(deadline_in_milliseconds);
+        }
+    }
+}
+
+// Exports of the lifecycle interface in wits/component, for components.
+#[cfg(all(target_arch = "wasm32", target_env = "p2"))]
+mod component {
+    use crate::host::component::{self, exports::iot::host::lifecycle::Guest};
+
+    struct Exports;
+
+    impl Guest for Exports {
+        // Components open sockets through wasi:sockets, they are given none.
+        fn init(config_file_path: String) -> Result<(), String> {
+            super::init(&config_file_path, &[]).map_err(|e| format!("{e:#}"))
+        }
+
+        fn on_tick(timer_id: u32) {
+            super::on_tick(timer_id);
+        }
+
+        fn shutdown(deadline_in_milliseconds: u64) {
+            super::shutdown(deadline_in_milliseconds);
+        }
+    }
+
+    component::export!(Exports with_types_in crate::host::component);
+}
@@ -0,0 +1,60 @@
+// Structured logging through the host, which stamps each record with the module's name and the current trace.
+// Modules use the macros, e.g. info!("Message sent."; "status_code" => status_code), fields are formatted with
+// Display.
+use crate::host::{self, Loglevel};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+pub fn log(level: Level, message: &str, fields: &[(&str, &str)]) {
+    let level = match level {
+        Level::Trace => Loglevel::Trace,
+        Level::Debug => Loglevel::Debug,
+        Level::Info => Loglevel::Info,
+        Level::Warn => Loglevel::Warn,
+        Level::Error => Loglevel::Error,
+    };
+    host::log(level, message, fields);
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($message:expr),+ $(; $($key:literal => $value:expr),* $(,)?)?) => {
+        $crate::logging::log(
+            $level,
+            &format!($($message),+),
+            &[$($(($key, &$value.to_string())),*)?],
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log!($crate::logging::Level::Trace, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log!($crate::logging::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log!($crate::logging::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log!($crate::logging::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log!($crate::logging::Level::Error, $($arg)+) };
+}
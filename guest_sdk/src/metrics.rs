@@ -0,0 +1,14 @@
+// Metrics recorded by the host under the module's name, labels are added to the module label.
+use crate::host;
+
+pub fn counter_increment(name: &str, value: u64, labels: &[(&str, &str)]) {
+    host::counterincrement(name, value, labels);
+}
+
+pub fn gauge_set(name: &str, value: f64, labels: &[(&str, &str)]) {
+    host::gaugeset(name, value, labels);
+}
+
+pub fn histogram_record(name: &str, value: f64, labels: &[(&str, &str)]) {
+    host::histogramrecord(name, value, labels);
+}
@@ -0,0 +1,57 @@
+// Pubsub client over the host's broker, either the pubsub server module or the host's native broker. Topics a module
+// publishes to, reads from and subscribes to must be granted in its host configuration.
+use crate::host;
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PublishOptions {
+    // Message is dropped, or moved to the dead letter topic, when not read within its time to live.
+    pub ttl: Option<Duration>,
+    // Message is only readable once the delay has passed.
+    pub delay: Option<Duration>,
+}
+
+pub fn publish(topic: &str, payload: &str) {
+    host::publish(topic, payload);
+}
+
+pub fn publish_with_options(topic: &str, payload: &str, options: PublishOptions) {
+    host::publishwithoptions(
+        topic,
+        payload,
+        options.ttl.map(|ttl| ttl.as_millis() as u64),
+        options.delay.map(|delay| delay.as_millis() as u64),
+    );
+}
+
+// Reads the next message on a topic, None when the topic has none. Host calls made after reading a message continue
+// the message's trace.
+pub fn read(topic: &str) -> Result<Option<Message>> {
+    let payload =
+        host::read(topic).map_err(|reason| anyhow!("Failed to read message: {reason}."))?;
+
+    Ok(payload.map(|payload| Message {
+        topic: topic.to_string(),
+        payload,
+    }))
+}
+
+// Receives the next message published to a topic since the module first subscribed to it, None when there is none.
+// Unlike reads, every subscribing module receives each message. The first call subscribes the module and returns None,
+// subscribing needs the host's native broker.
+pub fn subscribe(topic: &str) -> Result<Option<Message>> {
+    let payload = host::subscribe(topic)
+        .map_err(|reason| anyhow!("Failed to receive subscribed message: {reason}."))?;
+
+    Ok(payload.map(|payload| Message {
+        topic: topic.to_string(),
+        payload,
+    }))
+}
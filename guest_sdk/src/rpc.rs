@@ -0,0 +1,12 @@
+// Calls to handlers served by other modules, brokered by the host. Handlers a module calls must be granted in its
+// host configuration as '<module>.<handler>' or '<module>.*'.
+use crate::host;
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+
+// Calls a handler and waits for its reply, for at most the timeout capped by the serving module's maximum. A zero
+// timeout waits for the serving module's maximum.
+pub fn call(module_name: &str, handler: &str, payload: &str, timeout: Duration) -> Result<String> {
+    host::callhandler(module_name, handler, payload, timeout.as_millis() as u64)
+        .map_err(|e| anyhow!("Call to '{module_name}.{handler}' failed: {e}."))
+}
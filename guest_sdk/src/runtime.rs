@@ -0,0 +1,12 @@
+// State the host keeps for the running module.
+use crate::host;
+
+// Returns true once the host has started shutting down, modules running their own loops should stop.
+pub fn shutdown_requested() -> bool {
+    host::shutdownrequested()
+}
+
+// Returns the W3C traceparent of the module's current trace, to propagate it to other services.
+pub fn traceparent() -> Option<String> {
+    host::traceparent()
+}
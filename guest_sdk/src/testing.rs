@@ -0,0 +1,198 @@
+// In-memory fake of the host functions for native builds, so module logic can be tested with 'cargo test' without
+// the host. A test starts a 'FakeHost', drives the module through its 'Lifecycle' and checks what the module logged,
+// published, scheduled, recorded and requested. Topics are plain queues, time to live and delay are recorded but not
+// applied.
+pub(crate) mod host;
+
+use crate::{logging::Level, timers::MissedTickPolicy};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, MutexGuard, OnceLock},
+    time::Duration,
+};
+
+// Tests using the fake run one at a time, as module code may call host functions from any thread.
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogRecord {
+    pub level: Level,
+    pub message: String,
+    pub fields: Vec<(String, String)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Published {
+    pub topic: String,
+    pub payload: String,
+    pub ttl: Option<Duration>,
+    pub delay: Option<Duration>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Schedule {
+    Interval(Duration),
+    Cron(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Timer {
+    pub id: u32,
+    pub schedule: Schedule,
+    pub jitter: Duration,
+    pub policy: MissedTickPolicy,
+    pub cancelled: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    pub kind: MetricKind,
+    pub name: String,
+    pub value: f64,
+    pub labels: Vec<(String, String)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+pub(crate) struct State {
+    logs: Vec<LogRecord>,
+    topics: HashMap<String, VecDeque<String>>,
+    // Messages published to topics since the module first subscribed to them, as the host's native broker keeps them.
+    subscriptions: HashMap<String, VecDeque<String>>,
+    // Reads and subscribes of these topics fail with the reason, as the host fails denied reads.
+    read_errors: HashMap<String, String>,
+    published: Vec<Published>,
+    timers: Vec<Timer>,
+    metrics: Vec<Metric>,
+    http_requests: Vec<HttpRequest>,
+    // Reply to every http request, 200 with an empty body when not set.
+    http_response: Option<Result<(u16, Vec<u8>), String>>,
+    // Replies to rpc calls by '<module>.<handler>', calls to other handlers fail as if no module serves them.
+    call_replies: HashMap<String, Result<String, String>>,
+    traceparent: Option<String>,
+    shutdown_requested: bool,
+}
+
+impl State {
+    // Queues a message on a topic for reads, and on the module's subscription to the topic.
+    fn deliver(&mut self, topic: &str, payload: &str) {
+        self.topics
+            .entry(topic.to_string())
+            .or_default()
+            .push_back(payload.to_string());
+        if let Some(subscription) = self.subscriptions.get_mut(topic) {
+            subscription.push_back(payload.to_string());
+        }
+    }
+}
+
+fn state() -> MutexGuard<'static, State> {
+    STATE
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+// Fake host held by a test, reset when started.
+pub struct FakeHost {
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl FakeHost {
+    pub fn start() -> Self {
+        // A test which failed while holding the fake leaves it poisoned, state is reset anyway.
+        let guard = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        *state() = State::default();
+
+        Self { _guard: guard }
+    }
+
+    // Queues a message on a topic for the module to read, and to receive once it has subscribed to the topic.
+    pub fn publish(&self, topic: &str, payload: &str) {
+        state().deliver(topic, payload);
+    }
+
+    // Fails reads and subscribes of a topic with the reason, as the host does for denied or failed reads.
+    pub fn fail_reads(&self, topic: &str, reason: &str) {
+        state()
+            .read_errors
+            .insert(topic.to_string(), reason.to_string());
+    }
+
+    // Returns messages queued on a topic and not read yet.
+    pub fn messages(&self, topic: &str) -> Vec<String> {
+        state()
+            .topics
+            .get(topic)
+            .map(|queue| queue.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn set_http_response(&self, status_code: u16, body: &[u8]) {
+        state().http_response = Some(Ok((status_code, body.to_vec())));
+    }
+
+    // Fails every http request with the reason, as the host does for denied or failed requests.
+    pub fn fail_http_requests(&self, reason: &str) {
+        state().http_response = Some(Err(reason.to_string()));
+    }
+
+    pub fn set_call_reply(&self, module_name: &str, handler: &str, reply: Result<&str, &str>) {
+        state().call_replies.insert(
+            format!("{module_name}.{handler}"),
+            reply.map(str::to_string).map_err(str::to_string),
+        );
+    }
+
+    pub fn set_traceparent(&self, traceparent: Option<&str>) {
+        state().traceparent = traceparent.map(str::to_string);
+    }
+
+    pub fn request_shutdown(&self) {
+        state().shutdown_requested = true;
+    }
+
+    pub fn logs(&self) -> Vec<LogRecord> {
+        state().logs.clone()
+    }
+
+    // Returns true if any log record at the level contains the text.
+    pub fn logged(&self, level: Level, text: &str) -> bool {
+        state()
+            .logs
+            .iter()
+            .any(|record| record.level == level && record.message.contains(text))
+    }
+
+    // Returns messages the module published, in order.
+    pub fn published(&self) -> Vec<Published> {
+        state().published.clone()
+    }
+
+    pub fn timers(&self) -> Vec<Timer> {
+        state().timers.clone()
+    }
+
+    pub fn metrics(&self) -> Vec<Metric> {
+        state().metrics.clone()
+    }
+
+    pub fn http_requests(&self) -> Vec<HttpRequest> {
+        state().http_requests.clone()
+    }
+}
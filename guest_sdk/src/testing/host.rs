@@ -0,0 +1,217 @@
+// Fake host functions with the signatures wit-bindgen generates for wits/hostobservability.wit, replying the way the
+// host does.
+#[cfg(feature = "http")]
+use super::HttpRequest;
+use super::{state, LogRecord, Metric, MetricKind, Published, Schedule, Timer};
+use crate::{logging::Level, timers::MissedTickPolicy};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Loglevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Missedtickpolicy {
+    Skip,
+    Burst,
+    Delay,
+}
+
+pub fn log(level: Loglevel, message: &str, fields: &[(&str, &str)]) {
+    let level = match level {
+        Loglevel::Trace => Level::Trace,
+        Loglevel::Debug => Level::Debug,
+        Loglevel::Info => Level::Info,
+        Loglevel::Warn => Level::Warn,
+        Loglevel::Error => Level::Error,
+    };
+
+    state().logs.push(LogRecord {
+        level,
+        message: message.to_string(),
+        fields: to_owned(fields),
+    });
+}
+
+pub fn publish(topic: &str, message: &str) {
+    publishwithoptions(topic, message, None, None);
+}
+
+pub fn publishwithoptions(
+    topic: &str,
+    message: &str,
+    ttlinmilliseconds: Option<u64>,
+    delayinmilliseconds: Option<u64>,
+) {
+    let mut state = state();
+
+    state.published.push(Published {
+        topic: topic.to_string(),
+        payload: message.to_string(),
+        ttl: ttlinmilliseconds.map(Duration::from_millis),
+        delay: delayinmilliseconds.map(Duration::from_millis),
+    });
+    state.deliver(topic, message);
+}
+
+pub fn read(topic: &str) -> Result<Option<String>, String> {
+    let mut state = state();
+
+    if let Some(reason) = state.read_errors.get(topic) {
+        return Err(reason.clone());
+    }
+
+    Ok(state
+        .topics
+        .get_mut(topic)
+        .and_then(|queue| queue.pop_front()))
+}
+
+pub fn subscribe(topic: &str) -> Result<Option<String>, String> {
+    let mut state = state();
+
+    if let Some(reason) = state.read_errors.get(topic) {
+        return Err(reason.clone());
+    }
+
+    // First call subscribes the module, messages published from then on are received.
+    Ok(state
+        .subscriptions
+        .entry(topic.to_string())
+        .or_default()
+        .pop_front())
+}
+
+pub fn traceparent() -> Option<String> {
+    state().traceparent.clone()
+}
+
+pub fn shutdownrequested() -> bool {
+    state().shutdown_requested
+}
+
+pub fn scheduleinterval(
+    intervalinmilliseconds: u64,
+    jitterinmilliseconds: u64,
+    missedtickpolicy: Missedtickpolicy,
+) -> u32 {
+    schedule(
+        Schedule::Interval(Duration::from_millis(intervalinmilliseconds)),
+        jitterinmilliseconds,
+        missedtickpolicy,
+    )
+}
+
+pub fn schedulecron(
+    expression: &str,
+    jitterinmilliseconds: u64,
+    missedtickpolicy: Missedtickpolicy,
+) -> Result<u32, String> {
+    // Host's cron schedules start with seconds and may end with a year.
+    if !(6..=7).contains(&expression.split_whitespace().count()) {
+        return Err(format!("invalid cron expression '{expression}'"));
+    }
+
+    Ok(schedule(
+        Schedule::Cron(expression.to_string()),
+        jitterinmilliseconds,
+        missedtickpolicy,
+    ))
+}
+
+pub fn canceltimer(timerid: u32) {
+    if let Some(timer) = state().timers.iter_mut().find(|timer| timer.id == timerid) {
+        timer.cancelled = true;
+    }
+}
+
+pub fn counterincrement(name: &str, value: u64, labels: &[(&str, &str)]) {
+    record(MetricKind::Counter, name, value as f64, labels);
+}
+
+pub fn gaugeset(name: &str, value: f64, labels: &[(&str, &str)]) {
+    record(MetricKind::Gauge, name, value, labels);
+}
+
+pub fn histogramrecord(name: &str, value: f64, labels: &[(&str, &str)]) {
+    record(MetricKind::Histogram, name, value, labels);
+}
+
+pub fn callhandler(
+    modulename: &str,
+    handler: &str,
+    _payload: &str,
+    _timeoutinmilliseconds: u64,
+) -> Result<String, String> {
+    let target = format!("{modulename}.{handler}");
+
+    state()
+        .call_replies
+        .get(&target)
+        .cloned()
+        .unwrap_or_else(|| Err(format!("no module serves '{target}'")))
+}
+
+#[cfg(feature = "http")]
+pub fn http_request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Option<Vec<u8>>,
+) -> anyhow::Result<(u16, Vec<u8>)> {
+    let mut state = state();
+
+    state.http_requests.push(HttpRequest {
+        method: method.to_string(),
+        url: url.to_string(),
+        headers: to_owned(headers),
+        body,
+    });
+
+    match state.http_response.clone() {
+        Some(Ok(response)) => Ok(response),
+        Some(Err(reason)) => anyhow::bail!("Failed to make {method} request to '{url}': {reason}."),
+        None => Ok((200, vec![])),
+    }
+}
+
+// Timer ids start at 1, as the host's do.
+fn schedule(schedule: Schedule, jitterinmilliseconds: u64, policy: Missedtickpolicy) -> u32 {
+    let mut state = state();
+    let id = state.timers.len() as u32 + 1;
+
+    state.timers.push(Timer {
+        id,
+        schedule,
+        jitter: Duration::from_millis(jitterinmilliseconds),
+        policy: match policy {
+            Missedtickpolicy::Skip => MissedTickPolicy::Skip,
+            Missedtickpolicy::Burst => MissedTickPolicy::Burst,
+            Missedtickpolicy::Delay => MissedTickPolicy::Delay,
+        },
+        cancelled: false,
+    });
+
+    id
+}
+
+fn record(kind: MetricKind, name: &str, value: f64, labels: &[(&str, &str)]) {
+    state().metrics.push(Metric {
+        kind,
+        name: name.to_string(),
+        value,
+        labels: to_owned(labels),
+    });
+}
+
+fn to_owned(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
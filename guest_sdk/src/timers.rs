@@ -0,0 +1,44 @@
+// Timers the host runs for the module, each firing calls the module's 'on_tick' with the timer's id.
+use crate::host::{self, Missedtickpolicy};
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+
+// What the host does with ticks missed while the module was busy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissedTickPolicy {
+    // Drop missed ticks and carry on at the next scheduled one.
+    Skip,
+    // Fire missed ticks back to back until caught up.
+    Burst,
+    // Restart the interval from the late tick.
+    Delay,
+}
+
+impl From<MissedTickPolicy> for Missedtickpolicy {
+    fn from(policy: MissedTickPolicy) -> Self {
+        match policy {
+            MissedTickPolicy::Skip => Missedtickpolicy::Skip,
+            MissedTickPolicy::Burst => Missedtickpolicy::Burst,
+            MissedTickPolicy::Delay => Missedtickpolicy::Delay,
+        }
+    }
+}
+
+// Fires every interval, plus a random delay of up to jitter. Returns the timer's id.
+pub fn interval(interval: Duration, jitter: Duration, policy: MissedTickPolicy) -> u32 {
+    host::scheduleinterval(
+        interval.as_millis() as u64,
+        jitter.as_millis() as u64,
+        policy.into(),
+    )
+}
+
+// Fires on a cron schedule e.g. '0 */5 * * * *', plus a random delay of up to jitter. Returns the timer's id.
+pub fn cron(expression: &str, jitter: Duration, policy: MissedTickPolicy) -> Result<u32> {
+    host::schedulecron(expression, jitter.as_millis() as u64, policy.into())
+        .map_err(|e| anyhow!("Failed to schedule cron timer '{expression}': {e}."))
+}
+
+pub fn cancel(timer_id: u32) {
+    host::canceltimer(timer_id);
+}
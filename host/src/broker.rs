@@ -0,0 +1,794 @@
+// In-process message broker, selected instead of pubsub server module per deployment in host configuration so
+// 'publish', 'read' and 'subscribe' host functions deliver in memory rather than over a socket. Each host runs its
+// own broker. Topics have the same semantics as in the server module: they are created from configuration, each
+// message is read once in publish order, '@ttl' and '@delay' options expire and delay messages, and expired messages
+// are moved to the dead letter topic or dropped. Subscribing modules each get their own copy of every message published
+// after they first subscribed. Server module stays available to external clients, topics configured for the bridge
+// are relayed between the two.
+use crate::config::{Configuration, PubsubConfiguration};
+use crate::logging::{self, Level};
+use crate::metrics;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const MODULE_NAME: &str = "Wasm Host";
+// Address pubsub server module serves on, from the socket the host pre-opens for it.
+const SERVER_ADDRESS: &str = "127.0.0.1:8080";
+// Reply to a read of a topic with no visible messages, and prefix of the reply to a failed command.
+const EMPTY_REPLY: &str = "empty";
+const ERROR_REPLY: &str = "error ";
+const OPTION_PREFIX: char = '@';
+const TTL_OPTION: &str = "ttl";
+const DELAY_OPTION: &str = "delay";
+const SOURCE_OPTION: &str = "source";
+const TRACEPARENT_OPTION: &str = "traceparent";
+// Ends the options explicitly, so a value which itself starts with '@name=value' is not read as options.
+const END_OF_OPTIONS: &str = "@@";
+// How often messages nobody reads are expired and topic sizes are reported, reads expire messages as they reach them.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+// Messages kept for each subscribing module, the oldest are dropped once a module falls this far behind.
+const MAX_SUBSCRIPTION_MESSAGES: usize = 10_000;
+
+// Where host functions deliver messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    // Pubsub server module, over a socket connection per call.
+    Server,
+    // Broker in the host process.
+    Native,
+}
+
+// Native broker of a host, shared by the host functions of every module the host runs.
+pub struct Broker {
+    state: Mutex<State>,
+}
+
+struct State {
+    topics: HashMap<String, Topic>,
+    deadletter_topic: Option<String>,
+    // Topics published in the host which are copied to the server module, with the bridge's queue of them.
+    bridge_to_server: Vec<String>,
+    bridge: Option<Sender<(String, String)>>,
+}
+
+#[derive(Clone, Debug)]
+struct Message {
+    value: String,
+    // Name of the publishing module as stamped by the host.
+    source: Option<String>,
+    // W3C trace context stamped by the host at publish, handed back to readers with the message.
+    traceparent: Option<String>,
+    // Message is dropped or dead-lettered once this time has passed.
+    expires_at: Option<Instant>,
+    // Message is not delivered to readers before this time.
+    visible_at: Option<Instant>,
+}
+
+impl Message {
+    // Parses leading '@name=value' options from the payload, rest of the payload becomes the message value.
+    fn parse(payload: &str) -> Self {
+        let now = Instant::now();
+        let mut message = Self {
+            value: String::new(),
+            source: None,
+            traceparent: None,
+            expires_at: None,
+            visible_at: None,
+        };
+        let mut rest = payload.trim_start();
+
+        while rest.starts_with(OPTION_PREFIX) {
+            let (token, remaining) = rest.split_at(rest.find(' ').unwrap_or(rest.len()));
+
+            if token == END_OF_OPTIONS {
+                rest = remaining.strip_prefix(' ').unwrap_or(remaining);
+                break;
+            }
+
+            let (name, value) = match token[1..].split_once('=') {
+                Some(option) => option,
+                None => break,
+            };
+            let milliseconds = value.parse::<u64>().ok().map(Duration::from_millis);
+
+            match (name, milliseconds) {
+                (TTL_OPTION, Some(ttl)) => message.expires_at = Some(now + ttl),
+                (DELAY_OPTION, Some(delay)) => message.visible_at = Some(now + delay),
+                // Host stamps source and trace context first, later options cannot override them.
+                (SOURCE_OPTION, _) => {
+                    message.source.get_or_insert_with(|| value.to_string());
+                }
+                (TRACEPARENT_OPTION, _) => {
+                    message.traceparent.get_or_insert_with(|| value.to_string());
+                }
+                // Not a recognised option, treat it as part of the message value.
+                _ => break,
+            }
+
+            rest = remaining.trim_start();
+        }
+
+        message.value = rest.to_string();
+        message
+    }
+
+    // Parses a message read from the server module. Its options precede the value only when ended with '@@', as the
+    // server module replies to identities which continue traces, other replies are the value alone.
+    fn parse_reply(payload: &str) -> Self {
+        let has_options = payload
+            .split(' ')
+            .take_while(|token| token.starts_with(OPTION_PREFIX))
+            .any(|token| token == END_OF_OPTIONS);
+
+        if has_options {
+            Self::parse(payload)
+        } else {
+            Self::parse(&format!("{END_OF_OPTIONS} {payload}"))
+        }
+    }
+
+    // Returns message as handed to readers, prefixed with its source and trace context so the reader can continue the
+    // trace, and always ended with '@@' as the server module replies it.
+    fn payload(&self) -> String {
+        let mut options = self.stamped_options();
+        options.push(END_OF_OPTIONS.to_string());
+        options.push(self.value.to_string());
+        options.join(" ")
+    }
+
+    // Encodes message with its options as published to the server module, remaining ttl and delay are relative to now.
+    fn encode(&self, now: Instant) -> String {
+        let mut options = self.stamped_options();
+
+        if let Some(expires_at) = self.expires_at {
+            let ttl = expires_at.saturating_duration_since(now).as_millis();
+            options.push(format!("{OPTION_PREFIX}{TTL_OPTION}={ttl}"));
+        }
+
+        if let Some(visible_at) = self.visible_at {
+            let delay = visible_at.saturating_duration_since(now).as_millis();
+            options.push(format!("{OPTION_PREFIX}{DELAY_OPTION}={delay}"));
+        }
+
+        options.push(END_OF_OPTIONS.to_string());
+        options.push(self.value.to_string());
+        options.join(" ")
+    }
+
+    // Options stamped by the host at publish.
+    fn stamped_options(&self) -> Vec<String> {
+        let mut options = vec![];
+
+        if let Some(source) = &self.source {
+            options.push(format!("{OPTION_PREFIX}{SOURCE_OPTION}={source}"));
+        }
+
+        if let Some(traceparent) = &self.traceparent {
+            options.push(format!("{OPTION_PREFIX}{TRACEPARENT_OPTION}={traceparent}"));
+        }
+
+        options
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+
+    fn is_visible(&self, now: Instant) -> bool {
+        !matches!(self.visible_at, Some(visible_at) if visible_at > now)
+    }
+}
+
+// Holds messages in publish order, delayed messages are kept aside until they become visible.
+#[derive(Default)]
+struct Queue {
+    ready: VecDeque<Message>,
+    delayed: Vec<Message>,
+}
+
+impl Queue {
+    fn size(&self) -> usize {
+        self.ready.len() + self.delayed.len()
+    }
+
+    fn add(&mut self, message: Message) {
+        if message.is_visible(Instant::now()) {
+            self.ready.push_back(message);
+        } else {
+            self.delayed.push(message);
+        }
+    }
+
+    // Drops the oldest message, ready or delayed.
+    fn drop_oldest(&mut self) {
+        if self.ready.pop_front().is_none() && !self.delayed.is_empty() {
+            self.delayed.remove(0);
+        }
+    }
+
+    // Moves delayed messages which are now visible to the ready queue, in the order they were published.
+    fn promote_visible(&mut self, now: Instant) {
+        if self.delayed.is_empty() {
+            return;
+        }
+
+        let (visible, delayed): (Vec<Message>, Vec<Message>) = self
+            .delayed
+            .drain(..)
+            .partition(|message| message.is_visible(now));
+        self.delayed = delayed;
+        self.ready.extend(visible);
+    }
+
+    // Removes and returns the next visible message, with the expired messages removed ahead of it.
+    fn next(&mut self, now: Instant) -> (Option<Message>, Vec<Message>) {
+        self.promote_visible(now);
+
+        let mut expired = vec![];
+        while let Some(message) = self.ready.pop_front() {
+            if !message.is_expired(now) {
+                return (Some(message), expired);
+            }
+            expired.push(message);
+        }
+
+        (None, expired)
+    }
+
+    // Makes delayed messages visible, and removes and returns expired messages.
+    fn sweep(&mut self, now: Instant) -> Vec<Message> {
+        self.promote_visible(now);
+
+        let (mut expired, ready): (Vec<Message>, Vec<Message>) = self
+            .ready
+            .drain(..)
+            .partition(|message| message.is_expired(now));
+        self.ready = ready.into();
+
+        let (delayed_expired, delayed): (Vec<Message>, Vec<Message>) = self
+            .delayed
+            .drain(..)
+            .partition(|message| message.is_expired(now));
+        self.delayed = delayed;
+        expired.extend(delayed_expired);
+
+        expired
+    }
+}
+
+// Messages of a topic for its readers, and a copy for each subscribed module.
+#[derive(Default)]
+struct Topic {
+    queue: Queue,
+    subscriptions: HashMap<String, Queue>,
+}
+
+impl State {
+    // Adds a message to a topic and to each of its subscriptions, messages for topics which do not exist are dropped as
+    // by the server module.
+    fn add(&mut self, topic: &str, message: Message) {
+        let topic = match self.topics.get_mut(topic) {
+            Some(topic) => topic,
+            None => {
+                logging::log(
+                    Level::Debug,
+                    MODULE_NAME,
+                    &format!("Topic '{topic}' does not exist, dropping message."),
+                    &[("topic", topic)],
+                );
+                return;
+            }
+        };
+
+        for subscription in topic.subscriptions.values_mut() {
+            if subscription.size() >= MAX_SUBSCRIPTION_MESSAGES {
+                subscription.drop_oldest();
+            }
+            subscription.add(message.clone());
+        }
+        topic.queue.add(message);
+    }
+
+    // Moves expired messages to dead letter topic, messages expiring on the dead letter topic itself are dropped.
+    fn expire(&mut self, topic: &str, expired: Vec<Message>) {
+        for message in expired {
+            match self.deadletter_topic.clone() {
+                Some(deadletter_topic)
+                    if deadletter_topic != topic && self.topics.contains_key(&deadletter_topic) =>
+                {
+                    logging::log(
+                        Level::Warn,
+                        MODULE_NAME,
+                        &format!(
+                            "Message expired on topic '{topic}', moving it to '{deadletter_topic}'."
+                        ),
+                        &[("topic", topic)],
+                    );
+
+                    self.add(
+                        &deadletter_topic,
+                        Message {
+                            expires_at: None,
+                            visible_at: None,
+                            ..message
+                        },
+                    );
+                }
+                _ => logging::log(
+                    Level::Warn,
+                    MODULE_NAME,
+                    &format!("Message expired on topic '{topic}', dropping it."),
+                    &[("topic", topic)],
+                ),
+            }
+        }
+    }
+}
+
+// Starts a native broker when configured as the pubsub backend, and its bridge to the server module when any topics
+// are bridged. Returns None when host functions use the server module.
+pub fn start(
+    host_config: &Configuration,
+    server_auth_token: Option<String>,
+) -> Option<Arc<Broker>> {
+    let PubsubConfiguration {
+        backend,
+        topics,
+        deadletter_topic,
+        bridge_to_server,
+        mut bridge_from_server,
+        bridge_interval,
+    } = host_config.pubsub();
+
+    if backend == Backend::Server {
+        return None;
+    }
+
+    // A topic bridged both ways would have its messages relayed back and forth.
+    bridge_from_server.retain(|topic| {
+        let both_ways = bridge_to_server.contains(topic);
+        if both_ways {
+            logging::log(
+                Level::Warn,
+                MODULE_NAME,
+                &format!(
+                    "Topic '{topic}' is bridged both ways, only bridging it to the server module."
+                ),
+                &[("topic", topic)],
+            );
+        }
+        !both_ways
+    });
+
+    logging::log(
+        Level::Info,
+        MODULE_NAME,
+        &format!(
+            "Delivering messages in the host, topics '{}'.",
+            topics.join(" ")
+        ),
+        &[],
+    );
+
+    let bridged = !bridge_to_server.is_empty() || !bridge_from_server.is_empty();
+    let broker = Arc::new(Broker::new(topics, deadletter_topic, bridge_to_server));
+
+    // Threads stop once the host and every module using the broker have dropped it.
+    if bridged {
+        let (sender, receiver) = mpsc::channel();
+        broker.state.lock().unwrap().bridge = Some(sender);
+
+        let broker = Arc::downgrade(&broker);
+        thread::spawn(move || {
+            bridge(
+                &broker,
+                receiver,
+                &bridge_from_server,
+                bridge_interval,
+                server_auth_token.as_deref(),
+            )
+        });
+    }
+
+    let swept = Arc::downgrade(&broker);
+    thread::spawn(move || loop {
+        thread::sleep(SWEEP_INTERVAL);
+        match swept.upgrade() {
+            Some(broker) => broker.sweep(),
+            None => return,
+        }
+    });
+
+    Some(broker)
+}
+
+impl Broker {
+    fn new(
+        topics: Vec<String>,
+        deadletter_topic: Option<String>,
+        bridge_to_server: Vec<String>,
+    ) -> Self {
+        Self {
+            state: Mutex::new(State {
+                topics: topics
+                    .into_iter()
+                    .map(|topic| (topic, Topic::default()))
+                    .collect(),
+                deadletter_topic,
+                bridge_to_server,
+                bridge: None,
+            }),
+        }
+    }
+
+    // Publishes a message given with its '@' options.
+    pub fn publish(&self, topic: &str, payload: &str) {
+        self.publish_message(topic, Message::parse(payload));
+    }
+
+    fn publish_message(&self, topic: &str, message: Message) {
+        let mut state = self.state.lock().unwrap();
+
+        if state
+            .bridge_to_server
+            .iter()
+            .any(|bridged| bridged == topic)
+        {
+            if let Some(bridge) = &state.bridge {
+                let _ = bridge.send((topic.to_string(), message.encode(Instant::now())));
+            }
+        }
+
+        state.add(topic, message);
+    }
+
+    // Reads the next visible message of a topic, with its trace context as the server module replies it, None when
+    // there is none. Expired messages ahead of it are moved to the dead letter topic.
+    pub fn read(&self, topic: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let (message, expired) = state.topics.get_mut(topic)?.queue.next(Instant::now());
+        state.expire(topic, expired);
+
+        message.map(|message| message.payload())
+    }
+
+    // Returns the next message published to a topic since the module first subscribed to it, with its trace context.
+    // The first call subscribes the module and returns None. Replicas of a module share its subscription, expired
+    // messages are dropped as readers of the topic itself get them dead-lettered.
+    pub fn subscribe(&self, module_name: &str, topic: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        let subscription = state
+            .topics
+            .get_mut(topic)?
+            .subscriptions
+            .entry(module_name.to_string())
+            .or_default();
+
+        subscription
+            .next(Instant::now())
+            .0
+            .map(|message| message.payload())
+    }
+
+    // Expires messages nobody reads and reports the size of every topic, as the server module does between commands.
+    fn sweep(&self) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        let mut expired = vec![];
+        for (name, topic) in state.topics.iter_mut() {
+            expired.push((name.to_string(), topic.queue.sweep(now)));
+            for subscription in topic.subscriptions.values_mut() {
+                subscription.sweep(now);
+            }
+        }
+        for (topic, messages) in expired {
+            state.expire(&topic, messages);
+        }
+
+        let sizes: Vec<(String, usize)> = state
+            .topics
+            .iter()
+            .map(|(name, topic)| (name.to_string(), topic.queue.size()))
+            .collect();
+        // Metrics are recorded without holding up publishers and readers.
+        drop(state);
+
+        for (topic, size) in sizes {
+            metrics::gauge_set(
+                MODULE_NAME,
+                "wasm_pubsub_topic_size",
+                size as f64,
+                &[("topic", &topic)],
+            );
+        }
+    }
+}
+
+// Connects to pubsub server module, authenticating the connection when a token is given.
+pub fn connect(server_auth_token: Option<&str>) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(SERVER_ADDRESS)?;
+
+    if let Some(token) = server_auth_token {
+        stream.write_all(format!("auth {token}").as_bytes())?;
+        stream.flush()?;
+
+        // Wait for auth reply so it is not merged with the next command on the stream.
+        let mut buf = vec![0; 1000];
+        let n = stream.read(&mut buf)?;
+        let reply = String::from_utf8_lossy(&buf[..n]);
+
+        // Commands on an unauthenticated connection would run with anonymous permissions, don't use it.
+        if reply != "ok" {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("authentication with pubsub module failed: '{reply}'"),
+            ));
+        }
+    }
+
+    Ok(stream)
+}
+
+// Sends a command to pubsub server module on its own connection and returns the whole reply, however large or
+// fragmented: server module closes the connection once it has replied and read the end of the command stream.
+pub fn request(server_auth_token: Option<&str>, command: &str) -> io::Result<String> {
+    let mut stream = connect(server_auth_token)?;
+    stream.write_all(command.as_bytes())?;
+    stream.flush()?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut reply = vec![];
+    stream.read_to_end(&mut reply)?;
+
+    Ok(String::from_utf8_lossy(&reply).into_owned())
+}
+
+// Relays messages between the native broker and the server module: messages published in the host on bridged topics
+// are published to the server module as they arrive, and the server module's bridged topics are read into the
+// native broker every interval. Server module may not be running, failures are logged and retried.
+fn bridge(
+    broker: &Weak<Broker>,
+    to_server: Receiver<(String, String)>,
+    from_server: &[String],
+    interval: Duration,
+    server_auth_token: Option<&str>,
+) {
+    loop {
+        let until = Instant::now() + interval;
+
+        loop {
+            match to_server.recv_timeout(until.saturating_duration_since(Instant::now())) {
+                // Each message is sent on its own connection, the server module reads one command per read.
+                Ok((topic, message)) => {
+                    if let Err(e) = connect(server_auth_token).and_then(|mut stream| {
+                        stream.write_all(format!("{topic} {message}").as_bytes())?;
+                        stream.flush()
+                    }) {
+                        logging::log(
+                            Level::Warn,
+                            MODULE_NAME,
+                            &format!("Failed to bridge message on topic '{topic}' to pubsub module; error = {e}"),
+                            &[("topic", &topic)],
+                        );
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        for topic in from_server {
+            let broker = match broker.upgrade() {
+                Some(broker) => broker,
+                None => return,
+            };
+
+            if let Err(e) = bridge_from_server(&broker, topic, server_auth_token) {
+                logging::log(
+                    Level::Debug,
+                    MODULE_NAME,
+                    &format!("Failed to bridge topic '{topic}' from pubsub module; error = {e}"),
+                    &[("topic", topic)],
+                );
+            }
+        }
+    }
+}
+
+// Reads every visible message of a topic from the server module into the native broker.
+fn bridge_from_server(
+    broker: &Broker,
+    topic: &str,
+    server_auth_token: Option<&str>,
+) -> io::Result<()> {
+    loop {
+        // Reply is '<topic> <message>', '<topic> empty' or 'error <reason>'.
+        let reply = request(server_auth_token, &format!("read {topic}"))?;
+        let payload = reply.split_once(' ').map_or("", |(_, payload)| payload);
+
+        if reply.is_empty() || payload == EMPTY_REPLY || reply.starts_with(ERROR_REPLY) {
+            return Ok(());
+        }
+
+        // Message keeps the source and trace context it was published with, its value is not read as options.
+        broker.publish_message(topic, Message::parse_reply(payload));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn broker() -> Broker {
+        Broker::new(
+            vec![
+                "telemetry".to_string(),
+                "alert".to_string(),
+                "deadletter".to_string(),
+            ],
+            Some("deadletter".to_string()),
+            vec![],
+        )
+    }
+
+    #[test]
+    fn messages_are_read_once_in_publish_order_with_their_source() {
+        let broker = broker();
+        broker.publish("telemetry", "@source=sensor @@ first");
+        broker.publish("telemetry", "@@ @ttl=10 second");
+        broker.publish("missing", "dropped");
+
+        assert_eq!(
+            broker.read("telemetry").as_deref(),
+            Some("@source=sensor @@ first")
+        );
+        assert_eq!(
+            broker.read("telemetry").as_deref(),
+            Some("@@ @ttl=10 second")
+        );
+        assert_eq!(broker.read("telemetry"), None);
+        assert_eq!(broker.read("missing"), None);
+    }
+
+    #[test]
+    fn trace_context_is_handed_back_to_readers() {
+        let broker = broker();
+        broker.publish("telemetry", "@traceparent=00-trace-span-01 @@ reading");
+
+        assert_eq!(
+            broker.read("telemetry").as_deref(),
+            Some("@traceparent=00-trace-span-01 @@ reading")
+        );
+    }
+
+    #[test]
+    fn bridged_messages_keep_their_options_and_value() {
+        let broker = broker();
+        broker.publish_message(
+            "telemetry",
+            Message::parse_reply("@source=sensor @traceparent=00-trace-span-01 @@ @ttl=0 first"),
+        );
+        // Reply of a server module identity which does not continue traces.
+        broker.publish_message("telemetry", Message::parse_reply("@ttl=0 second"));
+
+        assert_eq!(
+            broker.read("telemetry").as_deref(),
+            Some("@source=sensor @traceparent=00-trace-span-01 @@ @ttl=0 first")
+        );
+        assert_eq!(
+            broker.read("telemetry").as_deref(),
+            Some("@@ @ttl=0 second")
+        );
+    }
+
+    #[test]
+    fn delayed_messages_are_read_once_visible() {
+        let broker = broker();
+        broker.publish("telemetry", "@delay=50 @@ delayed");
+        broker.publish("telemetry", "@@ immediate");
+
+        assert_eq!(broker.read("telemetry").as_deref(), Some("@@ immediate"));
+        assert_eq!(broker.read("telemetry"), None);
+
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(broker.read("telemetry").as_deref(), Some("@@ delayed"));
+    }
+
+    #[test]
+    fn expired_messages_are_dead_lettered_when_read() {
+        let broker = broker();
+        broker.publish("telemetry", "@ttl=0 @@ expired");
+        broker.publish("telemetry", "@@ current");
+
+        assert_eq!(broker.read("telemetry").as_deref(), Some("@@ current"));
+        assert_eq!(broker.read("deadletter").as_deref(), Some("@@ expired"));
+    }
+
+    #[test]
+    fn expired_messages_nobody_reads_are_dead_lettered_by_sweep() {
+        let broker = broker();
+        broker.publish("alert", "@ttl=0 @@ unread");
+        broker.sweep();
+
+        let state = broker.state.lock().unwrap();
+        assert_eq!(state.topics["alert"].queue.size(), 0);
+        assert_eq!(state.topics["deadletter"].queue.size(), 1);
+    }
+
+    #[test]
+    fn messages_expiring_on_dead_letter_topic_are_dropped() {
+        let broker = broker();
+        broker.publish("deadletter", "@ttl=0 @@ expired");
+        broker.sweep();
+
+        assert_eq!(broker.read("deadletter"), None);
+    }
+
+    #[test]
+    fn expired_messages_are_dropped_without_dead_letter_topic() {
+        let broker = Broker::new(vec!["telemetry".to_string()], None, vec![]);
+        broker.publish("telemetry", "@ttl=0 @@ expired");
+
+        assert_eq!(broker.read("telemetry"), None);
+    }
+
+    #[test]
+    fn subscribers_each_get_messages_published_after_they_subscribed() {
+        let broker = broker();
+        broker.publish("telemetry", "@@ before");
+
+        assert_eq!(broker.subscribe("gateway", "telemetry"), None);
+        assert_eq!(broker.subscribe("telemetry", "telemetry"), None);
+        broker.publish("telemetry", "@@ after");
+
+        assert_eq!(
+            broker.subscribe("gateway", "telemetry").as_deref(),
+            Some("@@ after")
+        );
+        assert_eq!(broker.subscribe("gateway", "telemetry"), None);
+        assert_eq!(
+            broker.subscribe("telemetry", "telemetry").as_deref(),
+            Some("@@ after")
+        );
+        // Readers of the topic are not affected by subscriptions.
+        assert_eq!(broker.read("telemetry").as_deref(), Some("@@ before"));
+        assert_eq!(broker.read("telemetry").as_deref(), Some("@@ after"));
+    }
+
+    #[test]
+    fn subscriptions_keep_the_latest_messages() {
+        let broker = broker();
+        broker.subscribe("gateway", "telemetry");
+        for index in 0..=MAX_SUBSCRIPTION_MESSAGES {
+            broker.publish("telemetry", &format!("@@ {index}"));
+        }
+
+        assert_eq!(
+            broker.subscribe("gateway", "telemetry").as_deref(),
+            Some("@@ 1")
+        );
+    }
+
+    #[test]
+    fn expired_messages_are_dropped_from_subscriptions() {
+        let broker = broker();
+        broker.subscribe("gateway", "telemetry");
+        broker.publish("telemetry", "@ttl=0 @@ expired");
+
+        assert_eq!(broker.subscribe("gateway", "telemetry"), None);
+        // Topic's own copy is dead-lettered once, by its readers.
+        assert_eq!(broker.read("telemetry"), None);
+        assert_eq!(broker.read("deadletter").as_deref(), Some("@@ expired"));
+        assert_eq!(broker.read("deadletter"), None);
+    }
+}
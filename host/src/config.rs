@@ -0,0 +1,663 @@
+use crate::broker::Backend;
+use crate::logging::{Format, Level, Output};
+use crate::tracing::Exporter;
+use crate::wasm::{
+    capabilities::Capability,
+    http_trigger::{HttpTrigger, Route},
+    message_trigger::MessageTrigger,
+    outbound_http::{AllowedHost, HttpPolicy},
+    preopens::Preopen,
+    rollout::CanaryPolicy,
+    rpc::RpcHandlers,
+};
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use toml::Value;
+
+// Topic name which grants access to all topics in a module's topic list.
+const ALL_TOPICS: &str = "*";
+// Commands of the pubsub server module, a message published to a topic of the same name would be read as the command.
+// Replies to reads of a topic named 'error' would be read as error replies.
+const RESERVED_TOPICS: &[&str] = &["read", "auth", "error"];
+const DEFAULT_LOG_FILE_PATH: &str = "./logs/host.log";
+const DEFAULT_MAX_LOG_FILE_SIZE_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_MAX_LOG_FILES: u32 = 5;
+const DEFAULT_TRACES_FILE_PATH: &str = "./traces/spans.json";
+const DEFAULT_OTLP_ENDPOINT: &str = "http://127.0.0.1:4318/v1/traces";
+const DEFAULT_TRACES_EXPORT_INTERVAL_IN_MILLISECONDS: u64 = 5000;
+const DEFAULT_SHUTDOWN_DEADLINE_IN_MILLISECONDS: u64 = 10000;
+// Host directory where module scratch directories are created when not configured.
+const DEFAULT_SCRATCH_DIRECTORY: &str = "./scratch";
+// Version label of modules which do not configure one.
+const DEFAULT_MODULE_VERSION: &str = "v1";
+const DEFAULT_CANARY_WEIGHT_PERCENT: u32 = 10;
+const DEFAULT_CANARY_MAX_TRAPS: u64 = 3;
+const DEFAULT_CANARY_MAX_ERROR_RATE_PERCENT: u32 = 10;
+const DEFAULT_CANARY_MIN_TICKS: u64 = 20;
+const DEFAULT_MESSAGE_TRIGGER_MAX_CONCURRENCY: usize = 4;
+const DEFAULT_MESSAGE_TRIGGER_POOL_SIZE: u32 = 16;
+const DEFAULT_MESSAGE_TRIGGER_POLL_INTERVAL_IN_MILLISECONDS: u64 = 100;
+const DEFAULT_MESSAGE_TRIGGER_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_HTTP_TRIGGER_MAX_CONCURRENCY: usize = 16;
+const DEFAULT_HTTP_TRIGGER_POOL_SIZE: u32 = 16;
+const DEFAULT_HTTP_TRIGGER_MAX_REQUEST_BODY_BYTES: usize = 1024 * 1024;
+const DEFAULT_DEADLETTER_TOPIC: &str = "deadletter";
+const DEFAULT_BRIDGE_INTERVAL_IN_MILLISECONDS: u64 = 100;
+const DEFAULT_RPC_MAX_CONCURRENCY: usize = 8;
+const DEFAULT_RPC_POOL_SIZE: u32 = 8;
+const DEFAULT_RPC_MAX_TIMEOUT_IN_MILLISECONDS: u64 = 5000;
+
+pub struct Configuration {
+    config_value: Value,
+}
+
+impl Configuration {
+    pub fn new(configfilecontents: String) -> Self {
+        Self::parse(&configfilecontents).unwrap()
+    }
+
+    // Parses host configuration, for configuration re-read while the host is running.
+    pub fn parse(configfilecontents: &str) -> Result<Self> {
+        Ok(Self {
+            config_value: configfilecontents.parse::<Value>()?,
+        })
+    }
+
+    // Returns configuration for a module, modules without a section get an empty (deny all) configuration
+    pub fn module(&self, module_name: &str) -> ModuleConfiguration {
+        let config_value = self
+            .config_value
+            .get("modules")
+            .and_then(|modules| modules.get(module_name))
+            .cloned()
+            .unwrap_or_else(|| Value::Table(Default::default()));
+
+        ModuleConfiguration {
+            module_name: module_name.to_string(),
+            replica: 0,
+            scratch_directory: Path::new(&self.scratch_directory()).join(module_name),
+            config_value,
+        }
+    }
+
+    // Returns names of all modules with a section in host configuration
+    pub fn module_names(&self) -> Vec<String> {
+        self.config_value
+            .get("modules")
+            .and_then(|modules| modules.as_table())
+            .map(|modules| modules.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // Returns log format, output and level filters, defaults to info level text logs on stdout
+    pub fn logging(&self) -> Result<LoggingConfiguration> {
+        let mut logging_config = LoggingConfiguration::default();
+
+        let logging = match self.config_value.get("logging") {
+            Some(value) => value,
+            None => return Ok(logging_config),
+        };
+
+        let value = |key: &str| logging.get(key).and_then(|value| value.as_str());
+        let level = |level: &str| {
+            Level::parse(level).ok_or_else(|| anyhow!("Invalid log level '{level}'."))
+        };
+
+        if value("format") == Some("json") {
+            logging_config.format = Format::Json;
+        }
+
+        if value("output") == Some("file") {
+            logging_config.output = Output::File {
+                path: PathBuf::from(value("file_path").unwrap_or(DEFAULT_LOG_FILE_PATH)),
+                max_file_size_bytes: match value("max_file_size_bytes") {
+                    Some(max) => max.parse::<u64>()?,
+                    None => DEFAULT_MAX_LOG_FILE_SIZE_BYTES,
+                },
+                max_files: match value("max_files") {
+                    Some(max) => max.parse::<u32>()?,
+                    None => DEFAULT_MAX_LOG_FILES,
+                },
+            };
+        }
+
+        if let Some(default_level) = value("level") {
+            logging_config.default_level = level(default_level)?;
+        }
+
+        if let Some(levels) = logging.get("levels").and_then(|value| value.as_table()) {
+            for (module_name, module_level) in levels {
+                let module_level = module_level.as_str().ok_or_else(|| {
+                    anyhow!("Log level of module '{module_name}' is not a string.")
+                })?;
+                logging_config
+                    .module_levels
+                    .insert(module_name.to_string(), level(module_level)?);
+            }
+        }
+
+        Ok(logging_config)
+    }
+
+    // Returns span exporter and export interval, spans are not exported when not configured
+    pub fn tracing(&self) -> TracingConfiguration {
+        let tracing = self.config_value.get("tracing");
+        let value = |key: &str| {
+            tracing
+                .and_then(|tracing| tracing.get(key))
+                .and_then(|value| value.as_str())
+        };
+
+        let exporter = match value("exporter") {
+            Some("file") => Exporter::File {
+                path: PathBuf::from(value("file_path").unwrap_or(DEFAULT_TRACES_FILE_PATH)),
+            },
+            Some("otlp") => Exporter::Otlp {
+                endpoint: value("endpoint")
+                    .unwrap_or(DEFAULT_OTLP_ENDPOINT)
+                    .to_string(),
+            },
+            _ => Exporter::None,
+        };
+
+        TracingConfiguration {
+            exporter,
+            export_interval: Duration::from_millis(
+                value("export_interval_in_milliseconds")
+                    .map_or(DEFAULT_TRACES_EXPORT_INTERVAL_IN_MILLISECONDS, |interval| {
+                        interval.parse::<u64>().unwrap()
+                    }),
+            ),
+        }
+    }
+
+    // Returns address to serve Prometheus metrics on, metrics endpoint is disabled when not configured
+    pub fn metrics_address(&self) -> Option<String> {
+        self.config_value
+            .get("metrics")
+            .and_then(|metrics| metrics.get("address"))
+            .and_then(|value| value.as_str())
+            .map(|address| address.to_string())
+    }
+
+    // Returns address the host serves http triggered modules on, http triggers are disabled when not configured
+    pub fn http_trigger_address(&self) -> Option<String> {
+        self.config_value
+            .get("http_trigger")
+            .and_then(|http_trigger| http_trigger.get("address"))
+            .and_then(|value| value.as_str())
+            .map(|address| address.to_string())
+    }
+
+    // Returns backend host functions deliver messages through and its topics, defaults to pubsub server module
+    pub fn pubsub(&self) -> PubsubConfiguration {
+        let pubsub = self.config_value.get("pubsub");
+        let value = |key: &str| {
+            pubsub
+                .and_then(|pubsub| pubsub.get(key))
+                .and_then(|value| value.as_str())
+        };
+        let list = |key: &str| {
+            value(key)
+                .unwrap_or("")
+                .split_whitespace()
+                .map(|item| item.to_string())
+                .collect()
+        };
+
+        PubsubConfiguration {
+            backend: match value("backend") {
+                Some("native") => Backend::Native,
+                _ => Backend::Server,
+            },
+            topics: list("topics"),
+            // Expired messages are moved to dead letter topic unless configured to be dropped.
+            deadletter_topic: match value("expired_message_action") {
+                Some("drop") => None,
+                _ => Some(
+                    value("deadletter_topic")
+                        .unwrap_or(DEFAULT_DEADLETTER_TOPIC)
+                        .to_string(),
+                ),
+            },
+            bridge_to_server: list("bridge_to_server"),
+            bridge_from_server: list("bridge_from_server"),
+            bridge_interval: Duration::from_millis(
+                value("bridge_interval_in_milliseconds")
+                    .map_or(DEFAULT_BRIDGE_INTERVAL_IN_MILLISECONDS, |interval| {
+                        interval.parse::<u64>().unwrap()
+                    }),
+            ),
+        }
+    }
+
+    // Returns time each module gets to shut down, modules still running after this are abandoned
+    pub fn shutdown_deadline(&self) -> Duration {
+        Duration::from_millis(
+            self.config_value
+                .get("shutdown_deadline_in_milliseconds")
+                .and_then(|value| value.as_str())
+                .map_or(DEFAULT_SHUTDOWN_DEADLINE_IN_MILLISECONDS, |deadline| {
+                    deadline.parse::<u64>().unwrap()
+                }),
+        )
+    }
+
+    // Returns host directory under which each module gets its own private scratch directory
+    pub fn scratch_directory(&self) -> String {
+        self.config_value
+            .get("scratch_directory")
+            .and_then(|value| value.as_str())
+            .unwrap_or(DEFAULT_SCRATCH_DIRECTORY)
+            .to_string()
+    }
+}
+
+pub struct PubsubConfiguration {
+    pub backend: Backend,
+    // Topics of the native broker, messages for other topics are dropped.
+    pub topics: Vec<String>,
+    // None when expired messages are dropped.
+    pub deadletter_topic: Option<String>,
+    // Topics published in the host which are also published to pubsub server module, for its external clients.
+    pub bridge_to_server: Vec<String>,
+    // Topics external clients publish to pubsub server module, read into the native broker every bridge interval.
+    pub bridge_from_server: Vec<String>,
+    pub bridge_interval: Duration,
+}
+
+pub struct TracingConfiguration {
+    pub exporter: Exporter,
+    pub export_interval: Duration,
+}
+
+pub struct LoggingConfiguration {
+    pub format: Format,
+    pub output: Output,
+    pub default_level: Level,
+    // Level filters by module name, overriding the default level.
+    pub module_levels: HashMap<String, Level>,
+}
+
+impl Default for LoggingConfiguration {
+    fn default() -> Self {
+        Self {
+            format: Format::Text,
+            output: Output::Stdout,
+            default_level: Level::Info,
+            module_levels: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ModuleConfiguration {
+    module_name: String,
+    // Index of the replica this configuration is for, replicas share the module's configuration and identity.
+    replica: u32,
+    scratch_directory: PathBuf,
+    config_value: Value,
+}
+
+impl ModuleConfiguration {
+    // Returns module name, used by the host to identify the module in logs and messages
+    pub fn module_name(&self) -> String {
+        self.module_name.to_string()
+    }
+
+    // Returns number of instances of the module the host runs, replicas consume the same topics as competing consumers
+    pub fn replicas(&self) -> u32 {
+        self.config_value
+            .get("replicas")
+            .and_then(|value| value.as_str())
+            .map_or(1, |replicas| replicas.parse::<u32>().unwrap().max(1))
+    }
+
+    pub fn replica(&self) -> u32 {
+        self.replica
+    }
+
+    // Returns configuration for one of the module's replicas
+    pub fn for_replica(&self, replica: u32) -> Self {
+        Self {
+            replica,
+            ..self.clone()
+        }
+    }
+
+    // Returns name identifying this replica, the module name alone when the module has a single replica
+    pub fn instance_name(&self) -> String {
+        if self.replicas() > 1 {
+            format!("{}[{}]", self.module_name, self.replica)
+        } else {
+            self.module_name.to_string()
+        }
+    }
+
+    // Returns topics the module is allowed to publish to
+    pub fn publish_topics(&self) -> Vec<String> {
+        self.list("publish_topics")
+    }
+
+    // Returns topics the module is allowed to read from
+    pub fn read_topics(&self) -> Vec<String> {
+        self.list("read_topics")
+    }
+
+    // Returns topics the module is allowed to subscribe to
+    pub fn subscribe_topics(&self) -> Vec<String> {
+        self.list("subscribe_topics")
+    }
+
+    // Returns host functions and resources granted to the module, fails on unknown capabilities.
+    pub fn capabilities(&self) -> Result<Vec<Capability>> {
+        self.list("capabilities")
+            .iter()
+            .map(|name| {
+                name.parse::<Capability>().map_err(|e| {
+                    anyhow!(
+                        "Invalid configuration for module '{}': {e}",
+                        self.module_name
+                    )
+                })
+            })
+            .collect()
+    }
+
+    // Returns host directories made available to the module, in addition to its scratch directory. Fails on invalid
+    // preopens rather than panicking, configuration is re-read whenever a module is (re)started.
+    pub fn preopens(&self) -> Result<Vec<Preopen>> {
+        let preopens = match self.config_value.get("preopens") {
+            Some(value) => value.as_array().ok_or_else(|| {
+                anyhow!(
+                    "Invalid configuration for module '{}': 'preopens' is not a list of tables.",
+                    self.module_name
+                )
+            })?,
+            None => return Ok(vec![]),
+        };
+
+        preopens
+            .iter()
+            .map(|preopen| {
+                let value = |key: &str| preopen.get(key).and_then(|value| value.as_str());
+                let required = |key: &str| {
+                    value(key).ok_or_else(|| {
+                        anyhow!(
+                            "Invalid configuration for module '{}': preopen is missing '{key}'.",
+                            self.module_name
+                        )
+                    })
+                };
+
+                Ok(Preopen {
+                    host_path: required("host_path")?.to_string(),
+                    guest_path: required("guest_path")?.to_string(),
+                    read_only: match value("read_only") {
+                        Some(read_only) => read_only.parse::<bool>().map_err(|e| {
+                            anyhow!(
+                                "Invalid configuration for module '{}': preopen 'read_only' = '{read_only}': {e}.",
+                                self.module_name
+                            )
+                        })?,
+                        None => true,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    // Returns whether the module is replaced when its wasm binary or config file changes
+    pub fn hot_reload(&self) -> bool {
+        self.config_value
+            .get("hot_reload")
+            .and_then(|value| value.as_str())
+            .is_some_and(|hot_reload| hot_reload.parse::<bool>().unwrap())
+    }
+
+    // Returns version label of the module's wasm binary, shown in the module registry
+    pub fn version(&self) -> String {
+        self.config_value
+            .get("version")
+            .and_then(|value| value.as_str())
+            .unwrap_or(DEFAULT_MODULE_VERSION)
+            .to_string()
+    }
+
+    // Returns canary version to run alongside the module, modules without a canary section run a single version
+    pub fn canary(&self) -> Option<CanaryPolicy> {
+        let canary = self.config_value.get("canary")?;
+        let value = |key: &str| canary.get(key).and_then(|value| value.as_str());
+
+        Some(CanaryPolicy {
+            version: value("version").unwrap().to_string(),
+            wasm_path: value("wasm_path").unwrap().to_string(),
+            config_file_path: value("config_file_path").map(|path| path.to_string()),
+            weight_percent: value("weight_percent")
+                .map_or(DEFAULT_CANARY_WEIGHT_PERCENT, |weight| {
+                    weight.parse::<u32>().unwrap().min(100)
+                }),
+            max_traps: value("max_traps")
+                .map_or(DEFAULT_CANARY_MAX_TRAPS, |max| max.parse::<u64>().unwrap()),
+            max_error_rate_percent: value("max_error_rate_percent")
+                .map_or(DEFAULT_CANARY_MAX_ERROR_RATE_PERCENT, |max| {
+                    max.parse::<u32>().unwrap()
+                }),
+            min_ticks: value("min_ticks")
+                .map_or(DEFAULT_CANARY_MIN_TICKS, |min| min.parse::<u64>().unwrap()),
+            promote_after_ticks: value("promote_after_ticks")
+                .map(|ticks| ticks.parse::<u64>().unwrap()),
+        })
+    }
+
+    // Returns host path of the module's wasm binary, when configured
+    pub fn wasm_path(&self) -> Option<String> {
+        self.config_value
+            .get("wasm_path")
+            .and_then(|value| value.as_str())
+            .map(|wasm_path| wasm_path.to_string())
+    }
+
+    // Returns topics which trigger the module's message handler, modules without a message trigger run an init loop
+    pub fn message_trigger(&self) -> Option<MessageTrigger> {
+        let trigger = self.config_value.get("trigger")?;
+        let value = |key: &str| trigger.get(key).and_then(|value| value.as_str());
+
+        if value("type") != Some("message") {
+            return None;
+        }
+
+        Some(MessageTrigger {
+            topics: value("topics")
+                .unwrap_or("")
+                .split_whitespace()
+                .map(|topic| topic.to_string())
+                .collect(),
+            max_concurrency: value("max_concurrency")
+                .map_or(DEFAULT_MESSAGE_TRIGGER_MAX_CONCURRENCY, |max| {
+                    max.parse::<usize>().unwrap()
+                }),
+            pool_size: value("pool_size").map_or(DEFAULT_MESSAGE_TRIGGER_POOL_SIZE, |size| {
+                size.parse::<u32>().unwrap()
+            }),
+            poll_interval: Duration::from_millis(value("poll_interval_in_milliseconds").map_or(
+                DEFAULT_MESSAGE_TRIGGER_POLL_INTERVAL_IN_MILLISECONDS,
+                |interval| interval.parse::<u64>().unwrap(),
+            )),
+            max_attempts: value("max_attempts")
+                .map_or(DEFAULT_MESSAGE_TRIGGER_MAX_ATTEMPTS, |max| {
+                    max.parse::<u32>().unwrap().max(1)
+                }),
+            deadletter_topic: value("deadletter_topic").map(|topic| topic.to_string()),
+        })
+    }
+
+    // Returns routes of requests handled by the module's http handler, modules without an http trigger get no requests
+    pub fn http_trigger(&self) -> Option<HttpTrigger> {
+        let trigger = self.config_value.get("trigger")?;
+        let value = |key: &str| trigger.get(key).and_then(|value| value.as_str());
+
+        if value("type") != Some("http") {
+            return None;
+        }
+
+        let routes = trigger
+            .get("routes")
+            .and_then(|routes| routes.as_array())
+            .map(|routes| {
+                routes
+                    .iter()
+                    .map(|route| {
+                        let value = |key: &str| route.get(key).and_then(|value| value.as_str());
+                        Route {
+                            method: value("method").unwrap_or("*").to_string(),
+                            path: value("path").unwrap().to_string(),
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Some(HttpTrigger {
+            routes,
+            max_concurrency: value("max_concurrency")
+                .map_or(DEFAULT_HTTP_TRIGGER_MAX_CONCURRENCY, |max| {
+                    max.parse::<usize>().unwrap()
+                }),
+            pool_size: value("pool_size").map_or(DEFAULT_HTTP_TRIGGER_POOL_SIZE, |size| {
+                size.parse::<u32>().unwrap()
+            }),
+            max_request_body_bytes: value("max_request_body_bytes")
+                .map_or(DEFAULT_HTTP_TRIGGER_MAX_REQUEST_BODY_BYTES, |max| {
+                    max.parse::<usize>().unwrap()
+                }),
+        })
+    }
+
+    // Returns handlers the module serves to other modules, modules without an rpc section serve none
+    pub fn rpc(&self) -> Option<RpcHandlers> {
+        let rpc = self.config_value.get("rpc")?;
+        let value = |key: &str| rpc.get(key).and_then(|value| value.as_str());
+
+        Some(RpcHandlers {
+            handlers: value("handlers")
+                .unwrap_or("")
+                .split_whitespace()
+                .map(|handler| handler.to_string())
+                .collect(),
+            max_concurrency: value("max_concurrency").map_or(DEFAULT_RPC_MAX_CONCURRENCY, |max| {
+                max.parse::<usize>().unwrap()
+            }),
+            pool_size: value("pool_size")
+                .map_or(DEFAULT_RPC_POOL_SIZE, |size| size.parse::<u32>().unwrap()),
+            max_timeout: Duration::from_millis(
+                value("max_timeout_in_milliseconds")
+                    .map_or(DEFAULT_RPC_MAX_TIMEOUT_IN_MILLISECONDS, |timeout| {
+                        timeout.parse::<u64>().unwrap()
+                    }),
+            ),
+        })
+    }
+
+    // Returns handlers of other modules the module is allowed to call, as '<module>.<handler>' or '<module>.*'
+    pub fn allowed_calls(&self) -> Vec<String> {
+        self.list("allowed_calls")
+    }
+
+    // Returns host path a guest path resolves to through the module's preopens
+    pub fn host_path(&self, guest_path: &str) -> Result<Option<PathBuf>> {
+        Ok(self.preopens()?.into_iter().find_map(|preopen| {
+            Path::new(guest_path)
+                .strip_prefix(&preopen.guest_path)
+                .ok()
+                .map(|relative_path| Path::new(&preopen.host_path).join(relative_path))
+        }))
+    }
+
+    // Returns outbound http policy for the module, modules without an http section cannot reach any host
+    pub fn http_policy(&self) -> Result<HttpPolicy> {
+        let mut policy = HttpPolicy::default();
+
+        let http = match self.config_value.get("http") {
+            Some(value) => value,
+            None => return Ok(policy),
+        };
+
+        let value = |key: &str| http.get(key).and_then(|value| value.as_str());
+        let list = |key: &str| -> Vec<String> {
+            value(key)
+                .unwrap_or("")
+                .split_whitespace()
+                .map(|item| item.to_string())
+                .collect()
+        };
+
+        policy.allowed_hosts = list("allowed_hosts")
+            .iter()
+            .map(|host| AllowedHost::parse(host))
+            .collect::<Result<_>>()
+            .map_err(|e| {
+                anyhow!(
+                    "Invalid configuration for module '{}': {e}",
+                    self.module_name
+                )
+            })?;
+        policy.allowed_methods = list("allowed_methods");
+        policy.max_request_body_bytes =
+            value("max_request_body_bytes").map(|max| max.parse::<usize>().unwrap());
+        policy.max_response_body_bytes =
+            value("max_response_body_bytes").map(|max| max.parse::<usize>().unwrap());
+        policy.max_requests_per_second =
+            value("max_requests_per_second").map(|max| max.parse::<usize>().unwrap());
+
+        if let Some(max) = value("max_concurrent_requests") {
+            policy.max_concurrent_requests = max.parse::<usize>().unwrap();
+        }
+
+        if let Some(timeout) = value("request_timeout_in_milliseconds") {
+            policy.request_timeout = Duration::from_millis(timeout.parse::<u64>().unwrap());
+        }
+
+        Ok(policy)
+    }
+
+    // Returns the replica's private scratch directory on the host, replicas of a module don't share files
+    pub fn scratch_directory(&self) -> PathBuf {
+        self.scratch_directory.join(self.replica.to_string())
+    }
+
+    // Returns space separated string value as a list, missing keys return an empty list
+    fn list(&self, key: &str) -> Vec<String> {
+        self.config_value
+            .get(key)
+            .and_then(|value| value.as_str())
+            .unwrap_or("")
+            .split_whitespace()
+            .map(|item| item.to_string())
+            .collect()
+    }
+}
+
+// Checks a topic name is usable with the pubsub server module's space separated commands.
+pub fn validate_topic(topic: &str) -> Result<(), String> {
+    if topic.is_empty() {
+        Err("topic name is empty".to_string())
+    } else if topic.contains(char::is_whitespace) {
+        Err(format!("topic name '{topic}' contains whitespace"))
+    } else if RESERVED_TOPICS.contains(&topic) {
+        Err(format!("topic name '{topic}' is reserved"))
+    } else {
+        Ok(())
+    }
+}
+
+// Returns true if topic is in the list of allowed topics, '*' allows all topics.
+pub fn is_topic_allowed(allowed_topics: &[String], topic: &str) -> bool {
+    allowed_topics
+        .iter()
+        .any(|allowed| allowed == ALL_TOPICS || allowed == topic)
+}
@@ -0,0 +1,387 @@
+// Embedding API of the host, for services which run wasm modules in their own process. The host binary is a thin
+// wrapper over it, starting the pubsub server and then every other module in host configuration.
+use crate::config::{Configuration, ModuleConfiguration};
+use crate::lifecycle::ModuleHandle;
+use crate::logging::{self, Level};
+use crate::shutdown::{self, Reason, ShutdownSignal};
+use crate::wasm::lifecycle_module::Options;
+use crate::wasm::{self, HostEnvironment, HostFunctions};
+use crate::{broker, metrics, registry, tracing};
+use std::{io, path::Path, sync::Arc, time::Duration};
+
+const MODULE_NAME: &str = "Wasm Host";
+
+// How the host drives a module, which decides the exports it calls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModuleKind {
+    // Module exporting the common lifecycle (init, ontick, shutdown), ticked by its timers after init.
+    Lifecycle,
+    // Module called for each message or http request, as configured in the trigger section of its configuration.
+    Triggered,
+}
+
+// Module to start, its permissions, replicas, versions and trigger are read from host configuration by name.
+#[derive(Clone)]
+pub struct ModuleSpec {
+    name: String,
+    kind: ModuleKind,
+    wasm_path: Option<String>,
+    config_file_path: Option<String>,
+    options: Options,
+}
+
+impl ModuleSpec {
+    pub fn new(name: &str, kind: ModuleKind) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            wasm_path: None,
+            config_file_path: None,
+            options: Options::default(),
+        }
+    }
+
+    // Returns spec for a module configured in host configuration, triggered when it has a trigger section.
+    pub fn from_config(host_config: &Configuration, name: &str) -> Self {
+        let module_config = host_config.module(name);
+        let kind = if module_config.message_trigger().is_some()
+            || module_config.http_trigger().is_some()
+        {
+            ModuleKind::Triggered
+        } else {
+            ModuleKind::Lifecycle
+        };
+
+        Self::new(name, kind)
+    }
+
+    // Host path of the module's wasm binary, defaults to 'wasm_path' in host configuration then to the module's
+    // own crate build output.
+    pub fn wasm_path(mut self, wasm_path: &str) -> Self {
+        self.wasm_path = Some(wasm_path.to_string());
+        self
+    }
+
+    // Guest path of the config file passed to the module's init, defaults to '/<module name>/config.toml'.
+    pub fn config_file_path(mut self, config_file_path: &str) -> Self {
+        self.config_file_path = Some(config_file_path.to_string());
+        self
+    }
+
+    // Address of a socket the host pre-opens for a lifecycle module and passes to its init, such a module runs a
+    // single replica and is not hot reloaded, as its socket cannot be bound twice.
+    pub fn socket_address(mut self, socket_address: &str) -> Self {
+        self.options.socket_address = Some(socket_address.to_string());
+        self
+    }
+
+    // Additional host a lifecycle module can post to, on top of its configured http policy.
+    pub fn allowed_host(mut self, allowed_host: Option<String>) -> Self {
+        self.options.allowed_host = allowed_host;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+pub struct HostBuilder {
+    host_config: Configuration,
+    config_file_path: String,
+    environment: HostEnvironment,
+}
+
+impl HostBuilder {
+    pub fn new(host_config: Configuration) -> Self {
+        Self {
+            host_config,
+            config_file_path: String::new(),
+            environment: HostEnvironment::default(),
+        }
+    }
+
+    // Path host configuration was read from, log levels are reloaded from it when it changes.
+    pub fn config_file_path(mut self, config_file_path: &str) -> Self {
+        self.config_file_path = config_file_path.to_string();
+        self
+    }
+
+    // Token used by host functions to authenticate with pubsub server module.
+    pub fn server_auth_token(mut self, server_auth_token: Option<String>) -> Self {
+        self.environment.server_auth_token = server_auth_token;
+        self
+    }
+
+    // Registers custom host functions, linked into modules granted capability 'custom:<name>'.
+    pub fn host_functions(mut self, host_functions: impl HostFunctions + 'static) -> Self {
+        self.environment
+            .host_functions
+            .push(Arc::new(host_functions));
+        self
+    }
+
+    // Initialises logging and tracing, starts the host's native broker and serves metrics and http triggers when
+    // configured. Logging and tracing are process wide, only the first host built in a process configures them.
+    pub fn build(mut self) -> io::Result<Host> {
+        logging::init(&self.host_config, &self.config_file_path).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid logging configuration: {e}"),
+            )
+        })?;
+        tracing::init(&self.host_config);
+        self.environment.broker = broker::start(
+            &self.host_config,
+            self.environment.server_auth_token.clone(),
+        );
+
+        if let Some(metrics_address) = self.host_config.metrics_address() {
+            metrics::serve(&metrics_address)?;
+        }
+
+        if let Some(http_trigger_address) = self.host_config.http_trigger_address() {
+            wasm::http_trigger::serve(&http_trigger_address)?;
+        }
+
+        Ok(Host {
+            host_config: self.host_config,
+            environment: self.environment,
+            module_names: vec![],
+            modules: vec![],
+        })
+    }
+}
+
+// Running host, modules are started and stopped by the embedding service.
+pub struct Host {
+    host_config: Configuration,
+    environment: HostEnvironment,
+    // Names of started modules, in start order, alongside their replicas.
+    module_names: Vec<String>,
+    modules: Vec<Vec<ModuleHandle>>,
+}
+
+impl Host {
+    // Starts all replicas of a module, each on its own thread.
+    pub fn start_module(&mut self, spec: ModuleSpec) -> io::Result<()> {
+        if self.module_names.contains(&spec.name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Module '{}' is already running.", spec.name),
+            ));
+        }
+
+        let module_config = self.host_config.module(&spec.name);
+        let wasm_path = spec
+            .wasm_path
+            .or_else(|| module_config.wasm_path())
+            .unwrap_or_else(|| default_wasm_path(&spec.name));
+        let wasm_config_path = spec
+            .config_file_path
+            .unwrap_or_else(|| format!("/{}/config.toml", spec.name));
+        let environment = self.environment.clone();
+        let shutdown_deadline = self.host_config.shutdown_deadline();
+
+        logging::log(
+            Level::Info,
+            MODULE_NAME,
+            &format!("Module '{}' Path: {wasm_path}", spec.name),
+            &[],
+        );
+
+        let replicas = match spec.kind {
+            ModuleKind::Lifecycle => {
+                let options = spec.options;
+
+                // Replicas could not all listen on the module's pre-opened socket.
+                let replicas = if options.socket_address.is_some() {
+                    if module_config.replicas() > 1 {
+                        logging::log(
+                            Level::Warn,
+                            MODULE_NAME,
+                            &format!(
+                                "Module '{}' has a socket so does not support replicas, running one.",
+                                spec.name
+                            ),
+                            &[],
+                        );
+                    }
+                    1
+                } else {
+                    module_config.replicas()
+                };
+
+                spawn_replicas(
+                    &module_config,
+                    replicas,
+                    shutdown_deadline,
+                    move |module_config, shutdown| {
+                        let _rpc =
+                            wasm::rpc::serve(&wasm_path, &module_config, &environment, &shutdown)?;
+                        wasm::lifecycle_module::run_module(
+                            &wasm_path,
+                            &wasm_config_path,
+                            &module_config,
+                            &options,
+                            &environment,
+                            shutdown,
+                        )
+                    },
+                )?
+            }
+            ModuleKind::Triggered => spawn_replicas(
+                &module_config,
+                module_config.replicas(),
+                shutdown_deadline,
+                move |module_config, shutdown| {
+                    let _rpc =
+                        wasm::rpc::serve(&wasm_path, &module_config, &environment, &shutdown)?;
+                    if module_config.http_trigger().is_some() {
+                        wasm::http_trigger::run_module(
+                            &wasm_path,
+                            &module_config,
+                            &environment,
+                            shutdown,
+                        )
+                    } else {
+                        wasm::message_trigger::run_module(
+                            &wasm_path,
+                            &module_config,
+                            &environment,
+                            shutdown,
+                        )
+                    }
+                },
+            )?,
+        };
+
+        self.module_names.push(spec.name);
+        self.modules.push(replicas);
+
+        Ok(())
+    }
+
+    // Shuts down all replicas of a module within the host's shutdown deadline. Returns exit code of the shutdown,
+    // None when the module is not running.
+    pub fn stop_module(&mut self, module_name: &str) -> Option<i32> {
+        let index = self
+            .module_names
+            .iter()
+            .position(|name| name == module_name)?;
+
+        self.module_names.remove(index);
+        let mut replicas = [self.modules.remove(index)];
+
+        let exit_code =
+            shutdown::shutdown_modules(&mut replicas, self.host_config.shutdown_deadline());
+        // Stopped module no longer shows in the registry, it is registered again if started again.
+        registry::unregister(module_name);
+
+        Some(exit_code)
+    }
+
+    // Returns errors of the module's replicas which failed, None when the module is not running. Failed replicas stay
+    // stopped until the module is stopped and started again.
+    pub fn module_errors(&self, module_name: &str) -> Option<Vec<String>> {
+        let index = self
+            .module_names
+            .iter()
+            .position(|name| name == module_name)?;
+
+        Some(
+            self.modules[index]
+                .iter()
+                .filter_map(|replica| replica.error())
+                .collect(),
+        )
+    }
+
+    // Returns names of running modules, in start order.
+    pub fn module_names(&self) -> &[String] {
+        &self.module_names
+    }
+
+    // Blocks until SIGINT/SIGTERM is received or one of the modules stops unexpectedly, installs the process
+    // signal handler so must only be called once.
+    pub fn wait_for_shutdown(&self) -> Reason {
+        shutdown::wait_for_shutdown(&self.modules)
+    }
+
+    // Shuts down all modules in reverse start order, so modules are stopped before the modules they depend on.
+    // Spans still waiting for export are exported before returning the process exit code.
+    pub fn shutdown(mut self) -> i32 {
+        self.modules.reverse();
+        let exit_code =
+            shutdown::shutdown_modules(&mut self.modules, self.host_config.shutdown_deadline());
+        tracing::flush();
+
+        exit_code
+    }
+
+    // Returns spec for each module with a section in host configuration, by module name.
+    pub fn configured_modules(&self) -> Vec<ModuleSpec> {
+        self.host_config
+            .module_names()
+            .iter()
+            .map(|module_name| ModuleSpec::from_config(&self.host_config, module_name))
+            .collect()
+    }
+}
+
+// Runs each of the module's replicas on its own thread, each replica is a separate instance of the module. When a
+// replica fails to spawn the replicas spawned before it are shut down within the deadline and the module unregistered.
+fn spawn_replicas<F>(
+    module_config: &ModuleConfiguration,
+    replicas: u32,
+    shutdown_deadline: Duration,
+    run: F,
+) -> io::Result<Vec<ModuleHandle>>
+where
+    F: Fn(ModuleConfiguration, ShutdownSignal) -> anyhow::Result<()> + Clone + Send + 'static,
+{
+    let mut modules = Vec::new();
+
+    for replica in 0..replicas {
+        let replica_config = module_config.for_replica(replica);
+        let run = run.clone();
+
+        let module = match ModuleHandle::spawn(&replica_config.instance_name(), move |shutdown| {
+            run(replica_config, shutdown)
+        }) {
+            Ok(module) => module,
+            Err(e) => {
+                shutdown::shutdown_modules(&mut [modules], shutdown_deadline);
+                registry::unregister(&module_config.module_name());
+                return Err(e);
+            }
+        };
+        registry::register_replica(
+            &module_config.module_name(),
+            replica,
+            module.shutdown_signal().clone(),
+        );
+
+        modules.push(module);
+    }
+
+    Ok(modules)
+}
+
+// Returns wasm binary built for a module in the module's own crate, for the host's build profile. Modules built as
+// components (wasm32-wasip2) are preferred over core modules (wasm32-wasi) when both were built.
+fn default_wasm_path(module_name: &str) -> String {
+    let profile = if cfg!(debug_assertions) {
+        "debug"
+    } else {
+        "release"
+    };
+
+    let component_path =
+        format!("../{module_name}/target/wasm32-wasip2/{profile}/{module_name}.wasm");
+    if Path::new(&component_path).exists() {
+        return component_path;
+    }
+
+    format!("../{module_name}/target/wasm32-wasi/{profile}/{module_name}.wasm")
+}
@@ -0,0 +1,16 @@
+// Wasm host runtime, embeddable in other Rust services through 'HostBuilder'.
+pub mod broker;
+pub mod config;
+pub mod embed;
+pub mod lifecycle;
+pub mod logging;
+pub mod metrics;
+pub mod registry;
+pub mod shutdown;
+pub mod tracing;
+pub mod wasm;
+
+pub use embed::{Host, HostBuilder, ModuleKind, ModuleSpec};
+pub use wasm::{Context, HostFunctions};
+// Wasmtime version host functions are linked with, so embedders can name 'Linker<Context>' in 'HostFunctions'.
+pub use wit_bindgen_wasmtime::wasmtime;
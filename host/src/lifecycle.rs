@@ -0,0 +1,78 @@
+use crate::logging::{self, Level};
+use crate::shutdown::ShutdownSignal;
+use anyhow::Result;
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+const MODULE_NAME: &str = "Wasm Host";
+
+// Handle to a module running on its own OS thread, host calls made by the module block only this thread.
+pub struct ModuleHandle {
+    module_name: String,
+    shutdown: ShutdownSignal,
+    // Error the module stopped with, the module is marked failed while the rest of the host keeps running.
+    error: Arc<Mutex<Option<String>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ModuleHandle {
+    // Runs the module on a new named thread, run is given the signal used to shut the module down.
+    pub fn spawn<F>(module_name: &str, run: F) -> io::Result<Self>
+    where
+        F: FnOnce(ShutdownSignal) -> Result<()> + Send + 'static,
+    {
+        let shutdown = ShutdownSignal::default();
+        let module_shutdown = shutdown.clone();
+        let error = Arc::new(Mutex::new(None));
+        let module_error = error.clone();
+        let name = module_name.to_string();
+
+        let thread = thread::Builder::new()
+            .name(format!("module-{module_name}"))
+            .spawn(move || {
+                // Module is marked stopped when run returns or panics, and failed when it returns an error.
+                let _finish_guard = module_shutdown.finish_guard();
+
+                if let Err(e) = run(module_shutdown.clone()) {
+                    logging::log(
+                        Level::Error,
+                        MODULE_NAME,
+                        &format!("Module '{name}' failed; error = {e:#}"),
+                        &[("module", &name)],
+                    );
+                    *module_error.lock().unwrap() = Some(format!("{e:#}"));
+                    module_shutdown.finish(false);
+                }
+            })?;
+
+        Ok(Self {
+            module_name: module_name.to_string(),
+            shutdown,
+            error,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn module_name(&self) -> &str {
+        &self.module_name
+    }
+
+    pub fn shutdown_signal(&self) -> &ShutdownSignal {
+        &self.shutdown
+    }
+
+    // Returns the error the module stopped with, None while it runs or when it stopped without one.
+    pub fn error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+
+    // Waits for the module thread to exit, only call once the module has finished otherwise this blocks.
+    pub fn join(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use crate::config::{Configuration, LoggingConfiguration};
+use anyhow::Result;
+use chrono::Utc;
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+const MODULE_NAME: &str = "Wasm Host";
+// Minimum time between checks of the host configuration file for changed log levels.
+const LEVELS_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+static LOGGER: OnceLock<Mutex<Logger>> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn parse(level: &str) -> Option<Self> {
+        match level.to_lowercase().as_str() {
+            "trace" => Some(Level::Trace),
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+pub enum Output {
+    Stdout,
+    File {
+        path: PathBuf,
+        max_file_size_bytes: u64,
+        max_files: u32,
+    },
+}
+
+struct Logger {
+    format: Format,
+    output: Output,
+    file: Option<File>,
+    default_level: Level,
+    module_levels: HashMap<String, Level>,
+    // Host configuration file is re-read for log level changes while the host is running.
+    config_file_path: String,
+    config_file_modified: Option<SystemTime>,
+    levels_checked_at: Instant,
+}
+
+impl Logger {
+    fn new(logging_config: LoggingConfiguration, config_file_path: &str) -> Self {
+        Self {
+            format: logging_config.format,
+            output: logging_config.output,
+            file: None,
+            default_level: logging_config.default_level,
+            module_levels: logging_config.module_levels,
+            config_file_path: config_file_path.to_string(),
+            config_file_modified: modified(config_file_path),
+            levels_checked_at: Instant::now(),
+        }
+    }
+
+    fn is_enabled(&mut self, level: Level, module: &str) -> bool {
+        self.reload_levels();
+
+        level
+            >= *self
+                .module_levels
+                .get(module)
+                .unwrap_or(&self.default_level)
+    }
+
+    // Applies log level changes made to host configuration file since it was last read.
+    fn reload_levels(&mut self) {
+        if self.levels_checked_at.elapsed() < LEVELS_RELOAD_INTERVAL {
+            return;
+        }
+        self.levels_checked_at = Instant::now();
+
+        let config_file_modified = modified(&self.config_file_path);
+        if config_file_modified == self.config_file_modified {
+            return;
+        }
+        self.config_file_modified = config_file_modified;
+
+        // Invalid changes keep the current levels, this runs while the logger is locked so must not panic.
+        let logging_config = fs::read_to_string(&self.config_file_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|config_file_contents| {
+                Configuration::parse(&config_file_contents)?.logging()
+            });
+
+        match logging_config {
+            Ok(logging_config) => {
+                self.default_level = logging_config.default_level;
+                self.module_levels = logging_config.module_levels;
+            }
+            Err(e) => {
+                let line = self.format(
+                    Level::Warn,
+                    MODULE_NAME,
+                    &format!("Failed to reload log levels, keeping current levels; error = {e}"),
+                    &[],
+                );
+                self.write(&line);
+            }
+        }
+    }
+
+    fn format(&self, level: Level, module: &str, message: &str, fields: &[(&str, &str)]) -> String {
+        let timestamp = Utc::now().to_rfc3339();
+
+        match self.format {
+            Format::Text => {
+                let fields: String = fields
+                    .iter()
+                    .map(|(key, value)| format!(" {key}={value:?}"))
+                    .collect();
+
+                format!(
+                    "{timestamp} {:<5} Module: {module}, Message: {message}{fields}",
+                    level.as_str()
+                )
+            }
+            Format::Json => {
+                let mut record = serde_json::Map::new();
+                record.insert("timestamp".to_string(), timestamp.into());
+                record.insert("level".to_string(), level.as_str().into());
+                record.insert("module".to_string(), module.into());
+                record.insert("message".to_string(), message.into());
+
+                let fields: serde_json::Map<String, serde_json::Value> = fields
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), (*value).into()))
+                    .collect();
+                record.insert("fields".to_string(), fields.into());
+
+                serde_json::Value::Object(record).to_string()
+            }
+        }
+    }
+
+    fn write(&mut self, line: &str) {
+        match &self.output {
+            Output::Stdout => println!("{line}"),
+            Output::File {
+                path,
+                max_file_size_bytes,
+                max_files,
+            } => {
+                let (path, max_file_size_bytes, max_files) =
+                    (path.to_path_buf(), *max_file_size_bytes, *max_files);
+
+                if fs::metadata(&path).is_ok_and(|m| m.len() >= max_file_size_bytes) {
+                    self.file = None;
+                    rotate(&path, max_files);
+                }
+
+                if self.file.is_none() {
+                    if let Some(parent) = path.parent() {
+                        let _ = fs::create_dir_all(parent);
+                    }
+                    self.file = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .ok();
+                }
+
+                if let Some(file) = self.file.as_mut() {
+                    let _ = writeln!(file, "{line}");
+                }
+            }
+        }
+    }
+}
+
+// Shifts 'host.log' to 'host.log.1', 'host.log.1' to 'host.log.2' and so on, dropping the oldest file.
+fn rotate(path: &Path, max_files: u32) {
+    let rotated = |index: u32| PathBuf::from(format!("{}.{index}", path.display()));
+
+    let _ = fs::remove_file(rotated(max_files));
+    for index in (1..max_files).rev() {
+        let _ = fs::rename(rotated(index), rotated(index + 1));
+    }
+
+    if max_files > 0 {
+        let _ = fs::rename(path, rotated(1));
+    } else {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Initialises host log pipeline, must be called before anything is logged otherwise defaults are used.
+pub fn init(host_config: &Configuration, config_file_path: &str) -> Result<()> {
+    let _ = LOGGER.set(Mutex::new(Logger::new(
+        host_config.logging()?,
+        config_file_path,
+    )));
+
+    Ok(())
+}
+
+pub fn log(level: Level, module: &str, message: &str, fields: &[(&str, &str)]) {
+    let logger =
+        LOGGER.get_or_init(|| Mutex::new(Logger::new(LoggingConfiguration::default(), "")));
+    // A panic while logging must not stop all later logging.
+    let mut logger = logger.lock().unwrap_or_else(|e| e.into_inner());
+
+    if !logger.is_enabled(level, module) {
+        return;
+    }
+
+    let line = logger.format(level, module, message, fields);
+    logger.write(&line);
+}
@@ -1,97 +1,100 @@
-mod wasm;
+use std::{fs, process, thread, time::Duration};
 
-use std::{thread, time::Duration};
-
-use async_std::task;
 use clap::Parser;
+use host::config::Configuration;
+use host::logging::{self, Level};
+use host::shutdown::Reason;
+use host::{HostBuilder, ModuleKind, ModuleSpec};
+
+const MODULE_NAME: &str = "Wasm Host";
+const SERVER_MODULE_NAME: &str = "server_module";
+const GATEWAY_MODULE_NAME: &str = "gateway_module";
 
 #[derive(Parser, Debug)]
 struct CliParams {
+    // Additional host gateway module can post to, on top of its configured http policy.
     #[arg(short, long)]
-    gateway_allowed_host: String,
+    gateway_allowed_host: Option<String>,
 
     #[arg(short, long)]
     server_socket_address: String,
+
+    // Token used by host functions to authenticate with pubsub server module.
+    #[arg(long)]
+    server_auth_token: Option<String>,
+
+    // Host configuration file with per module topic permissions.
+    #[arg(long, default_value = "config.toml")]
+    config_file_path: String,
 }
 
 fn main() {
     let cli_params = CliParams::parse();
 
-    println!("Initialising host service...");
-
-    let server_module_name = "server_module";
-    let gateway_module_name = "gateway_module";
-    let telemetry_module_name = "telemetry_module";
-
-    let (server_module_path, telemetry_module_path, gateway_module_path) = if cfg!(debug_assertions)
-    {
-        (
-            format!(
-                "../{0}/target/wasm32-wasi/debug/{0}.wasm",
-                server_module_name
-            ),
-            format!(
-                "../{0}/target/wasm32-wasi/debug/{0}.wasm",
-                telemetry_module_name
-            ),
-            format!(
-                "../{0}/target/wasm32-wasi/debug/{0}.wasm",
-                gateway_module_name
-            ),
-        )
-    } else {
-        (
-            format!(
-                "../{0}/target/wasm32-wasi/release/{0}.wasm",
-                server_module_name
-            ),
-            format!(
-                "../{0}/target/wasm32-wasi/release/{0}.wasm",
-                telemetry_module_name
-            ),
-            format!(
-                "../{0}/target/wasm32-wasi/release/{0}.wasm",
-                gateway_module_name
-            ),
-        )
-    };
-
-    println!("Server Module Path: {}", server_module_path);
-
-    let server_task = task::spawn(async move {
-        wasm::server_module::run_module(
-            &server_module_path,
-            &format!("./{0}/config.toml", server_module_name),
-            Some(cli_params.server_socket_address),
-        )
-        .unwrap();
-    });
+    let host_config = Configuration::new(
+        fs::read_to_string(&cli_params.config_file_path)
+            .expect("Could not read host configuration file."),
+    );
+
+    let mut host = HostBuilder::new(host_config)
+        .config_file_path(&cli_params.config_file_path)
+        .server_auth_token(cli_params.server_auth_token)
+        .build()
+        .expect("Could not start host endpoints.");
+
+    logging::log(
+        Level::Info,
+        MODULE_NAME,
+        "Initialising host service...",
+        &[],
+    );
+
+    host.start_module(
+        ModuleSpec::new(SERVER_MODULE_NAME, ModuleKind::Lifecycle)
+            .socket_address(&cli_params.server_socket_address),
+    )
+    .expect("Could not start server module thread.");
 
     // Induce synthetic delay of 5 secs before connections can be made to server module.
     thread::sleep(Duration::from_millis(5000));
 
-    println!("Gateway Module Path: {}", gateway_module_path);
-
-    let gateway_task = task::spawn(async move {
-        wasm::gateway_module::run_module(
-            &gateway_module_path,
-            &format!("./{0}/config.toml", gateway_module_name),
-            Some(cli_params.gateway_allowed_host),
-        );
-    });
-
-    println!("Telemetry Module Path: {}", telemetry_module_path);
-
-    let telemetry_task = task::spawn(async move {
-        wasm::telemetry_module::run_module(
-            &telemetry_module_path,
-            &format!("./{0}/config.toml", telemetry_module_name),
-        );
-    });
-
-    task::block_on(async {
-        server_task.await;
-        gateway_task.await;
-        telemetry_task.await;
-    });
+    // Every other module with a section in host configuration is started once the server module is up. Message and
+    // http triggered modules are configured only there, the host reads their topics or routes http requests to them.
+    for spec in host.configured_modules() {
+        if spec.name() == SERVER_MODULE_NAME {
+            continue;
+        }
+
+        // Host allowed via cmdline is added to gateway module's configured http policy.
+        let spec = if spec.name() == GATEWAY_MODULE_NAME {
+            spec.allowed_host(cli_params.gateway_allowed_host.clone())
+        } else {
+            spec
+        };
+
+        host.start_module(spec)
+            .expect("Could not start module thread.");
+    }
+
+    match host.wait_for_shutdown() {
+        Reason::Signal => logging::log(Level::Info, MODULE_NAME, "Shutdown signal received.", &[]),
+        Reason::AllModulesStopped => logging::log(
+            Level::Error,
+            MODULE_NAME,
+            "All modules stopped unexpectedly, shutting down.",
+            &[],
+        ),
+    }
+
+    // Modules are shut down in reverse start order, publishers and readers before pubsub server module.
+    let exit_code = host.shutdown();
+
+    logging::log(
+        Level::Info,
+        MODULE_NAME,
+        "Host service stopped.",
+        &[("exit_code", &exit_code.to_string())],
+    );
+
+    process::exit(exit_code);
 }
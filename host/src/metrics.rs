@@ -0,0 +1,267 @@
+use crate::logging::{self, Level};
+use crate::registry;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Mutex, OnceLock},
+    thread,
+    time::Duration,
+};
+
+const MODULE_NAME: &str = "Wasm Host";
+const METRICS_PATH: &str = "/metrics";
+// Module versions from the module registry, as JSON.
+const MODULES_PATH: &str = "/modules";
+// Longest a client may take to send its request or receive the response, so an idle client does not hold up others.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+// Upper bounds of histogram buckets, in the unit of the recorded values.
+const HISTOGRAM_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+
+// Labels are kept sorted by name so the same label set always identifies the same series.
+type Labels = BTreeMap<String, String>;
+
+enum Metric {
+    Counter(BTreeMap<Labels, f64>),
+    Gauge(BTreeMap<Labels, f64>),
+    Histogram(BTreeMap<Labels, Histogram>),
+}
+
+#[derive(Default)]
+struct Histogram {
+    bucket_counts: [u64; HISTOGRAM_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    metrics: BTreeMap<String, Metric>,
+}
+
+impl Registry {
+    // Returns false if metric was already registered with a different type.
+    fn update(&mut self, name: &str, labels: Labels, value: f64, kind: &str) -> bool {
+        let metric = self
+            .metrics
+            .entry(name.to_string())
+            .or_insert_with(|| match kind {
+                "counter" => Metric::Counter(BTreeMap::new()),
+                "gauge" => Metric::Gauge(BTreeMap::new()),
+                _ => Metric::Histogram(BTreeMap::new()),
+            });
+
+        match (metric, kind) {
+            (Metric::Counter(series), "counter") => {
+                *series.entry(labels).or_insert(0.0) += value;
+            }
+            (Metric::Gauge(series), "gauge") => {
+                series.insert(labels, value);
+            }
+            (Metric::Histogram(series), "histogram") => {
+                let histogram = series.entry(labels).or_default();
+                for (index, bound) in HISTOGRAM_BUCKETS.iter().enumerate() {
+                    if value <= *bound {
+                        histogram.bucket_counts[index] += 1;
+                    }
+                }
+                histogram.sum += value;
+                histogram.count += 1;
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    // Renders all metrics in Prometheus text exposition format.
+    fn render(&self) -> String {
+        let mut output = String::new();
+
+        for (name, metric) in &self.metrics {
+            match metric {
+                Metric::Counter(series) => {
+                    let _ = writeln!(output, "# TYPE {name} counter");
+                    for (labels, value) in series {
+                        let _ = writeln!(output, "{name}{} {value}", render_labels(labels, None));
+                    }
+                }
+                Metric::Gauge(series) => {
+                    let _ = writeln!(output, "# TYPE {name} gauge");
+                    for (labels, value) in series {
+                        let _ = writeln!(output, "{name}{} {value}", render_labels(labels, None));
+                    }
+                }
+                Metric::Histogram(series) => {
+                    let _ = writeln!(output, "# TYPE {name} histogram");
+                    for (labels, histogram) in series {
+                        for (index, bound) in HISTOGRAM_BUCKETS.iter().enumerate() {
+                            let _ = writeln!(
+                                output,
+                                "{name}_bucket{} {}",
+                                render_labels(labels, Some(&bound.to_string())),
+                                histogram.bucket_counts[index]
+                            );
+                        }
+                        let _ = writeln!(
+                            output,
+                            "{name}_bucket{} {}",
+                            render_labels(labels, Some("+Inf")),
+                            histogram.count
+                        );
+                        let _ = writeln!(
+                            output,
+                            "{name}_sum{} {}",
+                            render_labels(labels, None),
+                            histogram.sum
+                        );
+                        let _ = writeln!(
+                            output,
+                            "{name}_count{} {}",
+                            render_labels(labels, None),
+                            histogram.count
+                        );
+                    }
+                }
+            }
+        }
+
+        output
+    }
+}
+
+fn render_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut rendered: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+        .collect();
+
+    if let Some(le) = le {
+        rendered.push(format!("le=\"{le}\""));
+    }
+
+    if rendered.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", rendered.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Metric and label names must match Prometheus naming rules, invalid characters are replaced.
+fn sanitise(name: &str) -> String {
+    name.chars()
+        .enumerate()
+        .map(|(index, c)| match c {
+            'a'..='z' | 'A'..='Z' | '_' | ':' => c,
+            '0'..='9' if index > 0 => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn record(kind: &str, module: &str, name: &str, value: f64, labels: &[(&str, &str)]) {
+    let mut series_labels: Labels = labels
+        .iter()
+        .map(|(name, value)| (sanitise(name), value.to_string()))
+        .collect();
+    // Module label is always set by the host, modules cannot report metrics as another module.
+    series_labels.insert("module".to_string(), module.to_string());
+
+    let name = sanitise(name);
+    let updated = REGISTRY
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .update(&name, series_labels, value, kind);
+
+    if !updated {
+        logging::log(
+            Level::Warn,
+            MODULE_NAME,
+            &format!("Metric '{name}' is already registered with a different type, ignoring {kind} update."),
+            &[("module", module)],
+        );
+    }
+}
+
+pub fn counter_increment(module: &str, name: &str, value: f64, labels: &[(&str, &str)]) {
+    record("counter", module, name, value, labels);
+}
+
+pub fn gauge_set(module: &str, name: &str, value: f64, labels: &[(&str, &str)]) {
+    record("gauge", module, name, value, labels);
+}
+
+pub fn histogram_record(module: &str, name: &str, value: f64, labels: &[(&str, &str)]) {
+    record("histogram", module, name, value, labels);
+}
+
+// Serves metrics in Prometheus text format on '/metrics' and module versions on '/modules' at the given address,
+// on a background thread.
+pub fn serve(address: &str) -> std::io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+
+    logging::log(
+        Level::Info,
+        MODULE_NAME,
+        &format!("Serving metrics on 'http://{address}{METRICS_PATH}'."),
+        &[],
+    );
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream) {
+                logging::log(
+                    Level::Warn,
+                    MODULE_NAME,
+                    &format!("Failed to serve metrics request; error = {e}"),
+                    &[],
+                );
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+
+    let (status, content_type, body) = match path {
+        METRICS_PATH => {
+            let body = REGISTRY
+                .get_or_init(Default::default)
+                .lock()
+                .unwrap()
+                .render();
+            ("200 OK", "text/plain; version=0.0.4", body)
+        }
+        MODULES_PATH => ("200 OK", "application/json", registry::render()),
+        _ => ("404 Not Found", "text/plain", String::new()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
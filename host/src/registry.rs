@@ -0,0 +1,283 @@
+// Versions of each module the host runs, which one is active and how its canary is doing, and the status of each
+// of the module's replicas.
+use crate::shutdown::ShutdownSignal;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, OnceLock},
+};
+
+static REGISTRY: OnceLock<Mutex<BTreeMap<String, Module>>> = OnceLock::new();
+
+#[derive(Default)]
+struct Module {
+    versions: Vec<Version>,
+    replicas: BTreeMap<u32, Replica>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    // Version handling the module's work, apart from the share given to its canary.
+    Active,
+    // Version handling a weighted share of the module's work alongside the active version.
+    Canary,
+    // Canary which exceeded its error thresholds and was stopped.
+    RolledBack,
+    // Previously active version replaced by a promoted canary.
+    Retired,
+}
+
+impl Role {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Role::Active => "active",
+            Role::Canary => "canary",
+            Role::RolledBack => "rolled_back",
+            Role::Retired => "retired",
+        }
+    }
+}
+
+// Status reported by a replica while it runs, once shutdown is requested its shutdown signal is reported instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplicaStatus {
+    Starting,
+    Running,
+}
+
+struct Replica {
+    status: ReplicaStatus,
+    shutdown: Option<ShutdownSignal>,
+    ticks: u64,
+    last_tick_at: Option<DateTime<Utc>>,
+    updated_at: DateTime<Utc>,
+}
+
+impl Replica {
+    fn status(&self) -> &'static str {
+        let shutdown = match &self.shutdown {
+            Some(shutdown) => shutdown,
+            None => return self.running_status(),
+        };
+
+        match shutdown.finished() {
+            Some(true) => "stopped",
+            Some(false) => "failed",
+            None if shutdown.is_requested() => "stopping",
+            None => self.running_status(),
+        }
+    }
+
+    fn running_status(&self) -> &'static str {
+        match self.status {
+            ReplicaStatus::Starting => "starting",
+            ReplicaStatus::Running => "running",
+        }
+    }
+}
+
+struct Version {
+    version: String,
+    wasm_path: String,
+    role: Role,
+    weight_percent: u32,
+    ticks: u64,
+    failed_ticks: u64,
+    traps: u64,
+    updated_at: DateTime<Utc>,
+}
+
+// Ticks handled by a version across all replicas of its module.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ticks {
+    pub ticks: u64,
+    pub failed_ticks: u64,
+    pub traps: u64,
+}
+
+fn with_registry<R>(f: impl FnOnce(&mut BTreeMap<String, Module>) -> R) -> R {
+    f(&mut REGISTRY.get_or_init(Default::default).lock().unwrap())
+}
+
+fn with_version<R>(module: &str, version: &str, f: impl FnOnce(&mut Version) -> R) -> Option<R> {
+    with_registry(|registry| {
+        registry
+            .get_mut(module)?
+            .versions
+            .iter_mut()
+            .find(|entry| entry.version == version)
+            .map(f)
+    })
+}
+
+fn with_replica(module: &str, replica: u32, f: impl FnOnce(&mut Replica)) {
+    with_registry(|registry| {
+        let replica = registry
+            .entry(module.to_string())
+            .or_default()
+            .replicas
+            .entry(replica)
+            .or_insert_with(|| Replica {
+                status: ReplicaStatus::Starting,
+                shutdown: None,
+                ticks: 0,
+                last_tick_at: None,
+                updated_at: Utc::now(),
+            });
+        f(replica);
+    });
+}
+
+// Records a replica's shutdown signal, from which the host reports whether it is stopping, stopped or failed.
+pub fn register_replica(module: &str, replica: u32, shutdown: ShutdownSignal) {
+    with_replica(module, replica, |replica| replica.shutdown = Some(shutdown));
+}
+
+pub fn set_replica_status(module: &str, replica: u32, status: ReplicaStatus) {
+    with_replica(module, replica, |replica| {
+        replica.status = status;
+        replica.updated_at = Utc::now();
+    });
+}
+
+pub fn replica_ticked(module: &str, replica: u32) {
+    with_replica(module, replica, |replica| {
+        replica.ticks += 1;
+        replica.last_tick_at = Some(Utc::now());
+    });
+}
+
+// Removes a stopped module with its versions and replicas.
+pub fn unregister(module: &str) {
+    with_registry(|registry| registry.remove(module));
+}
+
+// Records a version of a module as started, replacing an earlier entry for the same version e.g. after hot reload.
+pub fn register(module: &str, version: &str, wasm_path: &str, role: Role, weight_percent: u32) {
+    with_registry(|registry| {
+        let versions = &mut registry.entry(module.to_string()).or_default().versions;
+        versions.retain(|entry| entry.version != version);
+        versions.push(Version {
+            version: version.to_string(),
+            wasm_path: wasm_path.to_string(),
+            role,
+            weight_percent,
+            ticks: 0,
+            failed_ticks: 0,
+            traps: 0,
+            updated_at: Utc::now(),
+        });
+    });
+}
+
+// Records a canary version of a module once, replicas starting the same canary share its entry, so its tick counts
+// and any rollback or promotion decided by one replica apply to all of them.
+pub fn register_canary(module: &str, version: &str, wasm_path: &str, weight_percent: u32) {
+    with_registry(|registry| {
+        let versions = &mut registry.entry(module.to_string()).or_default().versions;
+        if versions.iter().any(|entry| entry.version == version) {
+            return;
+        }
+
+        versions.push(Version {
+            version: version.to_string(),
+            wasm_path: wasm_path.to_string(),
+            role: Role::Canary,
+            weight_percent,
+            ticks: 0,
+            failed_ticks: 0,
+            traps: 0,
+            updated_at: Utc::now(),
+        });
+    });
+}
+
+// Returns whether the role changed, so a decision taken by several replicas is only counted once.
+pub fn set_role(module: &str, version: &str, role: Role, weight_percent: u32) -> bool {
+    with_version(module, version, |entry| {
+        let changed = entry.role != role;
+        entry.role = role;
+        entry.weight_percent = weight_percent;
+        entry.updated_at = Utc::now();
+        changed
+    })
+    .unwrap_or(false)
+}
+
+pub fn role(module: &str, version: &str) -> Option<Role> {
+    with_version(module, version, |entry| entry.role)
+}
+
+// Counts a tick handled by a version on any of the module's replicas, returns the version's totals.
+pub fn version_ticked(module: &str, version: &str, failed: bool, trapped: bool) -> Ticks {
+    with_version(module, version, |entry| {
+        entry.ticks += 1;
+        entry.failed_ticks += u64::from(failed);
+        entry.traps += u64::from(trapped);
+
+        Ticks {
+            ticks: entry.ticks,
+            failed_ticks: entry.failed_ticks,
+            traps: entry.traps,
+        }
+    })
+    .unwrap_or_default()
+}
+
+// Returns all modules with their versions and replicas as JSON, served on the host's '/modules' endpoint.
+pub fn render() -> String {
+    with_registry(|registry| {
+        let modules: serde_json::Map<String, serde_json::Value> = registry
+            .iter()
+            .map(|(module_name, module)| {
+                let active = module
+                    .versions
+                    .iter()
+                    .find(|entry| entry.role == Role::Active)
+                    .map(|entry| entry.version.clone());
+                let versions: Vec<serde_json::Value> = module
+                    .versions
+                    .iter()
+                    .map(|entry| {
+                        json!({
+                            "version": entry.version,
+                            "wasm_path": entry.wasm_path,
+                            "role": entry.role.as_str(),
+                            "weight_percent": entry.weight_percent,
+                            "ticks": entry.ticks,
+                            "failed_ticks": entry.failed_ticks,
+                            "traps": entry.traps,
+                            "updated_at": entry.updated_at.to_rfc3339(),
+                        })
+                    })
+                    .collect();
+
+                let replicas: Vec<serde_json::Value> = module
+                    .replicas
+                    .iter()
+                    .map(|(index, replica)| {
+                        json!({
+                            "replica": index,
+                            "status": replica.status(),
+                            "ticks": replica.ticks,
+                            "last_tick_at": replica.last_tick_at.map(|at| at.to_rfc3339()),
+                            "updated_at": replica.updated_at.to_rfc3339(),
+                        })
+                    })
+                    .collect();
+
+                (
+                    module_name.clone(),
+                    json!({
+                        "active_version": active,
+                        "versions": versions,
+                        "replicas": replicas,
+                    }),
+                )
+            })
+            .collect();
+
+        serde_json::Value::Object(modules).to_string()
+    })
+}
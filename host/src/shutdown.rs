@@ -0,0 +1,223 @@
+use crate::lifecycle::ModuleHandle;
+use crate::logging::{self, Level};
+use std::{
+    collections::HashSet,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+const MODULE_NAME: &str = "Wasm Host";
+
+// Process exit codes, when several apply the highest is used.
+pub const EXIT_OK: i32 = 0;
+// A module failed or stopped before shutdown was requested.
+pub const EXIT_MODULE_FAILED: i32 = 1;
+// A module did not finish shutting down within its deadline.
+pub const EXIT_DEADLINE_EXCEEDED: i32 = 2;
+
+// How often the host checks for modules which stopped unexpectedly while waiting for a signal.
+const MODULE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct State {
+    // Time a module has to finish shutting down, set when shutdown is requested.
+    deadline: Option<Duration>,
+    // Set once module has stopped, true if it stopped cleanly.
+    finished: Option<bool>,
+}
+
+// Shutdown state of a module, shared between the host and the module's thread and host functions.
+#[derive(Clone, Default)]
+pub struct ShutdownSignal {
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+impl ShutdownSignal {
+    pub fn request(&self, deadline: Duration) {
+        let (state, changed) = &*self.state;
+        state.lock().unwrap().deadline = Some(deadline);
+        changed.notify_all();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.deadline().is_some()
+    }
+
+    pub fn deadline(&self) -> Option<Duration> {
+        self.state.0.lock().unwrap().deadline
+    }
+
+    pub fn wait_until_requested(&self) {
+        let (state, changed) = &*self.state;
+        let _state = changed
+            .wait_while(state.lock().unwrap(), |state| state.deadline.is_none())
+            .unwrap();
+    }
+
+    // Waits up to timeout for shutdown to be requested, returns true if it was.
+    pub fn wait_requested(&self, timeout: Duration) -> bool {
+        let (state, changed) = &*self.state;
+        let state = changed
+            .wait_timeout_while(state.lock().unwrap(), timeout, |state| {
+                state.deadline.is_none()
+            })
+            .unwrap()
+            .0;
+
+        state.deadline.is_some()
+    }
+
+    pub fn finish(&self, success: bool) {
+        let (state, changed) = &*self.state;
+        state.lock().unwrap().finished.get_or_insert(success);
+        changed.notify_all();
+    }
+
+    pub fn finished(&self) -> Option<bool> {
+        self.state.0.lock().unwrap().finished
+    }
+
+    // Waits up to timeout for the module to stop, returns None if it is still running.
+    pub fn wait_finished(&self, timeout: Duration) -> Option<bool> {
+        let (state, changed) = &*self.state;
+        changed
+            .wait_timeout_while(state.lock().unwrap(), timeout, |state| {
+                state.finished.is_none()
+            })
+            .unwrap()
+            .0
+            .finished
+    }
+
+    // Returns guard which marks the module as stopped when dropped, as failed if its thread is panicking.
+    pub fn finish_guard(&self) -> FinishGuard {
+        FinishGuard(self.clone())
+    }
+}
+
+pub struct FinishGuard(ShutdownSignal);
+
+impl Drop for FinishGuard {
+    fn drop(&mut self) {
+        // Modules which stop before shutdown was requested have failed, they are expected to run until then.
+        self.0.finish(!thread::panicking() && self.0.is_requested());
+    }
+}
+
+// Why the host is shutting down.
+pub enum Reason {
+    Signal,
+    // Every replica of every module stopped before shutdown was requested.
+    AllModulesStopped,
+}
+
+// Blocks until SIGINT/SIGTERM is received or all of the modules' replicas have stopped. A replica which stops
+// unexpectedly is reported and left stopped, the host keeps running the others.
+pub fn wait_for_shutdown(modules: &[Vec<ModuleHandle>]) -> Reason {
+    let mut stopped = HashSet::new();
+
+    let (signal_sender, signal_receiver) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = signal_sender.send(());
+    })
+    .expect("Could not install signal handler.");
+
+    loop {
+        if signal_receiver.recv_timeout(MODULE_CHECK_INTERVAL).is_ok() {
+            return Reason::Signal;
+        }
+
+        for module in modules
+            .iter()
+            .flatten()
+            .filter(|module| module.shutdown_signal().finished().is_some())
+        {
+            if stopped.insert(module.module_name().to_string()) {
+                logging::log(
+                    Level::Error,
+                    MODULE_NAME,
+                    &format!(
+                        "Module '{}' stopped unexpectedly, other modules keep running.",
+                        module.module_name()
+                    ),
+                    &[],
+                );
+            }
+        }
+
+        if !stopped.is_empty() && stopped.len() == modules.iter().flatten().count() {
+            return Reason::AllModulesStopped;
+        }
+    }
+}
+
+// Shuts modules down one at a time in the given order, replicas of a module are shut down together and share its
+// deadline. Returns process exit code.
+pub fn shutdown_modules(modules: &mut [Vec<ModuleHandle>], deadline: Duration) -> i32 {
+    let mut exit_code = EXIT_OK;
+
+    for replicas in modules {
+        let started = Instant::now();
+
+        for replica in replicas.iter() {
+            logging::log(
+                Level::Info,
+                MODULE_NAME,
+                &format!("Shutting down module '{}'.", replica.module_name()),
+                &[(
+                    "deadline_in_milliseconds",
+                    &deadline.as_millis().to_string(),
+                )],
+            );
+
+            replica.shutdown_signal().request(deadline);
+        }
+
+        for replica in replicas.iter_mut() {
+            let module_name = replica.module_name().to_string();
+
+            let module_exit_code = match replica
+                .shutdown_signal()
+                .wait_finished(deadline.saturating_sub(started.elapsed()))
+            {
+                Some(true) => EXIT_OK,
+                Some(false) => EXIT_MODULE_FAILED,
+                None => EXIT_DEADLINE_EXCEEDED,
+            };
+
+            let elapsed = started.elapsed().as_millis().to_string();
+            match module_exit_code {
+                EXIT_OK => logging::log(
+                    Level::Info,
+                    MODULE_NAME,
+                    &format!("Module '{module_name}' shut down."),
+                    &[("elapsed_in_milliseconds", &elapsed)],
+                ),
+                EXIT_MODULE_FAILED => logging::log(
+                    Level::Error,
+                    MODULE_NAME,
+                    &format!("Module '{module_name}' failed."),
+                    &[],
+                ),
+                _ => logging::log(
+                    Level::Error,
+                    MODULE_NAME,
+                    &format!(
+                        "Module '{module_name}' did not shut down within its deadline, abandoning it."
+                    ),
+                    &[],
+                ),
+            }
+
+            // Threads of modules which missed their deadline are left running, process exit stops them.
+            if module_exit_code != EXIT_DEADLINE_EXCEEDED {
+                replica.join();
+            }
+
+            exit_code = exit_code.max(module_exit_code);
+        }
+    }
+
+    exit_code
+}
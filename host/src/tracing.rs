@@ -0,0 +1,343 @@
+use crate::config::{Configuration, TracingConfiguration};
+use crate::logging::{self, Level};
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const MODULE_NAME: &str = "Wasm Host";
+// Spans ended after this many are waiting for export are dropped.
+const MAX_QUEUED_SPANS: usize = 10000;
+// Only version and flags supported in W3C trace context headers, sampled.
+const TRACEPARENT_VERSION: &str = "00";
+const TRACEPARENT_FLAGS: &str = "01";
+
+static EXPORT_QUEUE: OnceLock<Mutex<Vec<Span>>> = OnceLock::new();
+// Exporter of the process and its http client, set by the first host initialising tracing with an exporter.
+static EXPORTER: OnceLock<(Exporter, reqwest::blocking::Client)> = OnceLock::new();
+
+pub enum Exporter {
+    None,
+    // Each export is appended to the file as one OTLP JSON request per line.
+    File { path: PathBuf },
+    // Each export is posted as an OTLP JSON request to a collector's OTLP/HTTP traces endpoint.
+    Otlp { endpoint: String },
+}
+
+// W3C trace context propagated with messages and outbound http requests.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub span_id: String,
+}
+
+impl TraceContext {
+    // Parses a 'traceparent' value e.g. '00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01'.
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let parts: Vec<&str> = traceparent.trim().split('-').collect();
+
+        match parts[..] {
+            [_version, trace_id, span_id, _flags] if is_id(trace_id, 32) && is_id(span_id, 16) => {
+                Some(Self {
+                    trace_id: trace_id.to_string(),
+                    span_id: span_id.to_string(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!(
+            "{TRACEPARENT_VERSION}-{}-{}-{TRACEPARENT_FLAGS}",
+            self.trace_id, self.span_id
+        )
+    }
+}
+
+// Ids are lowercase hex of a fixed length and must not be all zeroes.
+fn is_id(id: &str, len: usize) -> bool {
+    id.len() == len
+        && id.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+        && id.chars().any(|c| c != '0')
+}
+
+#[derive(Clone, Copy)]
+pub enum SpanKind {
+    Internal,
+    Server,
+    Client,
+    Producer,
+    Consumer,
+}
+
+impl SpanKind {
+    // Span kind values from OTLP protocol definition.
+    fn otlp_value(&self) -> u8 {
+        match self {
+            SpanKind::Internal => 1,
+            SpanKind::Server => 2,
+            SpanKind::Client => 3,
+            SpanKind::Producer => 4,
+            SpanKind::Consumer => 5,
+        }
+    }
+}
+
+pub struct Span {
+    name: String,
+    kind: SpanKind,
+    // Spans are grouped by module, module name is exported as the span's service name.
+    module: String,
+    trace_id: String,
+    span_id: String,
+    parent_span_id: Option<String>,
+    start_time: SystemTime,
+    end_time: Option<SystemTime>,
+    attributes: Vec<(String, String)>,
+    error: Option<String>,
+}
+
+impl Span {
+    // Starts a span as a child of the given context, or as the root of a new trace.
+    pub fn start(name: &str, kind: SpanKind, module: &str, parent: Option<&TraceContext>) -> Self {
+        let mut span = Self {
+            name: name.to_string(),
+            kind,
+            module: module.to_string(),
+            trace_id: new_id::<u128>(),
+            span_id: new_id::<u64>(),
+            parent_span_id: None,
+            start_time: SystemTime::now(),
+            end_time: None,
+            attributes: vec![],
+            error: None,
+        };
+
+        if let Some(parent) = parent {
+            span.set_parent(parent);
+        }
+
+        span
+    }
+
+    // Moves the span into the given trace, used when the parent is only known once the span has started e.g. reads.
+    pub fn set_parent(&mut self, parent: &TraceContext) {
+        self.trace_id = parent.trace_id.to_string();
+        self.parent_span_id = Some(parent.span_id.to_string());
+    }
+
+    pub fn context(&self) -> TraceContext {
+        TraceContext {
+            trace_id: self.trace_id.to_string(),
+            span_id: self.span_id.to_string(),
+        }
+    }
+
+    pub fn set_attribute(&mut self, key: &str, value: &str) {
+        self.attributes.push((key.to_string(), value.to_string()));
+    }
+
+    pub fn set_error(&mut self, message: &str) {
+        self.error = Some(message.to_string());
+    }
+
+    // Ends the span and queues it for export, spans are dropped when no exporter is configured.
+    pub fn end(mut self) {
+        let queue = match EXPORT_QUEUE.get() {
+            Some(queue) => queue,
+            None => return,
+        };
+
+        self.end_time = Some(SystemTime::now());
+
+        let mut queue = queue.lock().unwrap();
+        if queue.len() < MAX_QUEUED_SPANS {
+            queue.push(self);
+        }
+    }
+
+    fn to_otlp(&self) -> serde_json::Value {
+        let mut span = serde_json::json!({
+            "traceId": self.trace_id,
+            "spanId": self.span_id,
+            "name": self.name,
+            "kind": self.kind.otlp_value(),
+            "startTimeUnixNano": unix_nanos(self.start_time),
+            "endTimeUnixNano": unix_nanos(self.end_time.unwrap_or(self.start_time)),
+            "attributes": otlp_attributes(&self.attributes),
+        });
+
+        if let Some(parent_span_id) = &self.parent_span_id {
+            span["parentSpanId"] = parent_span_id.as_str().into();
+        }
+
+        // Status codes from OTLP protocol definition, 2 is error.
+        if let Some(error) = &self.error {
+            span["status"] = serde_json::json!({ "code": 2, "message": error });
+        }
+
+        span
+    }
+}
+
+fn new_id<T>() -> String
+where
+    T: std::fmt::LowerHex + PartialEq + Default,
+    rand::distributions::Standard: rand::distributions::Distribution<T>,
+{
+    loop {
+        let id: T = rand::random();
+        if id != T::default() {
+            return format!("{id:0width$x}", width = std::mem::size_of::<T>() * 2);
+        }
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn otlp_attributes(attributes: &[(String, String)]) -> serde_json::Value {
+    attributes
+        .iter()
+        .map(|(key, value)| serde_json::json!({ "key": key, "value": { "stringValue": value } }))
+        .collect()
+}
+
+// Builds an OTLP JSON export request, with a resource per module.
+fn export_request(spans: &[Span]) -> serde_json::Value {
+    let mut spans_by_module: BTreeMap<&str, Vec<serde_json::Value>> = BTreeMap::new();
+
+    for span in spans {
+        spans_by_module
+            .entry(span.module.as_str())
+            .or_default()
+            .push(span.to_otlp());
+    }
+
+    let resource_spans: Vec<serde_json::Value> = spans_by_module
+        .into_iter()
+        .map(|(module, spans)| {
+            serde_json::json!({
+                "resource": {
+                    "attributes": otlp_attributes(&[("service.name".to_string(), module.to_string())]),
+                },
+                "scopeSpans": [{ "scope": { "name": "wasm-host" }, "spans": spans }],
+            })
+        })
+        .collect();
+
+    serde_json::json!({ "resourceSpans": resource_spans })
+}
+
+fn export(
+    exporter: &Exporter,
+    client: &reqwest::blocking::Client,
+    spans: &[Span],
+) -> Result<(), String> {
+    let request = export_request(spans).to_string();
+
+    match exporter {
+        Exporter::None => Ok(()),
+        Exporter::File { path } => {
+            if let Some(parent) = path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{request}"))
+                .map_err(|e| e.to_string())
+        }
+        Exporter::Otlp { endpoint } => client
+            .post(endpoint)
+            .header("Content-Type", "application/json")
+            .body(request)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+    }
+}
+
+// Starts exporting ended spans on a background thread, spans are not recorded unless this is called with an exporter.
+// Exporter is process wide, hosts embedded in the same process after the first one share its exporter.
+pub fn init(host_config: &Configuration) {
+    let TracingConfiguration {
+        exporter,
+        export_interval,
+    } = host_config.tracing();
+
+    if let Exporter::None = exporter {
+        return;
+    }
+
+    let client = match reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            logging::log(
+                Level::Warn,
+                MODULE_NAME,
+                &format!("Failed to create trace exporter http client, spans are not exported; error = {e}"),
+                &[],
+            );
+            return;
+        }
+    };
+
+    let mut started = false;
+    EXPORTER.get_or_init(|| {
+        started = true;
+        (exporter, client)
+    });
+    if !started {
+        return;
+    }
+
+    EXPORT_QUEUE.get_or_init(Default::default);
+
+    thread::spawn(move || loop {
+        thread::sleep(export_interval);
+        flush();
+    });
+}
+
+// Exports spans ended since the last export, called every export interval and by the host before it exits so spans
+// of its shutdown are not lost.
+pub fn flush() {
+    let (exporter, client) = match EXPORTER.get() {
+        Some(exporter) => exporter,
+        None => return,
+    };
+
+    let spans: Vec<Span> = match EXPORT_QUEUE.get() {
+        Some(queue) => std::mem::take(&mut *queue.lock().unwrap()),
+        None => return,
+    };
+    if spans.is_empty() {
+        return;
+    }
+
+    if let Err(e) = export(exporter, client, &spans) {
+        logging::log(
+            Level::Warn,
+            MODULE_NAME,
+            &format!("Failed to export {} spans; error = {e}", spans.len()),
+            &[],
+        );
+    }
+}
@@ -1,174 +1,947 @@
-pub mod gateway_module;
-pub mod server_module;
-pub mod telemetry_module;
+pub mod capabilities;
+#[cfg(feature = "component")]
+pub mod component;
+pub mod contracts;
+pub mod http_trigger;
+pub mod lifecycle_module;
+pub mod message_trigger;
+pub mod outbound_http;
+pub mod preopens;
+pub mod reload;
+pub mod rollout;
+pub mod rpc;
+pub mod runner;
+pub mod scheduler;
 
 wit_bindgen_wasmtime::export!("../wits/hostobservability.wit");
 
-use anyhow::Result;
-use async_std::{
-    io::{ReadExt, WriteExt},
-    net::TcpStream,
-    task::block_on,
+use crate::broker::{self, Broker};
+use crate::config::{is_topic_allowed, validate_topic, ModuleConfiguration};
+use crate::logging::{self, Level};
+use crate::metrics;
+use crate::shutdown::ShutdownSignal;
+use crate::tracing::{Span, SpanKind, TraceContext};
+use anyhow::{Context as _, Result};
+use capabilities::Capability;
+use outbound_http::{AllowedHost, OutboundHttp};
+use runner::Instance;
+use scheduler::{MissedTickPolicy, Scheduler};
+use std::any::Any;
+use std::io::{self, Read, Write};
+use std::net::{self as stdnet, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wasmtime_wasi::{net, TcpListener};
+use wit_bindgen_wasmtime::wasmtime::{
+    CallHook, Config, Engine, InstanceAllocationStrategy, InstanceLimits, Linker, Module,
+    PoolingAllocationStrategy, ResourceLimiter, Store,
 };
-use chrono::Utc;
-use std::{fs, net as stdnet};
-use wasmtime_wasi::{net, Dir, TcpListener};
-use wit_bindgen_wasmtime::wasmtime::{Config, Engine, Linker, Module, Store};
 
 const PREOPENED_SOCKET_FD: u32 = 4;
+// Component binaries have layer 1 in their preamble, core modules layer 0.
+const COMPONENT_LAYER: [u8; 2] = [0x01, 0x00];
+// Message option used to carry trace context through pubsub server module.
+const TRACEPARENT_OPTION: &str = "@traceparent=";
+// Ends the options the host stamps on a message, so the module's message is never read as options.
+const END_OF_OPTIONS: &str = "@@";
+// Pubsub server module replies 'error <reason>' to failed commands and '<topic> empty' when a topic has no message.
+const ERROR_REPLY: &str = "error ";
+const EMPTY_REPLY: &str = "empty";
 const MODULE_NAME: &str = "Wasm Host";
+// Prefix of metrics the host records about modules, modules cannot record metrics with it.
+const RESERVED_METRIC_PREFIX: &str = "wasm_";
+// Host calls are added to the module's host call counter in batches of this size.
+const HOST_CALLS_BATCH_SIZE: u64 = 100;
+// Fuel given to each module, fuel is only used to measure work done by the module, not to limit it.
+const MODULE_FUEL: u64 = u64::MAX / 2;
+// Linear memory reserved for each pooled instance, in 64KiB wasm pages.
+const POOLED_INSTANCE_MEMORY_PAGES: u64 = 1024;
+
+pub struct Hostobservability {
+    // Name of the module this instance belongs to, host stamps it on logs and published messages.
+    module_name: String,
+    // Replica index stamped on logs, only set for modules running more than one replica.
+    replica: Option<String>,
+    publish_topics: Vec<String>,
+    read_topics: Vec<String>,
+    subscribe_topics: Vec<String>,
+    // Handlers of other modules the module may call.
+    allowed_calls: Vec<String>,
+    // Token used to authenticate connections to pubsub server module.
+    server_auth_token: Option<String>,
+    // Native broker messages are delivered through, None when they go through pubsub server module.
+    broker: Option<Arc<Broker>>,
+    // Trace context of the last message read by the module, later host calls are traced as part of it.
+    trace_context: Option<TraceContext>,
+    // Timers registered by the module, host calls the module's tick export as they fire.
+    scheduler: Scheduler,
+    shutdown: ShutdownSignal,
+    // Error level logs written by the module, counted against it during canary rollout.
+    error_logs: u64,
+}
 
-pub struct Hostobservability;
+impl Hostobservability {
+    pub fn new(
+        module_config: &ModuleConfiguration,
+        environment: &HostEnvironment,
+        shutdown: ShutdownSignal,
+    ) -> Self {
+        Self {
+            module_name: module_config.module_name(),
+            replica: (module_config.replicas() > 1).then(|| module_config.replica().to_string()),
+            publish_topics: module_config.publish_topics(),
+            read_topics: module_config.read_topics(),
+            subscribe_topics: module_config.subscribe_topics(),
+            allowed_calls: module_config.allowed_calls(),
+            server_auth_token: environment.server_auth_token.clone(),
+            broker: environment.broker.clone(),
+            trace_context: None,
+            scheduler: Scheduler::default(),
+            shutdown,
+            error_logs: 0,
+        }
+    }
 
-impl hostobservability::Hostobservability for Hostobservability {
-    fn loginfo(&mut self, _modulename: &str, _message: &str) {
-        #[cfg(debug_assertions)]
-        println!(
-            "{:#?} Module: {}, Message: {}",
-            Utc::now(),
-            _modulename,
-            _message
-        );
+    // Starts a span for a host function call, as part of the module's current trace if there is one.
+    fn span(&self, name: &str, kind: SpanKind) -> Span {
+        Span::start(name, kind, &self.module_name, self.trace_context.as_ref())
     }
 
-    fn publish(&mut self, topic: &str, message: &str) {
-        // Publish message to pubsub server module via socket connection.
-        // TODO: Reuse connection and move this to separate module.
-        block_on(async {
-            let mut stream = TcpStream::connect("127.0.0.1:8080").await.unwrap();
-            let payload = format!("{topic} {message}");
-            stream.write_all(payload.as_bytes()).await.unwrap();
-
-            self.loginfo(MODULE_NAME, &format!("Publishing message '{payload}' to topic '{topic}' on the messaging layer via host func."));
-        });
-    }
-
-    fn read(&mut self, topic: &str) -> String {
-        // Read message from pubsub server module via socket connection.
-        // TODO: Reuse connection and move this to separate module.
-        block_on(async {
-            let mut stream = TcpStream::connect("127.0.0.1:8080").await.unwrap();
-            let cmd_payload = format!("read {topic}");
-
-            self.loginfo(
-                MODULE_NAME,
-                &format!("Sending cmd '{cmd_payload}' on the messaging layer via host func."),
-            );
+    // Publishes a message with options set by the module ('@ttl=<ms> @delay=<ms> ' or empty).
+    fn publish_message(&mut self, topic: &str, module_options: &str, message: &str) {
+        // Publishing starts a new trace unless the module is handling a message it read as part of one.
+        let mut span = self.span(&format!("{topic} publish"), SpanKind::Producer);
+        span.set_attribute("messaging.destination.name", topic);
+
+        if let Err(reason) = self.check_topic(&self.publish_topics, "publish to", topic) {
+            span.set_error(&reason);
+            span.end();
+            return;
+        }
+
+        let traceparent = span.context().traceparent();
+
+        // Source and trace context are stamped first, the broker ignores any later ones set by the module.
+        let options = format!(
+            "@source={} {TRACEPARENT_OPTION}{traceparent} {module_options}{END_OF_OPTIONS}",
+            self.module_name
+        );
+        let payload = format!("{topic} {options} {message}");
+
+        if let Some(broker) = &self.broker {
+            broker.publish(topic, &format!("{options} {message}"));
+        } else {
+            // Publish message to pubsub server module via socket connection.
+            // TODO: Reuse connection and move this to separate module.
+            if let Err(e) = self
+                .connect()
+                .and_then(|mut stream| stream.write_all(payload.as_bytes()))
+            {
+                logging::log(
+                    Level::Error,
+                    MODULE_NAME,
+                    &format!("Failed to publish message to topic '{topic}' on pubsub module; error = {e}"),
+                    &[("module", &self.module_name), ("topic", topic)],
+                );
+                span.set_error(&e.to_string());
+                span.end();
+                return;
+            }
+        }
 
-            stream.write_all(cmd_payload.as_bytes()).await.unwrap();
-            stream.flush().await.unwrap();
+        logging::log(
+            Level::Debug,
+            MODULE_NAME,
+            &format!("Publishing message '{payload}' to topic '{topic}' on the messaging layer via host func."),
+            &[("module", &self.module_name), ("topic", topic)],
+        );
 
-            let mut response_buf = String::from("");
+        span.end();
+    }
 
-            loop {
-                let mut buf = vec![0; 1000];
+    // Checks a topic passed to a host function is valid and allowed, logging denied calls against the calling module.
+    fn check_topic(
+        &self,
+        allowed_topics: &[String],
+        operation: &str,
+        topic: &str,
+    ) -> Result<(), String> {
+        let reason = match validate_topic(topic) {
+            Err(reason) => reason,
+            Ok(()) if !is_topic_allowed(allowed_topics, topic) => {
+                format!("not authorised to {operation} topic '{topic}'")
+            }
+            Ok(()) => return Ok(()),
+        };
+
+        logging::log(
+            Level::Warn,
+            MODULE_NAME,
+            &format!("Denied module '{}': {reason}.", self.module_name),
+            &[("module", &self.module_name), ("topic", topic)],
+        );
 
-                let n = stream.read(&mut buf).await.unwrap();
+        Err(reason)
+    }
 
-                buf.truncate(n); // truncate any additional bytes from buffer vector.
+    // Returns whether a metric recorded by the module may be recorded, metrics named like the host's are dropped so
+    // a module cannot skew the host's metrics of it.
+    fn is_module_metric(&self, name: &str) -> bool {
+        if !name.starts_with(RESERVED_METRIC_PREFIX) {
+            return true;
+        }
+
+        logging::log(
+            Level::Warn,
+            MODULE_NAME,
+            &format!(
+                "Dropped metric '{name}' recorded by module '{}', prefix '{RESERVED_METRIC_PREFIX}' is reserved for host metrics.",
+                self.module_name
+            ),
+            &[("module", &self.module_name), ("metric", name)],
+        );
 
-                let buf_str = std::str::from_utf8(&buf).unwrap().to_string();
-                response_buf = buf_str.clone();
+        false
+    }
 
-                self.loginfo(
+    // Receives the next message of a topic for 'read', or of the module's subscription to it for 'subscribe'. Trace
+    // context is stripped from the message and the module's later host calls continue the message's trace.
+    fn receive(&mut self, topic: &str, subscribe: bool) -> Result<Option<String>, String> {
+        // Span is moved into the message's trace once the message has been read.
+        let mut span = Span::start(
+            &format!("{topic} receive"),
+            SpanKind::Consumer,
+            &self.module_name,
+            None,
+        );
+        span.set_attribute("messaging.destination.name", topic);
+
+        let (allowed_topics, operation) = match subscribe {
+            true => (&self.subscribe_topics, "subscribe to"),
+            false => (&self.read_topics, "read"),
+        };
+        if let Err(reason) = self.check_topic(allowed_topics, operation, topic) {
+            span.set_error(&reason);
+            span.end();
+            return Err(reason);
+        }
+
+        // Server module has no subscriptions, only the native broker fans messages out to every subscriber.
+        let payload = match (&self.broker, subscribe) {
+            (Some(broker), false) => Ok(broker.read(topic)),
+            (Some(broker), true) => Ok(broker.subscribe(&self.module_name, topic)),
+            (None, false) => self.read_from_server(topic),
+            (None, true) => Err("subscribe requires the native pubsub backend".to_string()),
+        };
+        let payload = match payload {
+            Ok(payload) => payload,
+            Err(reason) => {
+                logging::log(
+                    Level::Error,
                     MODULE_NAME,
-                    &format!("Received response from pubsub module: '{buf_str}'."),
+                    &format!("Failed to {operation} topic '{topic}'; error = {reason}"),
+                    &[("module", &self.module_name), ("topic", topic)],
                 );
+                span.set_error(&reason);
+                span.end();
+                return Err(reason);
+            }
+        };
 
-                // If response is received (i.e. any bytes) on socket stream, exit the loop.
-                if n > 0 {
-                    self.loginfo(
-                        MODULE_NAME,
-                        "Response stream dropped, no more data to read.",
-                    );
-                    break;
-                }
+        logging::log(
+            Level::Debug,
+            MODULE_NAME,
+            &format!("Received message on topic '{topic}': '{payload:?}'."),
+            &[],
+        );
+
+        // Options are stripped from the message and module's later host calls continue the message's trace.
+        let (trace_context, message) = match payload {
+            Some(payload) => {
+                let (trace_context, message) = split_options(&payload);
+                (trace_context, Some(message))
             }
+            None => (None, None),
+        };
+
+        if let Some(trace_context) = &trace_context {
+            span.set_parent(trace_context);
+        }
+        self.trace_context = trace_context.map(|_| span.context());
+        span.end();
 
-            response_buf
-        })
+        Ok(message)
     }
 
-    fn subscribe(&mut self, _topic: &str) -> String {
-        // TODO: Implement subscribe feature in pubsub before implementing code here.
-        todo!()
+    // Connects to pubsub server module, authenticating the connection when a token is configured.
+    // Host calls run on the module's own thread, so blocking on the socket only blocks the calling module.
+    fn connect(&mut self) -> io::Result<TcpStream> {
+        broker::connect(self.server_auth_token.as_deref())
+    }
+
+    // Reads message from pubsub server module via socket connection, None when the topic has no message.
+    // TODO: Reuse connection and move this to separate module.
+    fn read_from_server(&mut self, topic: &str) -> Result<Option<String>, String> {
+        let cmd_payload = format!("read {topic}");
+
+        logging::log(
+            Level::Debug,
+            MODULE_NAME,
+            &format!("Sending cmd '{cmd_payload}' on the messaging layer via host func."),
+            &[("module", &self.module_name), ("topic", topic)],
+        );
+
+        // Blocks until the whole reply is received.
+        let reply = broker::request(self.server_auth_token.as_deref(), &cmd_payload)
+            .map_err(|e| e.to_string())?;
+
+        // Reply is '<topic> <message>', '<topic> empty' or 'error <reason>'.
+        if let Some(reason) = reply.strip_prefix(ERROR_REPLY) {
+            return Err(reason.to_string());
+        }
+        match reply.split_once(' ') {
+            Some((_, EMPTY_REPLY)) => Ok(None),
+            Some((_, payload)) => Ok(Some(payload.to_string())),
+            None => Err(format!("unexpected reply '{reply}' from pubsub module")),
+        }
     }
 }
 
-fn default_wasi(open_socket: bool, socket_address: Option<String>) -> wasmtime_wasi::WasiCtx {
-    // Add a directory for access from wasm as root directory.
-    let dir: Dir = Dir::from_std_file(fs::File::open("../").expect("Could not open path"));
+impl hostobservability::Hostobservability for Hostobservability {
+    fn loginfo(&mut self, _modulename: &str, message: &str) {
+        // Self reported module name is ignored, host knows which module the call came from.
+        let mut fields = vec![];
+        if let Some(replica) = &self.replica {
+            fields.push(("replica", replica.as_str()));
+        }
+        logging::log(Level::Info, &self.module_name, message, &fields);
+    }
+
+    fn log(
+        &mut self,
+        level: hostobservability::Loglevel,
+        message: &str,
+        fields: Vec<(&str, &str)>,
+    ) {
+        // Logs written within a trace carry its trace id so they can be correlated with its spans.
+        let trace_id = self
+            .trace_context
+            .as_ref()
+            .map(|context| context.trace_id.clone());
+        let mut fields = fields;
+        if let Some(trace_id) = &trace_id {
+            fields.push(("trace_id", trace_id));
+        }
+        if let Some(replica) = &self.replica {
+            fields.push(("replica", replica));
+        }
+
+        if let hostobservability::Loglevel::Error = level {
+            self.error_logs += 1;
+        }
+
+        logging::log(level.into(), &self.module_name, message, &fields);
+    }
+
+    fn counterincrement(&mut self, name: &str, value: u64, labels: Vec<(&str, &str)>) {
+        let span = self.span("hostobservability.counterincrement", SpanKind::Internal);
+        if self.is_module_metric(name) {
+            metrics::counter_increment(&self.module_name, name, value as f64, &labels);
+        }
+        span.end();
+    }
+
+    fn gaugeset(&mut self, name: &str, value: f64, labels: Vec<(&str, &str)>) {
+        let span = self.span("hostobservability.gaugeset", SpanKind::Internal);
+        if self.is_module_metric(name) {
+            metrics::gauge_set(&self.module_name, name, value, &labels);
+        }
+        span.end();
+    }
+
+    fn histogramrecord(&mut self, name: &str, value: f64, labels: Vec<(&str, &str)>) {
+        let span = self.span("hostobservability.histogramrecord", SpanKind::Internal);
+        if self.is_module_metric(name) {
+            metrics::histogram_record(&self.module_name, name, value, &labels);
+        }
+        span.end();
+    }
 
-    if open_socket {
-        let stdnet_tcp_listener = stdnet::TcpListener::bind(socket_address.unwrap()).unwrap();
+    fn scheduleinterval(
+        &mut self,
+        intervalinmilliseconds: u64,
+        jitterinmilliseconds: u64,
+        missedtickpolicy: hostobservability::Missedtickpolicy,
+    ) -> u32 {
+        let span = self.span("hostobservability.scheduleinterval", SpanKind::Internal);
+
+        let timer_id = self.scheduler.schedule_interval(
+            Duration::from_millis(intervalinmilliseconds),
+            Duration::from_millis(jitterinmilliseconds),
+            missedtickpolicy.into(),
+        );
+
+        span.end();
+        timer_id
+    }
+
+    fn schedulecron(
+        &mut self,
+        expression: &str,
+        jitterinmilliseconds: u64,
+        missedtickpolicy: hostobservability::Missedtickpolicy,
+    ) -> Result<u32, String> {
+        let mut span = self.span("hostobservability.schedulecron", SpanKind::Internal);
+
+        let timer_id = self.scheduler.schedule_cron(
+            expression,
+            Duration::from_millis(jitterinmilliseconds),
+            missedtickpolicy.into(),
+        );
+
+        if let Err(e) = &timer_id {
+            span.set_error(e);
+        }
+        span.end();
+        timer_id
+    }
+
+    fn canceltimer(&mut self, timerid: u32) {
+        let span = self.span("hostobservability.canceltimer", SpanKind::Internal);
+        self.scheduler.cancel(timerid);
+        span.end();
+    }
+
+    fn shutdownrequested(&mut self) -> bool {
+        // Not traced, modules poll this.
+        self.shutdown.is_requested()
+    }
+
+    fn traceparent(&mut self) -> Option<String> {
+        // Not traced, it only returns the module's current trace context.
+        self.trace_context
+            .as_ref()
+            .map(|trace_context| trace_context.traceparent())
+    }
+
+    fn publish(&mut self, topic: &str, message: &str) {
+        self.publish_message(topic, "", message);
+    }
+
+    fn publishwithoptions(
+        &mut self,
+        topic: &str,
+        message: &str,
+        ttlinmilliseconds: Option<u64>,
+        delayinmilliseconds: Option<u64>,
+    ) {
+        // Options are sent as '@name=value' prefixes to the message, the broker strips them on receipt.
+        let mut options = String::from("");
+
+        if let Some(ttl) = ttlinmilliseconds {
+            options.push_str(&format!("@ttl={ttl} "));
+        }
+
+        if let Some(delay) = delayinmilliseconds {
+            options.push_str(&format!("@delay={delay} "));
+        }
+
+        self.publish_message(topic, &options, message);
+    }
+
+    fn read(&mut self, topic: &str) -> Result<Option<String>, String> {
+        self.receive(topic, false)
+    }
+
+    fn subscribe(&mut self, topic: &str) -> Result<Option<String>, String> {
+        self.receive(topic, true)
+    }
+
+    fn callhandler(
+        &mut self,
+        modulename: &str,
+        handler: &str,
+        payload: &str,
+        timeoutinmilliseconds: u64,
+    ) -> Result<String, String> {
+        // Handler is traced as part of the caller's trace, which continues the module's current one if there is one.
+        let mut span = self.span(&format!("{modulename}.{handler} call"), SpanKind::Client);
+        span.set_attribute("rpc.service", modulename);
+        span.set_attribute("rpc.method", handler);
+
+        let result = rpc::call(
+            self,
+            modulename,
+            handler,
+            payload,
+            timeoutinmilliseconds,
+            span.context(),
+        );
+        if let Err(e) = &result {
+            span.set_error(e);
+        }
+
+        span.end();
+        result
+    }
+}
+
+pub fn default_wasi(
+    module_config: &ModuleConfiguration,
+    socket_address: Option<String>,
+) -> Result<wasmtime_wasi::WasiCtx> {
+    wasi(
+        module_config,
+        module_dirs(module_config)?.as_ref(),
+        socket_address,
+    )
+}
+
+// Opens the module's directories, None for modules without filesystem capability. Modules instantiated per message,
+// request or call open them once and pass them to instance_wasi.
+pub fn module_dirs(module_config: &ModuleConfiguration) -> Result<Option<preopens::ModuleDirs>> {
+    if !module_config
+        .capabilities()?
+        .contains(&Capability::Filesystem)
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(preopens::ModuleDirs::open(
+        &module_config.preopens()?,
+        &module_config.scratch_directory(),
+    )?))
+}
+
+pub fn instance_wasi(
+    module_config: &ModuleConfiguration,
+    dirs: Option<&preopens::ModuleDirs>,
+) -> Result<wasmtime_wasi::WasiCtx> {
+    wasi(module_config, dirs, None)
+}
+
+fn wasi(
+    module_config: &ModuleConfiguration,
+    dirs: Option<&preopens::ModuleDirs>,
+    socket_address: Option<String>,
+) -> Result<wasmtime_wasi::WasiCtx> {
+    let capabilities = module_config.capabilities()?;
+    let mut wasi_ctx_builder = wasmtime_wasi::sync::WasiCtxBuilder::new().inherit_stdio();
+
+    if let (true, Some(socket_address)) =
+        (capabilities.contains(&Capability::Sockets), socket_address)
+    {
+        let stdnet_tcp_listener = stdnet::TcpListener::bind(socket_address)?;
         let wasi_tcp_listener = TcpListener::from_std(stdnet_tcp_listener);
 
         let wasi_socket = net::Socket::TcpListener(wasi_tcp_listener);
 
-        wasmtime_wasi::sync::WasiCtxBuilder::new()
-            .inherit_stdio()
-            .preopened_dir(dir, ".")
-            .expect("Could not preopen path")
-            .preopened_socket(PREOPENED_SOCKET_FD, wasi_socket)
-            .expect("Failed to open listener")
-            .build()
-    } else {
-        wasmtime_wasi::sync::WasiCtxBuilder::new()
-            .inherit_stdio()
-            .preopened_dir(dir, ".")
-            .expect("Could not preopen path")
-            .build()
+        wasi_ctx_builder = wasi_ctx_builder.preopened_socket(PREOPENED_SOCKET_FD, wasi_socket)?;
+    }
+
+    let mut wasi_ctx = wasi_ctx_builder.build();
+
+    // Only directories declared for the module and its own scratch directory are accessible.
+    if let Some(dirs) = dirs {
+        dirs.preopen(&mut wasi_ctx)?;
+    }
+
+    Ok(wasi_ctx)
+}
+
+impl From<hostobservability::Loglevel> for Level {
+    fn from(level: hostobservability::Loglevel) -> Self {
+        match level {
+            hostobservability::Loglevel::Trace => Level::Trace,
+            hostobservability::Loglevel::Debug => Level::Debug,
+            hostobservability::Loglevel::Info => Level::Info,
+            hostobservability::Loglevel::Warn => Level::Warn,
+            hostobservability::Loglevel::Error => Level::Error,
+        }
+    }
+}
+
+impl From<hostobservability::Missedtickpolicy> for MissedTickPolicy {
+    fn from(policy: hostobservability::Missedtickpolicy) -> Self {
+        match policy {
+            hostobservability::Missedtickpolicy::Skip => MissedTickPolicy::Skip,
+            hostobservability::Missedtickpolicy::Burst => MissedTickPolicy::Burst,
+            hostobservability::Missedtickpolicy::Delay => MissedTickPolicy::Delay,
+        }
+    }
+}
+
+// Runtime metrics collected by the host for a module instance.
+pub struct RuntimeMetrics {
+    module_name: String,
+    // Per instance gauges are labelled with the replica they were sampled from.
+    replica: String,
+    // Host calls not yet added to the module's host call counter.
+    host_calls: u64,
+}
+
+impl RuntimeMetrics {
+    fn new(module_config: &ModuleConfiguration) -> Self {
+        Self {
+            module_name: module_config.module_name(),
+            replica: module_config.replica().to_string(),
+            host_calls: 0,
+        }
+    }
+
+    fn host_call(&mut self) {
+        self.host_calls += 1;
+
+        if self.host_calls >= HOST_CALLS_BATCH_SIZE {
+            self.flush_host_calls();
+        }
+    }
+
+    // Adds host calls not counted yet to the module's host call counter.
+    fn flush_host_calls(&mut self) {
+        if self.host_calls > 0 {
+            metrics::counter_increment(
+                &self.module_name,
+                "wasm_module_host_calls_total",
+                self.host_calls as f64,
+                &[],
+            );
+            self.host_calls = 0;
+        }
+    }
+}
+
+// Instances handling a single message or request make fewer host calls than a batch, they are counted when dropped.
+impl Drop for RuntimeMetrics {
+    fn drop(&mut self) {
+        self.flush_host_calls();
+    }
+}
+
+impl ResourceLimiter for RuntimeMetrics {
+    fn memory_growing(&mut self, _current: usize, desired: usize, _maximum: Option<usize>) -> bool {
+        metrics::gauge_set(
+            &self.module_name,
+            "wasm_module_memory_bytes",
+            desired as f64,
+            &[("replica", &self.replica)],
+        );
+
+        true
+    }
+
+    fn table_growing(&mut self, _current: u32, _desired: u32, _maximum: Option<u32>) -> bool {
+        true
+    }
+}
+
+// Records fuel consumed by the module and its host calls, sampled whenever control returns to the host from a module
+// export.
+pub fn record_runtime_metrics(store: &mut Store<Context>) {
+    store.data_mut().runtime_metrics.flush_host_calls();
+
+    if let Some(fuel_consumed) = store.fuel_consumed() {
+        let runtime_metrics = &store.data().runtime_metrics;
+        metrics::gauge_set(
+            &runtime_metrics.module_name,
+            "wasm_module_fuel_consumed",
+            fuel_consumed as f64,
+            &[("replica", &runtime_metrics.replica)],
+        );
+    }
+}
+
+// Calls the module's tick export as its timers fire, returns once shutdown is requested or 'until' has passed.
+pub fn run_timers(
+    instance: &mut dyn Instance,
+    shutdown: &ShutdownSignal,
+    until: Instant,
+    mut on_tick: impl FnMut(&mut dyn Instance, u32) -> Result<()>,
+) -> Result<()> {
+    loop {
+        let next_due = instance
+            .scheduler()
+            .next_due()
+            .filter(|(_, due_at)| *due_at <= until);
+        let wake_at = next_due.map_or(until, |(_, due_at)| due_at);
+
+        if shutdown.wait_requested(wake_at.saturating_duration_since(Instant::now())) {
+            return Ok(());
+        }
+
+        let (timer_id, _) = match next_due {
+            Some(next_due) => next_due,
+            None => return Ok(()),
+        };
+
+        on_tick(instance, timer_id)?;
+        instance.record_runtime_metrics();
+
+        instance.scheduler().ticked(timer_id, Instant::now());
     }
 }
 
-pub struct Context<E> {
+// Custom host functions an embedding service links into modules. Modules import them from the import module
+// named by 'name' and are only linked with them when granted capability 'custom:<name>' in host configuration.
+pub trait HostFunctions: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn add_to_linker(&self, linker: &mut Linker<Context>) -> Result<()>;
+
+    // Returns state for a new instance of a module, linked functions reach it through 'Context::host_state'.
+    fn instance_state(&self, module_config: &ModuleConfiguration) -> Box<dyn Any + Send>;
+}
+
+// Settings shared by every module the host runs.
+#[derive(Clone, Default)]
+pub struct HostEnvironment {
+    // Token used by host functions to authenticate with pubsub server module.
+    pub server_auth_token: Option<String>,
+    // Native broker host functions deliver messages through, None when they use pubsub server module.
+    pub broker: Option<Arc<Broker>>,
+    // Custom host functions registered by the embedding service.
+    pub host_functions: Vec<Arc<dyn HostFunctions>>,
+}
+
+// Store data of a module instance.
+pub struct Context {
     wasi: wasmtime_wasi::WasiCtx,
+    outbound_http: OutboundHttp,
+    runtime_metrics: RuntimeMetrics,
     pub runtime_data: Option<Hostobservability>,
-    pub exports: E,
+    // Data of the bindings generated for the module's exports.
+    exports: Box<dyn Any + Send>,
+    // State of custom host functions linked into the module.
+    host_states: Vec<Box<dyn Any + Send>>,
+}
+
+impl Context {
+    pub fn exports_mut<E: 'static>(&mut self) -> &mut E {
+        self.exports
+            .downcast_mut()
+            .expect("Module exports are of a different type.")
+    }
+
+    pub fn host_state<S: 'static>(&mut self) -> &mut S {
+        self.host_states
+            .iter_mut()
+            .find_map(|state| state.downcast_mut())
+            .expect("Host functions state is not registered for the module.")
+    }
 }
 
-pub fn instantiate<E: Default, T>(
+pub fn instantiate<E: Default + Send + 'static, T>(
     wasm_path: &str,
-    mk_exports: impl FnOnce(&mut Store<Context<E>>, &Module, &mut Linker<Context<E>>) -> Result<T>,
-    wasi_ctx: impl FnOnce() -> wasmtime_wasi::WasiCtx,
+    mk_exports: impl FnOnce(&mut Store<Context>, &Module, &mut Linker<Context>) -> Result<T>,
+    wasi_ctx: impl FnOnce(&ModuleConfiguration) -> Result<wasmtime_wasi::WasiCtx>,
+    module_config: &ModuleConfiguration,
     allowed_host: Option<String>,
-) -> Result<(T, Store<Context<E>>)> {
-    let config = Config::new();
+    host_observability: Hostobservability,
+    host_functions: &[Arc<dyn HostFunctions>],
+) -> Result<(T, Store<Context>)> {
+    let instantiation_started = Instant::now();
+
+    let mut runtime = prepare(wasm_path, module_config, Config::new(), host_functions)?;
+    let mut store = new_store(
+        &runtime.engine,
+        wasi_ctx(module_config)?,
+        module_config,
+        allowed_host,
+        host_observability,
+        Box::new(E::default()),
+        host_functions,
+    )?;
+
+    let exports = mk_exports(&mut store, &runtime.module, &mut runtime.linker)?;
+
+    metrics::gauge_set(
+        &module_config.module_name(),
+        "wasm_module_instantiation_seconds",
+        instantiation_started.elapsed().as_secs_f64(),
+        &[("replica", &module_config.replica().to_string())],
+    );
+
+    Ok((exports, store))
+}
+
+// Compiled module and a linker with the host functions it was granted, shared by all instances of the module.
+pub struct Runtime {
+    pub engine: Engine,
+    pub module: Module,
+    pub linker: Linker<Context>,
+}
+
+// Returns whether the wasm binary is a component rather than a core module, from its preamble.
+pub fn is_component(wasm_path: &str) -> Result<bool> {
+    let mut preamble = [0; 8];
+    std::fs::File::open(wasm_path)
+        .and_then(|mut file| file.read_exact(&mut preamble))
+        .with_context(|| format!("Could not read wasm binary '{wasm_path}'"))?;
+
+    Ok(preamble[..4] == *b"\0asm" && preamble[6..] == COMPONENT_LAYER)
+}
+
+// Compiles the module with the given engine config, fuel metering is always enabled.
+pub fn prepare(
+    wasm_path: &str,
+    module_config: &ModuleConfiguration,
+    mut config: Config,
+    host_functions: &[Arc<dyn HostFunctions>],
+) -> Result<Runtime> {
+    config.consume_fuel(true);
     let engine = Engine::new(&config)?;
     let module = Module::from_file(&engine, wasm_path)?;
 
+    // Refuse to instantiate modules importing host functions they were not granted or the host does not know.
+    let capabilities = module_config.capabilities()?;
+    let custom_modules: Vec<&str> = host_functions
+        .iter()
+        .map(|host_functions| host_functions.name())
+        .collect();
+    capabilities::check_imports(
+        &module_config.module_name(),
+        &module,
+        &capabilities,
+        &custom_modules,
+    )?;
+
+    // Refuse modules built against WIT contracts the host does not provide, before linking reports it opaquely.
+    contracts::check_core(&module_config.module_name(), wasm_path, &module)?;
+
     let mut linker = Linker::new(&engine);
 
-    wasmtime_wasi::add_to_linker(&mut linker, |cx: &mut Context<E>| &mut cx.wasi)?;
+    wasmtime_wasi::add_to_linker(&mut linker, |cx: &mut Context| &mut cx.wasi)?;
+
+    // Add wasm host functions to linker, allowing them to be used in wasm modules.
+    // Functions of capabilities the module was not granted are shadowed below.
+    hostobservability::add_to_linker(&mut linker, |ctx| -> &mut Hostobservability {
+        ctx.runtime_data.as_mut().unwrap()
+    })?;
 
     // Only allow http outbound when requested, not all wasm modules should access it.
-    let http = match allowed_host {
-        None => wasi_experimental_http_wasmtime::HttpCtx {
-            allowed_hosts: None,
-            max_concurrent_requests: Some(42),
-        },
-        Some(allowed_host) => wasi_experimental_http_wasmtime::HttpCtx {
-            allowed_hosts: Some(vec![allowed_host]),
-            max_concurrent_requests: Some(42),
+    if capabilities.contains(&Capability::Http) {
+        outbound_http::add_to_linker(&mut linker, |cx: &mut Context| &mut cx.outbound_http)?;
+    }
+
+    for host_functions in granted(host_functions, &capabilities) {
+        host_functions.add_to_linker(&mut linker)?;
+    }
+
+    // Store is only used to type the linked functions, it never instantiates the module.
+    let mut store = new_store(
+        &engine,
+        wasmtime_wasi::sync::WasiCtxBuilder::new().build(),
+        module_config,
+        None,
+        Hostobservability::new(
+            module_config,
+            &HostEnvironment::default(),
+            ShutdownSignal::default(),
+        ),
+        Box::new(()),
+        &[],
+    )?;
+    capabilities::shadow_ungranted(&mut linker, &mut store, &capabilities, &custom_modules)?;
+
+    Ok(Runtime {
+        engine,
+        module,
+        linker,
+    })
+}
+
+// Returns engine config allocating instances from a pool of pre-allocated instance slots, for modules which are
+// instantiated per message or request.
+pub fn pooled_config(instances: u32) -> Config {
+    let mut config = Config::new();
+    config.allocation_strategy(InstanceAllocationStrategy::Pooling {
+        strategy: PoolingAllocationStrategy::default(),
+        instance_limits: InstanceLimits {
+            count: instances,
+            memory_pages: POOLED_INSTANCE_MEMORY_PAGES,
+            ..Default::default()
         },
-    };
+    });
 
-    // let http = wasi_experimental_http_wasmtime::HttpCtx { allowed_hosts: Some(vec![allowed_host]),max_concurrent_requests: Some(42) };
+    config
+}
 
-    wasi_experimental_http_wasmtime::HttpState::new()
-        .expect("HttpState::new failed")
-        .add_to_linker(&mut linker, move |_ctx| http.clone())?;
+// Splits the options a message is read with from its value, keeping its trace context. Options are ended with '@@',
+// messages read without options are the value alone.
+fn split_options(payload: &str) -> (Option<TraceContext>, String) {
+    let mut traceparent = None;
+    let mut rest = payload;
+
+    loop {
+        let (option, remaining) = rest.split_once(' ').unwrap_or((rest, ""));
+        if option == END_OF_OPTIONS {
+            return (
+                traceparent.and_then(TraceContext::parse),
+                remaining.to_string(),
+            );
+        }
+        if !option.starts_with('@') || !option.contains('=') {
+            break;
+        }
+        if let Some(value) = option.strip_prefix(TRACEPARENT_OPTION) {
+            traceparent.get_or_insert(value);
+        }
+        rest = remaining;
+    }
+
+    (None, payload.to_string())
+}
+
+// Creates a store for one instance of the module, with its own WASI context, fuel and host state.
+pub fn new_store(
+    engine: &Engine,
+    wasi_ctx: wasmtime_wasi::WasiCtx,
+    module_config: &ModuleConfiguration,
+    allowed_host: Option<String>,
+    host_observability: Hostobservability,
+    exports: Box<dyn Any + Send>,
+    host_functions: &[Arc<dyn HostFunctions>],
+) -> Result<Store<Context>> {
+    // Host allowed via cmdline is added to the module's configured http policy.
+    let mut http_policy = module_config.http_policy()?;
+    if let Some(allowed_host) = allowed_host {
+        http_policy
+            .allowed_hosts
+            .push(AllowedHost::parse(&allowed_host)?);
+    }
 
     let mut store = Store::new(
-        &engine,
+        engine,
         Context {
-            wasi: wasi_ctx(),
-            runtime_data: Some(Hostobservability {}),
-            exports: E::default(),
+            wasi: wasi_ctx,
+            outbound_http: OutboundHttp::new(&module_config.module_name(), http_policy),
+            runtime_metrics: RuntimeMetrics::new(module_config),
+            runtime_data: Some(host_observability),
+            exports,
+            host_states: granted(host_functions, &module_config.capabilities()?)
+                .map(|host_functions| host_functions.instance_state(module_config))
+                .collect(),
         },
     );
 
-    let exports = mk_exports(&mut store, &module, &mut linker)?;
+    store.add_fuel(MODULE_FUEL)?;
+    store.limiter(|cx| &mut cx.runtime_metrics);
+    store.call_hook(|cx, call_hook| {
+        if let CallHook::CallingHost = call_hook {
+            cx.runtime_metrics.host_call();
+        }
+        Ok(())
+    });
 
-    Ok((exports, store))
+    Ok(store)
+}
+
+// Returns custom host functions the module was granted.
+fn granted<'a>(
+    host_functions: &'a [Arc<dyn HostFunctions>],
+    capabilities: &'a [Capability],
+) -> impl Iterator<Item = &'a Arc<dyn HostFunctions>> {
+    host_functions.iter().filter(|host_functions| {
+        capabilities.contains(&Capability::Custom(host_functions.name().to_string()))
+    })
 }
@@ -0,0 +1,171 @@
+use anyhow::{bail, Result};
+use std::{fmt, str::FromStr};
+use wit_bindgen_wasmtime::wasmtime::{AsContextMut, Extern, Linker, Module, Trap};
+
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+const HTTP_MODULE: &str = "wasi_experimental_http";
+const HOST_OBSERVABILITY_MODULE: &str = "hostobservability";
+// Prefix of capabilities granting custom host functions, followed by their import module name.
+const CUSTOM_PREFIX: &str = "custom:";
+
+// Host functions and resources a module can be granted in host configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Capability {
+    Filesystem,
+    Sockets,
+    Http,
+    Pubsub,
+    Logging,
+    Metrics,
+    Timers,
+    // Calling handlers other modules serve, as permitted by the module's 'allowed_calls'.
+    Rpc,
+    // Custom host functions registered by a service embedding the host, named by their import module.
+    Custom(String),
+}
+
+impl FromStr for Capability {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "filesystem" => Ok(Capability::Filesystem),
+            "sockets" => Ok(Capability::Sockets),
+            "http" => Ok(Capability::Http),
+            "pubsub" => Ok(Capability::Pubsub),
+            "logging" => Ok(Capability::Logging),
+            "metrics" => Ok(Capability::Metrics),
+            "timers" => Ok(Capability::Timers),
+            "rpc" => Ok(Capability::Rpc),
+            _ => match name.strip_prefix(CUSTOM_PREFIX) {
+                Some(import_module) => Ok(Capability::Custom(import_module.to_string())),
+                None => bail!("Unknown capability '{name}'."),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Capability::Filesystem => "filesystem",
+            Capability::Sockets => "sockets",
+            Capability::Http => "http",
+            Capability::Pubsub => "pubsub",
+            Capability::Logging => "logging",
+            Capability::Metrics => "metrics",
+            Capability::Timers => "timers",
+            Capability::Rpc => "rpc",
+            Capability::Custom(name) => return write!(f, "{CUSTOM_PREFIX}{name}"),
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+// Returns capability required for an import, None for base WASI functions (stdio, clocks, random etc.) every module gets.
+// Other import modules are only known when they are custom host functions registered with the host.
+fn required_capability(
+    import_module: &str,
+    import_name: &str,
+    custom_modules: &[&str],
+) -> Result<Option<Capability>> {
+    let capability = match (import_module, import_name) {
+        (WASI_MODULE, name) if name.starts_with("path_") || name == "fd_readdir" => {
+            Some(Capability::Filesystem)
+        }
+        (WASI_MODULE, name) if name.starts_with("sock_") => Some(Capability::Sockets),
+        (WASI_MODULE, _) => None,
+        (HTTP_MODULE, _) => Some(Capability::Http),
+        (HOST_OBSERVABILITY_MODULE, "shutdownrequested") => None,
+        (HOST_OBSERVABILITY_MODULE, "loginfo" | "log") => Some(Capability::Logging),
+        (HOST_OBSERVABILITY_MODULE, "counterincrement" | "gaugeset" | "histogramrecord") => {
+            Some(Capability::Metrics)
+        }
+        (HOST_OBSERVABILITY_MODULE, "scheduleinterval" | "schedulecron" | "canceltimer") => {
+            Some(Capability::Timers)
+        }
+        (HOST_OBSERVABILITY_MODULE, "callhandler") => Some(Capability::Rpc),
+        (HOST_OBSERVABILITY_MODULE, _) => Some(Capability::Pubsub),
+        (import_module, _) if custom_modules.contains(&import_module) => {
+            Some(Capability::Custom(import_module.to_string()))
+        }
+        (import_module, _) => bail!("unknown import module '{import_module}'"),
+    };
+
+    Ok(capability)
+}
+
+// Fails if the module imports a host function which requires a capability it was not granted, or which the host
+// does not know.
+pub fn check_imports(
+    module_name: &str,
+    module: &Module,
+    granted: &[Capability],
+    custom_modules: &[&str],
+) -> Result<()> {
+    for import in module.imports() {
+        let capability = match required_capability(import.module(), import.name(), custom_modules) {
+            Ok(capability) => capability,
+            Err(e) => bail!(
+                "Module '{module_name}' imports '{}::{}' from an {e}, no host functions are registered under it.",
+                import.module(),
+                import.name()
+            ),
+        };
+
+        if let Some(capability) = capability {
+            if !granted.contains(&capability) {
+                bail!(
+                    "Module '{module_name}' imports '{}::{}' which requires capability '{capability}', this is not granted to the module in host configuration.",
+                    import.module(),
+                    import.name()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Shadows functions in the linker which need a capability the module was not granted with functions which trap, so
+// the linker only reaches granted host functions, as a component's linker only links granted interfaces. WASI and
+// hostobservability bindings link all their functions at once, linked functions are only typed through a store.
+pub fn shadow_ungranted<T>(
+    linker: &mut Linker<T>,
+    mut store: impl AsContextMut<Data = T>,
+    granted: &[Capability],
+    custom_modules: &[&str],
+) -> Result<()> {
+    let items: Vec<(String, String, Extern)> = linker
+        .iter(&mut store)
+        .map(|(import_module, import_name, item)| {
+            (import_module.to_string(), import_name.to_string(), item)
+        })
+        .collect();
+
+    let mut ungranted = vec![];
+    for (import_module, import_name, item) in items {
+        let capability = required_capability(&import_module, &import_name, custom_modules)?;
+
+        if let (Some(capability), Extern::Func(func)) = (capability, item) {
+            if !granted.contains(&capability) {
+                let ty = func.ty(&store);
+                ungranted.push((import_module, import_name, ty, capability));
+            }
+        }
+    }
+
+    linker.allow_shadowing(true);
+    for (import_module, import_name, ty, capability) in ungranted {
+        let message = format!(
+            "'{import_module}::{import_name}' requires capability '{capability}', which is not granted"
+        );
+        linker.func_new(&import_module, &import_name, ty, move |_, _, _| {
+            Err(Trap::new(&message))
+        })?;
+    }
+    linker.allow_shadowing(false);
+
+    Ok(())
+}
@@ -0,0 +1,449 @@
+wasmtime::component::bindgen!({
+    path: "../wits/component",
+    world: "lifecycle-module",
+    trappable_imports: true,
+    with: {
+        "iot:host/timers/timer": Timer,
+    },
+});
+
+// Runs modules built as WebAssembly components (WASI preview 2) alongside core modules, on a current wasmtime with
+// its component model bindings. Host functions are served by the same 'Hostobservability' core modules use.
+use super::capabilities::Capability;
+use super::contracts;
+use super::hostobservability::{self as core_host, Hostobservability as _};
+use super::lifecycle_module::Options;
+use super::preopens::SCRATCH_GUEST_PATH;
+use super::runner::Instance;
+use super::scheduler::Scheduler;
+use super::{HostEnvironment, Hostobservability, RuntimeMetrics, MODULE_FUEL, MODULE_NAME};
+use crate::config::ModuleConfiguration;
+use crate::logging::{self, Level};
+use crate::metrics;
+use crate::shutdown::ShutdownSignal;
+use anyhow::{anyhow, Context as _, Result};
+use iot::host::{logging as component_logging, timers};
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Mutex, OnceLock},
+    time::{Instant, SystemTime},
+};
+use wasmtime::component::{Component, Linker, Resource, ResourceTable};
+use wasmtime::{CallHook, Config, Engine, ResourceLimiter, Store};
+use wasmtime_wasi_p2::{DirPerms, FilePerms, WasiCtx, WasiCtxBuilder, WasiView};
+
+static ENGINE: OnceLock<Engine> = OnceLock::new();
+// Compiled components by wasm path, with the modification time of the binary they were compiled from.
+static COMPONENTS: OnceLock<Mutex<HashMap<String, (SystemTime, Component)>>> = OnceLock::new();
+
+// Timer a component registered, cancelled when the component drops it.
+pub struct Timer {
+    id: u32,
+}
+
+// Store data of a component instance.
+pub struct ComponentContext {
+    wasi: WasiCtx,
+    table: ResourceTable,
+    host: Hostobservability,
+    runtime_metrics: RuntimeMetrics,
+}
+
+impl WasiView for ComponentContext {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+
+    fn ctx(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+}
+
+// Instantiates a component and calls its init, the component's lifecycle is then driven by the module runner.
+pub fn start(
+    wasm_path: &str,
+    wasm_config_path: &str,
+    module_config: &ModuleConfiguration,
+    options: &Options,
+    environment: &HostEnvironment,
+    shutdown: &ShutdownSignal,
+) -> Result<ComponentInstance> {
+    let module_name = module_config.module_name();
+    let instantiation_started = Instant::now();
+
+    if options.socket_address.is_some() {
+        return Err(anyhow!(
+            "Module '{module_name}' is a component, components cannot be given a pre-opened socket."
+        ));
+    }
+
+    let engine = engine()?;
+    let component = compile(engine, wasm_path)?;
+    contracts::check_component(&module_name, engine, &component)?;
+
+    let capabilities = module_config.capabilities()?;
+    warn_unsupported(&module_name, &capabilities);
+
+    // WASI preview 2 is linked in full, access is limited through the WASI context instead.
+    let mut linker = Linker::new(engine);
+    wasmtime_wasi_p2::add_to_linker_sync(&mut linker)?;
+
+    // Interfaces the module was not granted are left unlinked, instantiation then fails naming the missing one.
+    iot::host::runtime::add_to_linker(&mut linker, |cx: &mut ComponentContext| cx)?;
+    if capabilities.contains(&Capability::Logging) {
+        component_logging::add_to_linker(&mut linker, |cx: &mut ComponentContext| cx)?;
+    }
+    if capabilities.contains(&Capability::Metrics) {
+        iot::host::metrics::add_to_linker(&mut linker, |cx: &mut ComponentContext| cx)?;
+    }
+    if capabilities.contains(&Capability::Timers) {
+        timers::add_to_linker(&mut linker, |cx: &mut ComponentContext| cx)?;
+    }
+    if capabilities.contains(&Capability::Pubsub) {
+        iot::host::pubsub::add_to_linker(&mut linker, |cx: &mut ComponentContext| cx)?;
+    }
+
+    let mut store = Store::new(
+        engine,
+        ComponentContext {
+            wasi: wasi_ctx(module_config, &capabilities)?,
+            table: ResourceTable::new(),
+            host: Hostobservability::new(module_config, environment, shutdown.clone()),
+            runtime_metrics: RuntimeMetrics::new(module_config),
+        },
+    );
+    store.set_fuel(MODULE_FUEL)?;
+    store.limiter(|cx| &mut cx.runtime_metrics);
+    store.call_hook(|mut cx, call_hook| {
+        if let CallHook::CallingHost = call_hook {
+            cx.data_mut().runtime_metrics.host_call();
+        }
+        Ok(())
+    });
+
+    let bindings = LifecycleModule::instantiate(&mut store, &component, &linker)
+        .with_context(|| {
+            format!("Module '{module_name}' cannot be instantiated, it may import an interface it was not granted")
+        })?;
+
+    metrics::gauge_set(
+        &module_name,
+        "wasm_module_instantiation_seconds",
+        instantiation_started.elapsed().as_secs_f64(),
+        &[("replica", &module_config.replica().to_string())],
+    );
+
+    // Call init of the component, it schedules its work on timers during init.
+    bindings
+        .iot_host_lifecycle()
+        .call_init(&mut store, wasm_config_path)?
+        .map_err(|e| anyhow!("Module '{module_name}' failed to initialise: {e}"))?;
+
+    let mut instance = ComponentInstance { bindings, store };
+    instance.record_runtime_metrics();
+
+    Ok(instance)
+}
+
+// Returns the engine all components run on, so replicas, canaries and reloads share it and its compiled code.
+fn engine() -> Result<&'static Engine> {
+    if let Some(engine) = ENGINE.get() {
+        return Ok(engine);
+    }
+
+    let mut config = Config::new();
+    config.wasm_component_model(true);
+    config.consume_fuel(true);
+    let engine = Engine::new(&config)?;
+
+    Ok(ENGINE.get_or_init(|| engine))
+}
+
+// Compiles a component once per version of its binary, replicas and canaries of the same binary reuse it and hot
+// reload compiles it again only once it changed.
+fn compile(engine: &Engine, wasm_path: &str) -> Result<Component> {
+    let modified = fs::metadata(wasm_path)?.modified()?;
+    let mut components = COMPONENTS.get_or_init(Default::default).lock().unwrap();
+
+    match components.get(wasm_path) {
+        Some((compiled_modified, component)) if *compiled_modified == modified => {
+            Ok(component.clone())
+        }
+        _ => {
+            let component = Component::from_file(engine, wasm_path)?;
+            components.insert(wasm_path.to_string(), (modified, component.clone()));
+            Ok(component)
+        }
+    }
+}
+
+// Capabilities components cannot use yet, components granted them are started without them.
+fn warn_unsupported(module_name: &str, capabilities: &[Capability]) {
+    for capability in capabilities {
+        if let Capability::Http | Capability::Rpc | Capability::Custom(_) = capability {
+            logging::log(
+                Level::Warn,
+                MODULE_NAME,
+                &format!(
+                    "Module '{module_name}' is a component, capability '{capability}' is only available to core modules."
+                ),
+                &[("module", module_name)],
+            );
+        }
+    }
+}
+
+// WASI preview 2 context, with the same filesystem and socket access core modules get from their capabilities.
+fn wasi_ctx(module_config: &ModuleConfiguration, capabilities: &[Capability]) -> Result<WasiCtx> {
+    let mut builder = WasiCtxBuilder::new();
+    builder.inherit_stdio();
+
+    let sockets = capabilities.contains(&Capability::Sockets);
+    builder
+        .allow_tcp(sockets)
+        .allow_udp(sockets)
+        .allow_ip_name_lookup(sockets);
+
+    // Only directories declared for the module and its own scratch directory are accessible.
+    if capabilities.contains(&Capability::Filesystem) {
+        for preopen in module_config.preopens()? {
+            let (dir_perms, file_perms) = if preopen.read_only {
+                (DirPerms::READ, FilePerms::READ)
+            } else {
+                (DirPerms::all(), FilePerms::all())
+            };
+            builder.preopened_dir(
+                &preopen.host_path,
+                &preopen.guest_path,
+                dir_perms,
+                file_perms,
+            )?;
+        }
+
+        let scratch_dir = module_config.scratch_directory();
+        fs::create_dir_all(&scratch_dir)?;
+        builder.preopened_dir(
+            &scratch_dir,
+            SCRATCH_GUEST_PATH,
+            DirPerms::all(),
+            FilePerms::all(),
+        )?;
+    }
+
+    Ok(builder.build())
+}
+
+// Component instance, driven by the module runner like a core module instance.
+pub struct ComponentInstance {
+    bindings: LifecycleModule,
+    store: Store<ComponentContext>,
+}
+
+impl Instance for ComponentInstance {
+    fn scheduler(&mut self) -> &mut Scheduler {
+        &mut self.store.data_mut().host.scheduler
+    }
+
+    fn on_tick(&mut self, timer_id: u32) -> Result<()> {
+        self.bindings
+            .iot_host_lifecycle()
+            .call_on_tick(&mut self.store, timer_id)
+    }
+
+    fn shutdown(&mut self, deadline_in_milliseconds: u64) -> Result<()> {
+        self.bindings
+            .iot_host_lifecycle()
+            .call_shutdown(&mut self.store, deadline_in_milliseconds)
+    }
+
+    fn error_logs(&self) -> u64 {
+        self.store.data().host.error_logs
+    }
+
+    // Records fuel consumed by the component and its host calls, sampled whenever control returns to the host from an
+    // export.
+    fn record_runtime_metrics(&mut self) {
+        self.store.data_mut().runtime_metrics.flush_host_calls();
+
+        if let Ok(fuel) = self.store.get_fuel() {
+            let runtime_metrics = &self.store.data().runtime_metrics;
+            metrics::gauge_set(
+                &runtime_metrics.module_name,
+                "wasm_module_fuel_consumed",
+                (MODULE_FUEL - fuel) as f64,
+                &[("replica", &runtime_metrics.replica)],
+            );
+        }
+    }
+}
+
+// Bridges the current wasmtime's limiter to the one runtime metrics implement for the wasmtime of the core module
+// bindings, so both count memory growth the same way. Goes away once core modules move to the component model and
+// the host runs a single wasmtime.
+impl ResourceLimiter for RuntimeMetrics {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> Result<bool> {
+        Ok(
+            wit_bindgen_wasmtime::wasmtime::ResourceLimiter::memory_growing(
+                self, current, desired, maximum,
+            ),
+        )
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        _desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+// Lists of string pairs are passed to host functions as borrowed pairs, as from core modules.
+fn pairs(pairs: &[(String, String)]) -> Vec<(&str, &str)> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect()
+}
+
+impl component_logging::Host for ComponentContext {
+    fn log(
+        &mut self,
+        level: component_logging::LogLevel,
+        message: String,
+        fields: Vec<(String, String)>,
+    ) -> Result<()> {
+        let level = match level {
+            component_logging::LogLevel::Trace => core_host::Loglevel::Trace,
+            component_logging::LogLevel::Debug => core_host::Loglevel::Debug,
+            component_logging::LogLevel::Info => core_host::Loglevel::Info,
+            component_logging::LogLevel::Warn => core_host::Loglevel::Warn,
+            component_logging::LogLevel::Error => core_host::Loglevel::Error,
+        };
+
+        self.host.log(level, &message, pairs(&fields));
+        Ok(())
+    }
+}
+
+impl iot::host::metrics::Host for ComponentContext {
+    fn counter_increment(
+        &mut self,
+        name: String,
+        value: u64,
+        labels: Vec<(String, String)>,
+    ) -> Result<()> {
+        self.host.counterincrement(&name, value, pairs(&labels));
+        Ok(())
+    }
+
+    fn gauge_set(&mut self, name: String, value: f64, labels: Vec<(String, String)>) -> Result<()> {
+        self.host.gaugeset(&name, value, pairs(&labels));
+        Ok(())
+    }
+
+    fn histogram_record(
+        &mut self,
+        name: String,
+        value: f64,
+        labels: Vec<(String, String)>,
+    ) -> Result<()> {
+        self.host.histogramrecord(&name, value, pairs(&labels));
+        Ok(())
+    }
+}
+
+impl From<timers::MissedTickPolicy> for core_host::Missedtickpolicy {
+    fn from(policy: timers::MissedTickPolicy) -> Self {
+        match policy {
+            timers::MissedTickPolicy::Skip => core_host::Missedtickpolicy::Skip,
+            timers::MissedTickPolicy::Burst => core_host::Missedtickpolicy::Burst,
+            timers::MissedTickPolicy::Delay => core_host::Missedtickpolicy::Delay,
+        }
+    }
+}
+
+impl timers::Host for ComponentContext {}
+
+impl timers::HostTimer for ComponentContext {
+    fn interval(
+        &mut self,
+        interval_in_milliseconds: u64,
+        jitter_in_milliseconds: u64,
+        missed_tick_policy: timers::MissedTickPolicy,
+    ) -> Result<Resource<Timer>> {
+        let id = self.host.scheduleinterval(
+            interval_in_milliseconds,
+            jitter_in_milliseconds,
+            missed_tick_policy.into(),
+        );
+
+        Ok(self.table.push(Timer { id })?)
+    }
+
+    fn cron(
+        &mut self,
+        expression: String,
+        jitter_in_milliseconds: u64,
+        missed_tick_policy: timers::MissedTickPolicy,
+    ) -> Result<Result<Resource<Timer>, String>> {
+        match self.host.schedulecron(
+            &expression,
+            jitter_in_milliseconds,
+            missed_tick_policy.into(),
+        ) {
+            Ok(id) => Ok(Ok(self.table.push(Timer { id })?)),
+            Err(e) => Ok(Err(e)),
+        }
+    }
+
+    fn id(&mut self, timer: Resource<Timer>) -> Result<u32> {
+        Ok(self.table.get(&timer)?.id)
+    }
+
+    fn drop(&mut self, timer: Resource<Timer>) -> Result<()> {
+        let timer = self.table.delete(timer)?;
+        self.host.canceltimer(timer.id);
+        Ok(())
+    }
+}
+
+impl iot::host::pubsub::Host for ComponentContext {
+    fn publish(&mut self, topic: String, message: String) -> Result<()> {
+        self.host.publish(&topic, &message);
+        Ok(())
+    }
+
+    fn publish_with_options(
+        &mut self,
+        topic: String,
+        message: String,
+        ttl_in_milliseconds: Option<u64>,
+        delay_in_milliseconds: Option<u64>,
+    ) -> Result<()> {
+        self.host
+            .publishwithoptions(&topic, &message, ttl_in_milliseconds, delay_in_milliseconds);
+        Ok(())
+    }
+
+    fn read(&mut self, topic: String) -> Result<Result<Option<String>, String>> {
+        Ok(self.host.read(&topic))
+    }
+
+    fn traceparent(&mut self) -> Result<Option<String>> {
+        Ok(self.host.traceparent())
+    }
+}
+
+impl iot::host::runtime::Host for ComponentContext {
+    fn shutdown_requested(&mut self) -> Result<bool> {
+        Ok(self.host.shutdownrequested())
+    }
+}
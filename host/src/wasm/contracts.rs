@@ -0,0 +1,616 @@
+// Versioned WIT contracts between the host and its modules. Each WIT file starts with the package and version it
+// defines, core modules embed the WIT files they were built against in a custom section and components name the
+// package version in their imports. Modules are checked against the host's contracts at load, so a module built
+// against other WIT files is refused with the functions missing or changed rather than with a link error.
+use super::MODULE_NAME;
+use crate::logging::{self, Level};
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::{fmt, fs};
+#[cfg(feature = "component")]
+use wasmtime::{
+    component::{types::ComponentItem, Component},
+    Engine,
+};
+use wit_bindgen_wasmtime::wasmtime::Module;
+#[cfg(feature = "component")]
+use wit_parser::Resolve;
+
+// Custom section of core modules holding the WIT files they were built against.
+const CONTRACT_SECTION: &str = "iot-host-contract";
+// Comment starting each legacy WIT file, naming the package it defines and its version.
+const PACKAGE_HEADER: &str = "// package ";
+#[cfg(feature = "component")]
+const COMPONENT_PACKAGE: &str = "iot:host";
+// Functions the host calls on a component's lifecycle export.
+#[cfg(feature = "component")]
+const COMPONENT_LIFECYCLE: &str = "lifecycle";
+
+// Whether the host provides the contract's functions to modules or calls them on modules.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Import,
+    Export,
+}
+
+const CORE_CONTRACTS: &[(&str, Direction)] = &[
+    (
+        include_str!("../../../wits/hostobservability.wit"),
+        Direction::Import,
+    ),
+    (
+        include_str!("../../../wits/wasmlifecyclefunctions.wit"),
+        Direction::Export,
+    ),
+    (
+        include_str!("../../../wits/wasmmessagefunctions.wit"),
+        Direction::Export,
+    ),
+    (
+        include_str!("../../../wits/wasmhttpfunctions.wit"),
+        Direction::Export,
+    ),
+    (
+        include_str!("../../../wits/wasmrpcfunctions.wit"),
+        Direction::Export,
+    ),
+];
+
+#[cfg(feature = "component")]
+const COMPONENT_CONTRACT: &str = include_str!("../../../wits/component/host.wit");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Version {
+    major: u64,
+    minor: u64,
+    patch: u64,
+}
+
+impl Version {
+    fn parse(version: &str) -> Option<Self> {
+        let mut parts = version.splitn(3, '.').map(|part| part.parse::<u64>().ok());
+        Some(Self {
+            major: parts.next()??,
+            minor: parts.next()??,
+            patch: parts.next()??,
+        })
+    }
+
+    // Returns whether functions provided at this version satisfy a consumer built against the other version, by
+    // semver: same major version (same minor before 1.0) and no newer minor version than provided.
+    fn provides(&self, consumer: &Version) -> bool {
+        self.major == consumer.major
+            && if self.major == 0 {
+                self.minor == consumer.minor
+            } else {
+                self.minor >= consumer.minor
+            }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+// Legacy WIT package, its functions and types by name with their definitions normalised to single spaced text.
+struct Contract {
+    package: String,
+    version: Version,
+    items: BTreeMap<String, String>,
+}
+
+// Splits '<package>@<version>'.
+fn package_version(name: &str) -> Option<(&str, Version)> {
+    let (package, version) = name.trim().split_once('@')?;
+    Some((package, Version::parse(version)?))
+}
+
+// Parses legacy WIT files, several may be concatenated as they are in a module's contract section.
+fn parse_contracts(wit: &str) -> Result<Vec<Contract>> {
+    let mut contracts: Vec<Contract> = vec![];
+    let mut item = String::new();
+    let mut depth = 0;
+
+    for line in wit.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix(PACKAGE_HEADER) {
+            let (package, version) = match package_version(name) {
+                Some(package_version) => package_version,
+                None => bail!("Invalid contract package '{name}'."),
+            };
+            contracts.push(Contract {
+                package: package.to_string(),
+                version,
+                items: BTreeMap::new(),
+            });
+            continue;
+        }
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+
+        let contract = match contracts.last_mut() {
+            Some(contract) => contract,
+            None => {
+                bail!("WIT contract does not start with '{PACKAGE_HEADER}<package>@<version>'.")
+            }
+        };
+
+        // Types span lines until their braces close, functions are a single line.
+        depth += line.matches('{').count() as i32 - line.matches('}').count() as i32;
+        item.push(' ');
+        item.push_str(line);
+        if depth > 0 {
+            continue;
+        }
+
+        let definition = item.split_whitespace().collect::<Vec<_>>().join(" ");
+        let name = match definition.split_once(' ') {
+            Some(("enum" | "record" | "variant" | "flags" | "union" | "type", rest)) => {
+                rest.split([' ', '{', '=']).next().unwrap_or_default()
+            }
+            _ => definition.split(':').next().unwrap_or_default(),
+        }
+        .to_string();
+        contract.items.insert(name, definition);
+        item.clear();
+    }
+
+    Ok(contracts)
+}
+
+// Returns contents of the custom sections with the given name in a core module binary, in binary order.
+fn custom_sections<'a>(wasm: &'a [u8], name: &str) -> Vec<&'a [u8]> {
+    let mut sections = vec![];
+    // Sections follow the 8 byte preamble, each is an id byte followed by its size.
+    let mut offset = 8;
+
+    while offset < wasm.len() {
+        let id = wasm[offset];
+        let (size, read) = match leb128(&wasm[offset + 1..]) {
+            Some(size) => size,
+            None => break,
+        };
+        let start = offset + 1 + read;
+        let end = (start + size).min(wasm.len());
+
+        // Custom sections start with their name.
+        if id == 0 {
+            if let Some((name_size, read)) = leb128(&wasm[start..end]) {
+                let name_end = start + read + name_size;
+                if name_end <= end && &wasm[start + read..name_end] == name.as_bytes() {
+                    sections.push(&wasm[name_end..end]);
+                }
+            }
+        }
+
+        offset = end;
+    }
+
+    sections
+}
+
+// Decodes an unsigned LEB128 u32, returns the value and the bytes read.
+fn leb128(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0;
+    for (index, byte) in bytes.iter().take(5).enumerate() {
+        value |= ((byte & 0x7f) as usize) << (7 * index);
+        if byte & 0x80 == 0 {
+            return Some((value, index + 1));
+        }
+    }
+
+    None
+}
+
+// Fails when a core module was built against contracts the host does not provide, listing every incompatibility.
+// Modules built before contracts were embedded are not checked.
+pub fn check_core(module_name: &str, wasm_path: &str, module: &Module) -> Result<()> {
+    let wasm = fs::read(wasm_path)?;
+    let embedded = custom_sections(&wasm, CONTRACT_SECTION)
+        .into_iter()
+        .map(String::from_utf8_lossy)
+        .collect::<String>();
+
+    if embedded.is_empty() {
+        logging::log(
+            Level::Warn,
+            MODULE_NAME,
+            &format!("Module '{module_name}' embeds no WIT contract, it is not checked against host contracts."),
+            &[],
+        );
+        return Ok(());
+    }
+
+    let module_contracts = parse_contracts(&embedded)?;
+    let mut host_contracts = vec![];
+    for (wit, direction) in CORE_CONTRACTS {
+        for contract in parse_contracts(wit)? {
+            host_contracts.push((contract, *direction));
+        }
+    }
+
+    let mut problems = vec![];
+    for module_contract in &module_contracts {
+        match host_contracts
+            .iter()
+            .find(|(host_contract, _)| host_contract.package == module_contract.package)
+        {
+            Some((host_contract, direction)) => {
+                problems.extend(diff(host_contract, module_contract, *direction))
+            }
+            None => problems.push(format!(
+                "targets contract '{}@{}' which the host does not provide",
+                module_contract.package, module_contract.version
+            )),
+        }
+    }
+
+    // Every host function a module imports must come from a contract it was built against.
+    for import in module.imports() {
+        let package = format!("iot:{}", import.module());
+        let provided_by_host = host_contracts
+            .iter()
+            .any(|(host_contract, _)| host_contract.package == package);
+        let embedded_by_module = module_contracts
+            .iter()
+            .any(|module_contract| module_contract.package == package);
+
+        if provided_by_host && !embedded_by_module {
+            problems.push(format!(
+                "imports '{}::{}' but embeds no '{package}' contract",
+                import.module(),
+                import.name()
+            ));
+        }
+    }
+
+    report(module_name, problems)
+}
+
+// Returns differences between the host's and a module's version of a contract which break the module.
+fn diff(host: &Contract, module: &Contract, direction: Direction) -> Vec<String> {
+    let mut problems = vec![];
+    let package = &host.package;
+
+    let (provider, consumer) = match direction {
+        Direction::Import => (host, module),
+        Direction::Export => (module, host),
+    };
+    if !provider.version.provides(&consumer.version) {
+        problems.push(format!(
+            "built against '{package}@{}', host provides '{package}@{}'",
+            module.version, host.version
+        ));
+    }
+
+    for (name, definition) in &consumer.items {
+        match provider.items.get(name) {
+            None if direction == Direction::Import => problems.push(format!(
+                "'{package}' item '{name}' is missing from the host"
+            )),
+            None => problems.push(format!(
+                "'{package}' item '{name}' is not exported by the module"
+            )),
+            Some(provided) if provided != definition => problems.push(format!(
+                "'{package}' item '{name}' changed, host has '{}', module has '{}'",
+                host.items[name], module.items[name]
+            )),
+            Some(_) => (),
+        }
+    }
+
+    problems
+}
+
+// Returns the host's component package version and the functions and types of each of its interfaces, resource
+// functions are named as in the component model ('[static]timer.interval', '[method]timer.id').
+#[cfg(feature = "component")]
+fn component_interfaces() -> Result<(Version, BTreeMap<String, BTreeSet<String>>)> {
+    let mut resolve = Resolve::new();
+    let package = resolve.push_str("host.wit", COMPONENT_CONTRACT)?;
+    let package = &resolve.packages[package];
+
+    let version = match &package.name.version {
+        Some(version) => Version {
+            major: version.major,
+            minor: version.minor,
+            patch: version.patch,
+        },
+        None => bail!("Component WIT contract names no '{COMPONENT_PACKAGE}@<version>' package."),
+    };
+    let interfaces = package
+        .interfaces
+        .iter()
+        .map(|(name, interface)| {
+            let interface = &resolve.interfaces[*interface];
+            let items = interface
+                .types
+                .keys()
+                .chain(interface.functions.keys())
+                .cloned()
+                .collect();
+            (name.clone(), items)
+        })
+        .collect();
+
+    Ok((version, interfaces))
+}
+
+// Fails when a component imports interfaces or functions of the host package the host does not provide, or does not
+// export the lifecycle functions the host calls. Changed signatures are reported by the component linker.
+#[cfg(feature = "component")]
+pub fn check_component(module_name: &str, engine: &Engine, component: &Component) -> Result<()> {
+    let (host_version, interfaces) = component_interfaces()?;
+    let component_type = component.component_type();
+    let mut problems = vec![];
+
+    let imports = component_type
+        .imports(engine)
+        .map(|(name, item)| (name, item, Direction::Import));
+    let lifecycle = format!("{COMPONENT_PACKAGE}/{COMPONENT_LIFECYCLE}@");
+    let exports = component_type
+        .exports(engine)
+        .filter(|(name, _)| name.starts_with(&lifecycle))
+        .map(|(name, item)| (name, item, Direction::Export));
+
+    for (name, item, direction) in imports.chain(exports) {
+        // Interfaces of the host package are named '<package>/<interface>@<version>', WASI is linked in full.
+        let interface = match name
+            .strip_prefix(COMPONENT_PACKAGE)
+            .and_then(|name| name.strip_prefix('/'))
+        {
+            Some(interface) => interface,
+            None => continue,
+        };
+        let (interface, version) = match package_version(interface) {
+            Some(interface_version) => interface_version,
+            None => {
+                problems.push(format!(
+                    "'{name}' names no version of '{COMPONENT_PACKAGE}'"
+                ));
+                continue;
+            }
+        };
+
+        let compatible = match direction {
+            Direction::Import => host_version.provides(&version),
+            Direction::Export => version.provides(&host_version),
+        };
+        if !compatible {
+            problems.push(format!(
+                "'{interface}' built against '{COMPONENT_PACKAGE}@{version}', host provides '{COMPONENT_PACKAGE}@{host_version}'"
+            ));
+        }
+
+        let host_items = match interfaces.get(interface) {
+            Some(host_items) => host_items,
+            None => {
+                problems.push(format!("interface '{interface}' is missing from the host"));
+                continue;
+            }
+        };
+        let items = match item {
+            ComponentItem::ComponentInstance(instance) => instance
+                .exports(engine)
+                .map(|(name, _)| name.to_string())
+                .collect::<BTreeSet<_>>(),
+            _ => continue,
+        };
+
+        let (provided, consumed, provider) = match direction {
+            Direction::Import => (host_items, &items, "the host"),
+            Direction::Export => (&items, host_items, "the module"),
+        };
+        for name in consumed.difference(provided) {
+            problems.push(format!(
+                "'{interface}' item '{name}' is missing from {provider}"
+            ));
+        }
+    }
+
+    report(module_name, problems)
+}
+
+// Fails listing every problem found, one per line.
+fn report(module_name: &str, problems: Vec<String>) -> Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    bail!(
+        "Module '{module_name}' is not compatible with the host's WIT contracts:\n  - {}",
+        problems.join("\n  - ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(version: &str) -> Version {
+        Version::parse(version).unwrap()
+    }
+
+    fn contract(wit: &str) -> Contract {
+        parse_contracts(wit).unwrap().remove(0)
+    }
+
+    // Custom section with the given name and contents, its size encoded in as many LEB128 bytes as needed.
+    fn custom_section(name: &str, contents: &[u8]) -> Vec<u8> {
+        let mut body = vec![name.len() as u8];
+        body.extend(name.as_bytes());
+        body.extend(contents);
+
+        let mut section = vec![0];
+        let mut size = body.len();
+        loop {
+            let byte = (size & 0x7f) as u8;
+            size >>= 7;
+            if size == 0 {
+                section.push(byte);
+                break;
+            }
+            section.push(byte | 0x80);
+        }
+        section.extend(body);
+        section
+    }
+
+    #[test]
+    fn provided_version_satisfies_same_major_and_older_minor() {
+        assert!(version("1.2.0").provides(&version("1.2.3")));
+        assert!(version("1.2.0").provides(&version("1.1.0")));
+        assert!(!version("1.2.0").provides(&version("1.3.0")));
+        assert!(!version("2.0.0").provides(&version("1.0.0")));
+        assert!(!version("1.0.0").provides(&version("2.0.0")));
+    }
+
+    #[test]
+    fn minor_versions_must_match_before_one() {
+        assert!(version("0.2.0").provides(&version("0.2.1")));
+        assert!(!version("0.3.0").provides(&version("0.2.0")));
+        assert!(!version("0.2.0").provides(&version("0.3.0")));
+    }
+
+    #[test]
+    fn parses_concatenated_contracts_with_multi_line_types() {
+        let contracts = parse_contracts(
+            "// package iot:first@1.2.0\n\
+             record device {\n\
+             \x20   id: string,\n\
+             \x20   tags: list<string>,\n\
+             }\n\
+             // Comments and blank lines are skipped.\n\
+             \n\
+             send: func(device: device) -> expected<string, string>\n\
+             // package iot:second@0.1.0\n\
+             enum level { low, high }\n\
+             level: func() -> level\n",
+        )
+        .unwrap();
+
+        assert_eq!(contracts.len(), 2);
+        assert_eq!(contracts[0].package, "iot:first");
+        assert_eq!(contracts[0].version, version("1.2.0"));
+        assert_eq!(
+            contracts[0].items["device"],
+            "record device { id: string, tags: list<string>, }"
+        );
+        assert_eq!(
+            contracts[0].items["send"],
+            "send: func(device: device) -> expected<string, string>"
+        );
+        assert_eq!(contracts[1].package, "iot:second");
+        assert_eq!(contracts[1].items["level"], "level: func() -> level");
+        assert_eq!(contracts[1].items.len(), 1);
+    }
+
+    #[test]
+    fn contracts_must_start_with_valid_package() {
+        assert!(parse_contracts("send: func()\n").is_err());
+        assert!(parse_contracts("// package iot:first@1.2\nsend: func()\n").is_err());
+        assert!(parse_contracts("// package iot:first\n").is_err());
+    }
+
+    #[test]
+    fn finds_custom_sections_by_name_in_binary_order() {
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        wasm.extend(custom_section(CONTRACT_SECTION, b"first"));
+        // Type section with one empty function type.
+        wasm.extend([1, 4, 1, 0x60, 0, 0]);
+        wasm.extend(custom_section("name", b"other"));
+        let large = "x".repeat(200);
+        wasm.extend(custom_section(CONTRACT_SECTION, large.as_bytes()));
+
+        assert_eq!(
+            custom_sections(&wasm, CONTRACT_SECTION),
+            [b"first".as_slice(), large.as_bytes()]
+        );
+        assert_eq!(custom_sections(&wasm, "name"), [b"other".as_slice()]);
+        assert!(custom_sections(&wasm, "missing").is_empty());
+    }
+
+    #[test]
+    fn truncated_binary_yields_complete_sections_only() {
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        wasm.extend(custom_section(CONTRACT_SECTION, b"first"));
+        wasm.extend([0, 0x80]);
+
+        assert_eq!(
+            custom_sections(&wasm, CONTRACT_SECTION),
+            [b"first".as_slice()]
+        );
+    }
+
+    #[test]
+    fn imported_contract_must_provide_every_item_the_module_uses() {
+        let host = contract(
+            "// package iot:host@1.1.0\nsend: func(topic: string)\nread: func() -> string\n",
+        );
+
+        let older = contract("// package iot:host@1.0.0\nsend: func(topic: string)\n");
+        assert!(diff(&host, &older, Direction::Import).is_empty());
+
+        let module = contract(
+            "// package iot:host@1.2.0\nsend: func(topic: string, ttl: u64)\nflush: func()\n",
+        );
+        assert_eq!(
+            diff(&host, &module, Direction::Import),
+            [
+                "built against 'iot:host@1.2.0', host provides 'iot:host@1.1.0'",
+                "'iot:host' item 'flush' is missing from the host",
+                "'iot:host' item 'send' changed, host has 'send: func(topic: string)', module has 'send: func(topic: string, ttl: u64)'",
+            ]
+        );
+    }
+
+    #[test]
+    fn exported_contract_must_provide_every_item_the_host_calls() {
+        let host =
+            contract("// package iot:lifecycle@1.1.0\ninit: func()\nontick: func(id: u32)\n");
+
+        let newer = contract(
+            "// package iot:lifecycle@1.2.0\ninit: func()\nontick: func(id: u32)\nextra: func()\n",
+        );
+        assert!(diff(&host, &newer, Direction::Export).is_empty());
+
+        let module = contract("// package iot:lifecycle@1.0.0\ninit: func()\n");
+        assert_eq!(
+            diff(&host, &module, Direction::Export),
+            [
+                "built against 'iot:lifecycle@1.0.0', host provides 'iot:lifecycle@1.1.0'",
+                "'iot:lifecycle' item 'ontick' is not exported by the module",
+            ]
+        );
+    }
+
+    #[test]
+    fn host_contracts_parse() {
+        for (wit, _) in CORE_CONTRACTS {
+            assert!(!parse_contracts(wit).unwrap().is_empty());
+        }
+    }
+
+    #[cfg(feature = "component")]
+    #[test]
+    fn component_contract_names_interface_items_as_the_component_model() {
+        let (version, interfaces) = component_interfaces().unwrap();
+
+        assert_eq!(version, Version::parse("0.2.0").unwrap());
+        assert!(interfaces["logging"].contains("log-level"));
+        assert!(interfaces["logging"].contains("log"));
+        for item in [
+            "timer",
+            "missed-tick-policy",
+            "[static]timer.interval",
+            "[static]timer.cron",
+            "[method]timer.id",
+        ] {
+            assert!(interfaces["timers"].contains(item), "{item}");
+        }
+        assert!(interfaces.contains_key("lifecycle"));
+    }
+}
@@ -0,0 +1,644 @@
+wit_bindgen_wasmtime::import!("../wits/wasmhttpfunctions.wit");
+
+// Http triggered modules have no init loop, the host serves http and calls the 'handlehttp' export of the module
+// whose route matches each request, in a new instance from the module's instance pool.
+use super::preopens::ModuleDirs;
+use super::{HostEnvironment, Hostobservability, Runtime, MODULE_NAME};
+use crate::config::ModuleConfiguration;
+use crate::logging::{self, Level};
+use crate::metrics;
+use crate::shutdown::ShutdownSignal;
+use crate::tracing::{Span, SpanKind, TraceContext};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, OnceLock, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use wasmhttpfunctions::{Httprequest, Wasmhttpfunctions, WasmhttpfunctionsData};
+
+// Route path suffix which matches any path starting with the rest of the route path.
+const PATH_WILDCARD: &str = "*";
+const ANY_METHOD: &str = "*";
+// How often a module shutting down checks whether its in flight requests have finished.
+const IN_FLIGHT_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+// Connections served at once, further connections get '503 Service Unavailable' without their request being read.
+const MAX_CONNECTIONS: usize = 256;
+// Longest a read or write on a connection may block, so slow clients cannot hold connection threads.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+// Longer request lines get '414 URI Too Long', larger headers '431 Request Header Fields Too Large'.
+const MAX_REQUEST_LINE_BYTES: usize = 8 * 1024;
+const MAX_HEADER_BYTES: usize = 32 * 1024;
+// Methods whose requests carry a body, these get '411 Length Required' without Content-Length.
+const METHODS_WITH_BODY: [&str; 3] = ["POST", "PUT", "PATCH"];
+// Statuses a module may respond with, others are replaced with '500 Internal Server Error'.
+const MIN_STATUS: u16 = 100;
+const MAX_STATUS: u16 = 599;
+
+static ROUTES: OnceLock<RwLock<Vec<(Route, Arc<Handler>)>>> = OnceLock::new();
+
+#[derive(Clone, Debug)]
+pub struct Route {
+    pub method: String,
+    // Exact path, or a path prefix when it ends with '*'.
+    pub path: String,
+}
+
+impl Route {
+    fn matches(&self, method: &str, path: &str) -> bool {
+        let method_matches = self.method == ANY_METHOD || self.method.eq_ignore_ascii_case(method);
+        let path_matches = match self.path.strip_suffix(PATH_WILDCARD) {
+            Some(prefix) => path.starts_with(prefix),
+            None => self.path == path,
+        };
+
+        method_matches && path_matches
+    }
+}
+
+pub struct HttpTrigger {
+    pub routes: Vec<Route>,
+    // Requests handled at once, further requests are rejected with '503 Service Unavailable'.
+    pub max_concurrency: usize,
+    // Instances the pooling allocator reserves memory and tables for up front.
+    pub pool_size: u32,
+    // Larger request bodies are rejected with '413 Payload Too Large'.
+    pub max_request_body_bytes: usize,
+}
+
+// Module serving requests for its routes.
+struct Handler {
+    module_config: ModuleConfiguration,
+    runtime: Runtime,
+    // Directories opened once for the module, each instance gets its own handles.
+    dirs: Option<ModuleDirs>,
+    environment: HostEnvironment,
+    shutdown: ShutdownSignal,
+    max_concurrency: usize,
+    max_request_body_bytes: usize,
+    in_flight: AtomicUsize,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+// Request counted in flight against the handler it was routed to, until dropped.
+struct InFlight(Arc<Handler>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Response {
+    fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+}
+
+// Routes the module's requests to it until shutdown is requested, then waits for its in flight requests.
+pub fn run_module(
+    wasm_path: &str,
+    module_config: &ModuleConfiguration,
+    environment: &HostEnvironment,
+    shutdown: ShutdownSignal,
+) -> Result<()> {
+    let module_name = module_config.module_name();
+    let trigger = module_config
+        .http_trigger()
+        .ok_or_else(|| anyhow!("Module '{module_name}' has no http trigger configured."))?;
+
+    // Module is compiled once, each request gets an instance allocated from the pool.
+    let config = super::pooled_config(trigger.pool_size.max(trigger.max_concurrency as u32));
+    let mut runtime = super::prepare(
+        wasm_path,
+        module_config,
+        config,
+        &environment.host_functions,
+    )?;
+    Wasmhttpfunctions::add_to_linker(&mut runtime.linker, |cx: &mut super::Context| {
+        cx.exports_mut()
+    })?;
+
+    let handler = Arc::new(Handler {
+        module_config: module_config.clone(),
+        runtime,
+        dirs: super::module_dirs(module_config)?,
+        environment: environment.clone(),
+        shutdown: shutdown.clone(),
+        max_concurrency: trigger.max_concurrency.max(1),
+        max_request_body_bytes: trigger.max_request_body_bytes,
+        in_flight: AtomicUsize::new(0),
+    });
+
+    let routes = ROUTES.get_or_init(Default::default);
+    routes.write().unwrap().extend(
+        trigger
+            .routes
+            .iter()
+            .map(|route| (route.clone(), handler.clone())),
+    );
+
+    for route in &trigger.routes {
+        logging::log(
+            Level::Info,
+            MODULE_NAME,
+            &format!(
+                "Routing '{} {}' to module '{module_name}'.",
+                route.method, route.path
+            ),
+            &[("module", &module_name)],
+        );
+    }
+
+    shutdown.wait_until_requested();
+
+    // New requests for the module's routes get '404 Not Found' from here, requests already routed finish.
+    routes
+        .write()
+        .unwrap()
+        .retain(|(_, route_handler)| !Arc::ptr_eq(route_handler, &handler));
+    while handler.in_flight.load(Ordering::SeqCst) > 0 {
+        thread::sleep(IN_FLIGHT_CHECK_INTERVAL);
+    }
+
+    Ok(())
+}
+
+// Serves http requests for http triggered modules at the given address, on a background thread.
+pub fn serve(address: &str) -> std::io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+
+    logging::log(
+        Level::Info,
+        MODULE_NAME,
+        &format!("Serving http triggered modules on 'http://{address}'."),
+        &[],
+    );
+
+    thread::spawn(move || {
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        for stream in listener.incoming().flatten() {
+            if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                let _ = stream
+                    .set_write_timeout(Some(CONNECTION_TIMEOUT))
+                    .and_then(|_| write_response(&stream, Response::status(503)));
+                continue;
+            }
+
+            // Each connection is handled on its own thread, modules limit their own concurrency.
+            let connections = connections.clone();
+            thread::spawn(move || {
+                if let Err(e) = respond(stream) {
+                    logging::log(
+                        Level::Warn,
+                        MODULE_NAME,
+                        &format!("Failed to serve http request; error = {e}"),
+                        &[],
+                    );
+                }
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn respond(stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+
+    let response = match read_head(&mut reader)? {
+        Ok(request) => route(&mut reader, request)?,
+        Err(status) => Response::status(status),
+    };
+
+    write_response(&stream, response)
+}
+
+// Reads request line and headers into a request without body, returns the status to reply with when they are
+// malformed or too large.
+fn read_head(reader: &mut impl BufRead) -> io::Result<Result<Request, u16>> {
+    let request_line = match read_line(reader, MAX_REQUEST_LINE_BYTES)? {
+        Some(request_line) => request_line,
+        None => return Ok(Err(414)),
+    };
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return Ok(Err(400)),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut headers = vec![];
+    let mut header_bytes = 0;
+    loop {
+        let line = match read_line(reader, MAX_HEADER_BYTES - header_bytes)? {
+            Some(line) => line,
+            None => return Ok(Err(431)),
+        };
+        header_bytes += line.len();
+
+        if line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    Ok(Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        headers,
+        body: vec![],
+    }))
+}
+
+// Reads a line of at most limit bytes, None when it is longer. Connection closing mid line ends the line.
+fn read_line(reader: &mut impl BufRead, limit: usize) -> io::Result<Option<String>> {
+    let mut line = vec![];
+    reader
+        .by_ref()
+        .take(limit as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+
+    if line.len() > limit {
+        return Ok(None);
+    }
+
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
+
+// Hands the request to the module whose route matches it, reading its body first.
+fn route(reader: &mut impl BufRead, mut request: Request) -> io::Result<Response> {
+    let (method, path) = (request.method.as_str(), request.path.as_str());
+    let header = |name: &str| {
+        request
+            .headers
+            .iter()
+            .find(|(header_name, _)| header_name == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    // Request is counted in flight from the moment its handler is chosen, so a replica shutting down meanwhile waits
+    // for it rather than stopping while its body is read.
+    let handler = {
+        let routes = ROUTES.get_or_init(Default::default).read().unwrap();
+        let mut matching = routes
+            .iter()
+            .filter(|(route, _)| route.matches(method, path))
+            .map(|(_, handler)| handler);
+
+        // Replicas of a module register the same routes, requests go to the least busy replica of the first match.
+        matching
+            .next()
+            .map(|first| {
+                let module_name = first.module_config.module_name();
+                matching
+                    .filter(|handler| handler.module_config.module_name() == module_name)
+                    .fold(first, |least_busy, handler| {
+                        if handler.in_flight.load(Ordering::SeqCst)
+                            < least_busy.in_flight.load(Ordering::SeqCst)
+                        {
+                            handler
+                        } else {
+                            least_busy
+                        }
+                    })
+                    .clone()
+            })
+            .map(|handler| {
+                let busy =
+                    handler.in_flight.fetch_add(1, Ordering::SeqCst) >= handler.max_concurrency;
+                (InFlight(handler), busy)
+            })
+    };
+
+    let (in_flight, busy) = match handler {
+        Some(handler) => handler,
+        None => return Ok(Response::status(404)),
+    };
+    let handler = &in_flight.0;
+
+    if busy {
+        metrics::counter_increment(
+            &handler.module_config.module_name(),
+            "wasm_http_requests_total",
+            1.0,
+            &[("method", method), ("status", "503")],
+        );
+        return Ok(Response::status(503));
+    }
+
+    // Only bodies framed by Content-Length are supported.
+    if header("transfer-encoding")
+        .is_some_and(|encoding| !encoding.eq_ignore_ascii_case("identity"))
+    {
+        return Ok(Response::status(501));
+    }
+    let content_length = match header("content-length") {
+        Some(value) => match value.parse::<usize>() {
+            Ok(content_length) => content_length,
+            Err(_) => return Ok(Response::status(400)),
+        },
+        None if METHODS_WITH_BODY
+            .iter()
+            .any(|with_body| with_body.eq_ignore_ascii_case(method)) =>
+        {
+            return Ok(Response::status(411))
+        }
+        None => 0,
+    };
+
+    if content_length > handler.max_request_body_bytes {
+        return Ok(Response::status(413));
+    }
+
+    request.body = vec![0; content_length];
+    reader.read_exact(&mut request.body)?;
+
+    Ok(handler.handle(request))
+}
+
+fn write_response(mut writer: &TcpStream, response: Response) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\n",
+        response.status,
+        reason(response.status)
+    )?;
+    for (name, value) in &response.headers {
+        // Framing headers are set by the host.
+        if !name.eq_ignore_ascii_case("content-length") && !name.eq_ignore_ascii_case("connection")
+        {
+            write!(writer, "{name}: {value}\r\n")?;
+        }
+    }
+    write!(
+        writer,
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    )?;
+    writer.write_all(&response.body)?;
+    writer.flush()
+}
+
+impl Handler {
+    // Handles a request in a new instance of the module, traps are returned as '500 Internal Server Error'.
+    fn handle(&self, request: Request) -> Response {
+        let module_name = self.module_config.module_name();
+        let started = Instant::now();
+
+        let parent = request
+            .headers
+            .iter()
+            .find(|(name, _)| name == "traceparent")
+            .and_then(|(_, value)| TraceContext::parse(value));
+        let mut span = Span::start(
+            &format!("{} {}", request.method, request.path),
+            SpanKind::Server,
+            &module_name,
+            parent.as_ref(),
+        );
+        span.set_attribute("http.method", &request.method);
+        span.set_attribute("http.target", &request.path);
+
+        // Host calls made by the handler are traced as part of the request's trace.
+        let mut host_observability = Hostobservability::new(
+            &self.module_config,
+            &self.environment,
+            self.shutdown.clone(),
+        );
+        host_observability.trace_context = Some(span.context());
+
+        let response = self.call(&request, host_observability).unwrap_or_else(|e| {
+            span.set_error(&e.to_string());
+            logging::log(
+                Level::Warn,
+                MODULE_NAME,
+                &format!("Module '{module_name}' failed to handle http request; error = {e}"),
+                &[("module", &module_name), ("path", &request.path)],
+            );
+            Response::status(500)
+        });
+
+        let status = response.status.to_string();
+        span.set_attribute("http.status_code", &status);
+        span.end();
+
+        metrics::counter_increment(
+            &module_name,
+            "wasm_http_requests_total",
+            1.0,
+            &[("method", &request.method), ("status", &status)],
+        );
+        metrics::histogram_record(
+            &module_name,
+            "wasm_http_request_duration_seconds",
+            started.elapsed().as_secs_f64(),
+            &[("method", &request.method)],
+        );
+
+        response
+    }
+
+    fn call(&self, request: &Request, host_observability: Hostobservability) -> Result<Response> {
+        let mut store = super::new_store(
+            &self.runtime.engine,
+            super::instance_wasi(&self.module_config, self.dirs.as_ref())?,
+            &self.module_config,
+            None,
+            host_observability,
+            Box::<WasmhttpfunctionsData>::default(),
+            &self.environment.host_functions,
+        )?;
+
+        let instance = self
+            .runtime
+            .linker
+            .instantiate(&mut store, &self.runtime.module)?;
+        let exports = Wasmhttpfunctions::new(&mut store, &instance, |cx| cx.exports_mut())?;
+
+        let headers: Vec<(&str, &str)> = request
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        let response = exports.handlehttp(
+            &mut store,
+            Httprequest {
+                method: &request.method,
+                path: &request.path,
+                query: &request.query,
+                headers: &headers,
+                body: &request.body,
+            },
+        )?;
+        super::record_runtime_metrics(&mut store);
+
+        if !(MIN_STATUS..=MAX_STATUS).contains(&response.status) {
+            bail!(
+                "Module returned invalid response status '{}'.",
+                response.status
+            );
+        }
+
+        // Header names and values are written as they are, line breaks in them would split the response.
+        if let Some((name, _)) = response
+            .headers
+            .iter()
+            .find(|(name, value)| !is_valid_header(name, value))
+        {
+            bail!(
+                "Module returned invalid response header '{}'.",
+                name.escape_debug()
+            );
+        }
+
+        Ok(Response {
+            status: response.status,
+            headers: response.headers,
+            body: response.body,
+        })
+    }
+}
+
+// Names must be tokens, values must not contain line breaks or other control characters except tab.
+fn is_valid_header(name: &str, value: &str) -> bool {
+    let valid_name = !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    let valid_value = value.chars().all(|c| c == '\t' || !c.is_control());
+
+    valid_name && valid_value
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        203 => "Non-Authoritative Information",
+        204 => "No Content",
+        205 => "Reset Content",
+        206 => "Partial Content",
+        300 => "Multiple Choices",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        402 => "Payment Required",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        407 => "Proxy Authentication Required",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        422 => "Unprocessable Content",
+        425 => "Too Early",
+        426 => "Upgrade Required",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        451 => "Unavailable For Legal Reasons",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        // Status line still needs a reason phrase, clients ignore it.
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn head(request: &str) -> Result<Request, u16> {
+        read_head(&mut request.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn reads_request_line_and_headers() {
+        let request = head("GET /devices/config?id=1 HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/devices/config");
+        assert_eq!(request.query, "id=1");
+        assert_eq!(
+            request.headers,
+            [("host".to_string(), "localhost".to_string())]
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_request_line_and_headers() {
+        let target = "a".repeat(MAX_REQUEST_LINE_BYTES);
+        assert_eq!(
+            head(&format!("GET /{target} HTTP/1.1\r\n\r\n")).err(),
+            Some(414)
+        );
+
+        let header = format!("X-Large: {}\r\n", "a".repeat(1024));
+        let headers = header.repeat(MAX_HEADER_BYTES / header.len() + 1);
+        assert_eq!(
+            head(&format!("GET / HTTP/1.1\r\n{headers}\r\n")).err(),
+            Some(431)
+        );
+
+        assert_eq!(head("\r\n").err(), Some(400));
+    }
+
+    #[test]
+    fn rejects_response_headers_which_would_split_the_response() {
+        assert!(is_valid_header("Content-Type", "text/plain; charset=utf-8"));
+        assert!(!is_valid_header("X-Injected", "a\r\nSet-Cookie: session=1"));
+        assert!(!is_valid_header("X-Injected\r\nSet-Cookie", "session=1"));
+        assert!(!is_valid_header("", "value"));
+    }
+}
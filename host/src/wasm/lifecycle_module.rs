@@ -0,0 +1,119 @@
+wit_bindgen_wasmtime::import!("../wits/wasmlifecyclefunctions.wit");
+
+// Runs any module exporting the common lifecycle (init, ontick, shutdown), whatever the module does.
+use super::runner::{CoreInstance, Instance, Lifecycle};
+use super::HostEnvironment;
+use crate::config::ModuleConfiguration;
+use crate::registry::{self, ReplicaStatus};
+use crate::shutdown::ShutdownSignal;
+use anyhow::Result;
+use wasmlifecyclefunctions::{Wasmlifecyclefunctions, WasmlifecyclefunctionsData};
+use wit_bindgen_wasmtime::wasmtime::Store;
+
+// Host settings for a module run by the host, on top of its host configuration.
+#[derive(Clone, Default)]
+pub struct Options {
+    // Address of a socket the host pre-opens for the module, passed to its init.
+    pub socket_address: Option<String>,
+    // Additional host the module can post to, on top of its configured http policy.
+    pub allowed_host: Option<String>,
+}
+
+pub fn run_module(
+    wasm_path: &str,
+    wasm_config_path: &str,
+    module_config: &ModuleConfiguration,
+    options: &Options,
+    environment: &HostEnvironment,
+    shutdown: ShutdownSignal,
+) -> Result<()> {
+    let socket_fds: &[u32] = match options.socket_address {
+        Some(_) => &[super::PREOPENED_SOCKET_FD],
+        None => &[],
+    };
+
+    // Instantiates a version of the module and calls its init, called again for each new version when hot reload
+    // is enabled and for the module's canary version. Each version may be a core module or a component.
+    let start = |wasm_path: &str, wasm_config_path: &str| -> Result<Box<dyn Instance>> {
+        if super::is_component(wasm_path)? {
+            #[cfg(feature = "component")]
+            return Ok(Box::new(super::component::start(
+                wasm_path,
+                wasm_config_path,
+                module_config,
+                options,
+                environment,
+                &shutdown,
+            )?));
+
+            #[cfg(not(feature = "component"))]
+            anyhow::bail!(
+                "Module '{}' is a component, the host was built without feature 'component'.",
+                module_config.module_name()
+            );
+        }
+
+        let (wasm_exports, mut store) = super::instantiate::<WasmlifecyclefunctionsData, _>(
+            wasm_path,
+            |store: &mut Store<super::Context>, module, linker| {
+                // Instantiates wasm module instance from auto generated binding code.
+                let funcs = Wasmlifecyclefunctions::instantiate(store, module, linker, |cx| {
+                    cx.exports_mut()
+                });
+
+                Ok(funcs?.0)
+            },
+            |module_config| super::default_wasi(module_config, options.socket_address.clone()),
+            module_config,
+            options.allowed_host.clone(),
+            super::Hostobservability::new(module_config, environment, shutdown.clone()),
+            &environment.host_functions,
+        )?;
+
+        // Modules with a socket serve connections from within init, so they are running from here.
+        if !socket_fds.is_empty() {
+            registry::set_replica_status(
+                &module_config.module_name(),
+                module_config.replica(),
+                ReplicaStatus::Running,
+            );
+        }
+
+        // Call init of guest/wasm modules, module schedules its work on timers during init.
+        wasm_exports.init(&mut store, wasm_config_path, socket_fds)?;
+        super::record_runtime_metrics(&mut store);
+
+        Ok(Box::new(CoreInstance::new(
+            wasm_exports,
+            store,
+            Lifecycle {
+                on_tick: |wasm_exports, store, timer_id| {
+                    wasm_exports.ontick(store, timer_id)?;
+                    Ok(())
+                },
+                shutdown: |wasm_exports, store, deadline_in_milliseconds| {
+                    wasm_exports.shutdown(store, deadline_in_milliseconds)?;
+                    Ok(())
+                },
+            },
+        )))
+    };
+
+    if socket_fds.is_empty() {
+        // Host calls the module on each tick until shutdown, then gives it until its deadline to clean up.
+        return super::runner::run(module_config, wasm_path, wasm_config_path, &shutdown, start);
+    }
+
+    // Socket cannot be bound by a second instance, so modules with a socket are not hot reloaded or canaried. They
+    // return from init once they have drained after shutdown is requested.
+    let mut instance = start(wasm_path, wasm_config_path)?;
+
+    // Module stays loaded until the host shuts down, then gets until its deadline to clean up.
+    shutdown.wait_until_requested();
+    let deadline = shutdown.deadline().unwrap_or_default();
+
+    instance.shutdown(deadline.as_millis() as u64)?;
+    instance.record_runtime_metrics();
+
+    Ok(())
+}
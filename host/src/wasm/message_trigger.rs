@@ -0,0 +1,286 @@
+wit_bindgen_wasmtime::import!("../wits/wasmmessagefunctions.wit");
+
+// Message triggered modules have no init loop, the host reads their trigger topics on their behalf and calls their
+// 'handlemessage' export for each message, in a new instance from the module's instance pool. Messages the module
+// fails to handle are retried, then published to the trigger's dead letter topic.
+use super::hostobservability::Hostobservability as _;
+use super::preopens::ModuleDirs;
+use super::{HostEnvironment, Hostobservability, Runtime, MODULE_NAME};
+use crate::config::{is_topic_allowed, ModuleConfiguration};
+use crate::logging::{self, Level};
+use crate::metrics;
+use crate::shutdown::ShutdownSignal;
+use crate::tracing::{Span, SpanKind, TraceContext};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    sync::{mpsc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+use wasmmessagefunctions::{Wasmmessagefunctions, WasmmessagefunctionsData};
+
+pub struct MessageTrigger {
+    // Topics the host reads on the module's behalf, the module must be allowed to read them.
+    pub topics: Vec<String>,
+    // Messages handled at once, no further messages are read until a handler finishes.
+    pub max_concurrency: usize,
+    // Instances the pooling allocator reserves memory and tables for up front.
+    pub pool_size: u32,
+    // Time the host waits before reading again once all trigger topics were empty.
+    pub poll_interval: Duration,
+    // Times a message is handed to the module, each in a new instance, before it is given up on.
+    pub max_attempts: u32,
+    // Topic messages are published to once all attempts failed, dropped when not configured. Module must be allowed
+    // to publish to it.
+    pub deadletter_topic: Option<String>,
+}
+
+struct Message {
+    topic: String,
+    payload: String,
+    trace_context: Option<TraceContext>,
+}
+
+pub fn run_module(
+    wasm_path: &str,
+    module_config: &ModuleConfiguration,
+    environment: &HostEnvironment,
+    shutdown: ShutdownSignal,
+) -> Result<()> {
+    let module_name = module_config.module_name();
+    let trigger = module_config
+        .message_trigger()
+        .ok_or_else(|| anyhow!("Module '{module_name}' has no message trigger configured."))?;
+
+    // Module is compiled once, each message gets an instance allocated from the pool.
+    let config = super::pooled_config(trigger.pool_size.max(trigger.max_concurrency as u32));
+    let mut runtime = super::prepare(
+        wasm_path,
+        module_config,
+        config,
+        &environment.host_functions,
+    )?;
+    Wasmmessagefunctions::add_to_linker(&mut runtime.linker, |cx: &mut super::Context| {
+        cx.exports_mut()
+    })?;
+    // Directories are opened once, not for every message.
+    let dirs = super::module_dirs(module_config)?;
+
+    if let Some(topic) = &trigger.deadletter_topic {
+        if !is_topic_allowed(&module_config.publish_topics(), topic) {
+            bail!("Module '{module_name}' is not allowed to publish to its dead letter topic '{topic}'.");
+        }
+    }
+
+    logging::log(
+        Level::Info,
+        MODULE_NAME,
+        &format!(
+            "Module '{module_name}' triggered by messages on '{}'.",
+            trigger.topics.join(" ")
+        ),
+        &[
+            ("module", &module_name),
+            ("max_concurrency", &trigger.max_concurrency.to_string()),
+        ],
+    );
+
+    // Host reads messages with the module's own topic permissions.
+    let mut reader = Hostobservability::new(module_config, environment, shutdown.clone());
+
+    // Messages are handed over only once a handler is free to take them.
+    let (sender, receiver) = mpsc::sync_channel::<Message>(0);
+    let receiver = Mutex::new(receiver);
+
+    thread::scope(|scope| {
+        for _ in 0..trigger.max_concurrency.max(1) {
+            scope.spawn(|| loop {
+                let message = match receiver.lock().unwrap().recv() {
+                    Ok(message) => message,
+                    Err(_) => return,
+                };
+
+                handle(
+                    &runtime,
+                    dirs.as_ref(),
+                    &trigger,
+                    module_config,
+                    environment,
+                    &shutdown,
+                    message,
+                );
+            });
+        }
+
+        // Handlers finish messages they have already taken after shutdown is requested.
+        while !shutdown.is_requested() {
+            let mut read_any = false;
+
+            for topic in &trigger.topics {
+                if let Some(message) = read(&mut reader, topic) {
+                    read_any = true;
+                    sender.send(message).unwrap();
+                }
+            }
+
+            if !read_any {
+                shutdown.wait_requested(trigger.poll_interval);
+            }
+        }
+
+        drop(sender);
+    });
+
+    Ok(())
+}
+
+// Reads next message from a topic on the module's behalf, None when the topic is empty or the read was denied.
+fn read(reader: &mut Hostobservability, topic: &str) -> Option<Message> {
+    let payload = reader.read(topic).ok()??;
+
+    Some(Message {
+        topic: topic.to_string(),
+        payload,
+        trace_context: reader.trace_context.take(),
+    })
+}
+
+// Handles a message in a new instance of the module for each attempt, messages failing every attempt are published
+// to the dead letter topic, or logged and dropped without one.
+fn handle(
+    runtime: &Runtime,
+    dirs: Option<&ModuleDirs>,
+    trigger: &MessageTrigger,
+    module_config: &ModuleConfiguration,
+    environment: &HostEnvironment,
+    shutdown: &ShutdownSignal,
+    message: Message,
+) {
+    let module_name = module_config.module_name();
+    let started = Instant::now();
+
+    let mut span = Span::start(
+        &format!("{} process", message.topic),
+        SpanKind::Consumer,
+        &module_name,
+        message.trace_context.as_ref(),
+    );
+    span.set_attribute("messaging.destination.name", &message.topic);
+
+    let mut attempt = 0;
+    let (outcome, error) = loop {
+        attempt += 1;
+
+        let (outcome, error) = match handle_attempt(
+            runtime,
+            dirs,
+            module_config,
+            environment,
+            shutdown,
+            &message,
+            &span,
+        ) {
+            Ok(Ok(())) => ("success", None),
+            Ok(Err(e)) => ("error", Some(e)),
+            Err(e) => ("trap", Some(e.to_string())),
+        };
+
+        match error {
+            // Message is not retried once shutdown is requested, it is dead lettered or dropped instead.
+            Some(error) if attempt < trigger.max_attempts && !shutdown.is_requested() => {
+                logging::log(
+                    Level::Warn,
+                    MODULE_NAME,
+                    &format!(
+                        "Module '{module_name}' failed to handle message on attempt {attempt} of {}, retrying; error = {error}",
+                        trigger.max_attempts
+                    ),
+                    &[("module", &module_name), ("topic", &message.topic)],
+                );
+            }
+            error => break (outcome, error),
+        }
+    };
+
+    if let Some(error) = error {
+        span.set_error(&error);
+
+        match &trigger.deadletter_topic {
+            Some(deadletter_topic) => {
+                logging::log(
+                    Level::Warn,
+                    MODULE_NAME,
+                    &format!(
+                        "Module '{module_name}' failed to handle message after {attempt} attempts, moving it to '{deadletter_topic}'; error = {error}"
+                    ),
+                    &[("module", &module_name), ("topic", &message.topic)],
+                );
+
+                // Published on the module's behalf, as part of the message's trace.
+                let mut publisher = Hostobservability::new(
+                    module_config,
+                    environment,
+                    shutdown.clone(),
+                );
+                publisher.trace_context = message.trace_context.as_ref().map(|_| span.context());
+                publisher.publish(deadletter_topic, &message.payload);
+            }
+            None => logging::log(
+                Level::Warn,
+                MODULE_NAME,
+                &format!(
+                    "Module '{module_name}' failed to handle message after {attempt} attempts, dropping it; error = {error}"
+                ),
+                &[("module", &module_name), ("topic", &message.topic)],
+            ),
+        }
+    }
+    span.end();
+
+    metrics::counter_increment(
+        &module_name,
+        "wasm_messages_handled_total",
+        1.0,
+        &[("topic", &message.topic), ("result", outcome)],
+    );
+    metrics::histogram_record(
+        &module_name,
+        "wasm_message_handle_duration_seconds",
+        started.elapsed().as_secs_f64(),
+        &[("topic", &message.topic)],
+    );
+}
+
+// Calls the module's 'handlemessage' export in a new instance, host calls it makes are traced as part of the
+// message's trace.
+fn handle_attempt(
+    runtime: &Runtime,
+    dirs: Option<&ModuleDirs>,
+    module_config: &ModuleConfiguration,
+    environment: &HostEnvironment,
+    shutdown: &ShutdownSignal,
+    message: &Message,
+    span: &Span,
+) -> Result<Result<(), String>> {
+    let mut host_observability =
+        Hostobservability::new(module_config, environment, shutdown.clone());
+    host_observability.trace_context = message.trace_context.as_ref().map(|_| span.context());
+
+    let mut store = super::new_store(
+        &runtime.engine,
+        super::instance_wasi(module_config, dirs)?,
+        module_config,
+        None,
+        host_observability,
+        Box::<WasmmessagefunctionsData>::default(),
+        &environment.host_functions,
+    )?;
+
+    let instance = runtime.linker.instantiate(&mut store, &runtime.module)?;
+    let exports = Wasmmessagefunctions::new(&mut store, &instance, |cx| cx.exports_mut())?;
+
+    let result = exports.handlemessage(&mut store, &message.topic, &message.payload)?;
+    super::record_runtime_metrics(&mut store);
+
+    Ok(result)
+}
@@ -0,0 +1,659 @@
+// Host implementation of the 'wasi_experimental_http' imports used by wasi-experimental-http guest crate,
+// enforcing a per module outbound http policy.
+use crate::logging::{self, Level};
+use crate::tracing::{Span, SpanKind, TraceContext};
+use anyhow::{anyhow, Result};
+use reqwest::{
+    blocking::Client,
+    header::{HeaderMap, HeaderName, HeaderValue},
+    redirect, Method, Url,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Read,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use wit_bindgen_wasmtime::wasmtime::{Caller, Linker, Memory};
+
+const HTTP_MODULE: &str = "wasi_experimental_http";
+const ALL_HOSTS: &str = "*";
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 42;
+const DEFAULT_REQUEST_TIMEOUT_IN_MILLISECONDS: u64 = 30000;
+const TRACEPARENT_HEADER: &str = "traceparent";
+
+// Error codes as defined by wasi-experimental-http guest crate.
+const ERROR_INVALID_HANDLE: u32 = 1;
+const ERROR_MEMORY_NOT_FOUND: u32 = 2;
+const ERROR_MEMORY_ACCESS: u32 = 3;
+const ERROR_BUFFER_TOO_SMALL: u32 = 4;
+const ERROR_HEADER_NOT_FOUND: u32 = 5;
+const ERROR_UTF8: u32 = 6;
+const ERROR_DESTINATION_NOT_ALLOWED: u32 = 7;
+const ERROR_INVALID_METHOD: u32 = 8;
+const ERROR_INVALID_ENCODING: u32 = 9;
+const ERROR_INVALID_URL: u32 = 10;
+const ERROR_REQUEST: u32 = 11;
+const ERROR_TOO_MANY_SESSIONS: u32 = 13;
+
+// Requests of every instance of a module by module name, limits apply to the module rather than to each instance, as
+// triggered modules get a new instance per message or request.
+static MODULE_REQUESTS: OnceLock<Mutex<HashMap<String, Arc<Mutex<ModuleRequests>>>>> =
+    OnceLock::new();
+
+#[derive(Default)]
+struct ModuleRequests {
+    // Requests sent and not yet answered with their full response body.
+    in_flight: usize,
+    // Start times of requests in the last second.
+    recent: VecDeque<Instant>,
+    // Client shared by the module's instances, built on the module's first request and again once its request
+    // timeout changes, as each client runs its own thread.
+    client: Option<(Duration, Client)>,
+}
+
+fn module_requests(module_name: &str) -> Arc<Mutex<ModuleRequests>> {
+    MODULE_REQUESTS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry(module_name.to_string())
+        .or_default()
+        .clone()
+}
+
+// Counts a request in flight until dropped.
+struct InFlight(Arc<Mutex<ModuleRequests>>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().in_flight -= 1;
+    }
+}
+
+// Destination a module may send requests to e.g. 'https://*.m.pipedream.net' or 'api.example.com:8443'.
+#[derive(Clone, Debug)]
+pub struct AllowedHost {
+    scheme: Option<String>,
+    host: String,
+    port: Option<u16>,
+}
+
+impl AllowedHost {
+    pub fn parse(pattern: &str) -> Result<Self> {
+        let (scheme, rest) = match pattern.split_once("://") {
+            Some((scheme, rest)) => (Some(scheme.to_lowercase()), rest),
+            None => (None, pattern),
+        };
+        let rest = rest.trim_end_matches('/');
+
+        // IPv6 literals are bracketed, as in urls e.g. '[::1]:8080', their colons are not port separators.
+        let (host, port) = match rest.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+            Some((address, "")) => (&rest[..address.len() + 2], None),
+            Some((address, port)) => match port.strip_prefix(':') {
+                Some(port) => (&rest[..address.len() + 2], Some(port)),
+                None => return Err(anyhow!("Invalid allowed host '{pattern}'.")),
+            },
+            None => match rest.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (rest, None),
+            },
+        };
+        let port =
+            match port {
+                Some(port) => Some(port.parse::<u16>().map_err(|e| {
+                    anyhow!("Invalid allowed host '{pattern}', port '{port}': {e}.")
+                })?),
+                None => None,
+            };
+
+        Ok(Self {
+            scheme,
+            host: host.to_lowercase(),
+            port,
+        })
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let host = url.host_str().unwrap_or("").to_lowercase();
+
+        let host_matches = match self.host.strip_prefix("*.") {
+            // Wildcard matches any sub domain, but not the domain itself.
+            Some(domain) => host.ends_with(&format!(".{domain}")),
+            None => self.host == ALL_HOSTS || self.host == host,
+        };
+
+        host_matches
+            && self
+                .scheme
+                .as_deref()
+                .map_or(true, |scheme| scheme == url.scheme())
+            && self
+                .port
+                .map_or(true, |port| Some(port) == url.port_or_known_default())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HttpPolicy {
+    pub allowed_hosts: Vec<AllowedHost>,
+    // Empty list allows all methods.
+    pub allowed_methods: Vec<String>,
+    pub max_request_body_bytes: Option<usize>,
+    pub max_response_body_bytes: Option<usize>,
+    pub max_concurrent_requests: usize,
+    pub max_requests_per_second: Option<usize>,
+    pub request_timeout: Duration,
+}
+
+impl Default for HttpPolicy {
+    // Default policy denies all destinations.
+    fn default() -> Self {
+        Self {
+            allowed_hosts: vec![],
+            allowed_methods: vec![],
+            max_request_body_bytes: None,
+            max_response_body_bytes: None,
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+            max_requests_per_second: None,
+            request_timeout: Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_IN_MILLISECONDS),
+        }
+    }
+}
+
+struct HttpResponse {
+    headers: HeaderMap,
+    body: Vec<u8>,
+    body_read_position: usize,
+}
+
+// Per module outbound http state, holds responses until the module closes them.
+pub struct OutboundHttp {
+    module_name: String,
+    policy: HttpPolicy,
+    responses: HashMap<u32, HttpResponse>,
+    next_handle: u32,
+    requests: Arc<Mutex<ModuleRequests>>,
+}
+
+impl OutboundHttp {
+    pub fn new(module_name: &str, policy: HttpPolicy) -> Self {
+        Self {
+            module_name: module_name.to_string(),
+            policy,
+            responses: HashMap::new(),
+            next_handle: 1,
+            requests: module_requests(module_name),
+        }
+    }
+
+    // Returns the module's shared client.
+    fn client(&self) -> Result<Client> {
+        let mut requests = self.requests.lock().unwrap();
+        if let Some((timeout, client)) = &requests.client {
+            if *timeout == self.policy.request_timeout {
+                return Ok(client.clone());
+            }
+        }
+
+        // Redirects are not followed, they could lead to destinations the policy does not allow.
+        let client = Client::builder()
+            .timeout(self.policy.request_timeout)
+            .redirect(redirect::Policy::none())
+            .build()?;
+        requests.client = Some((self.policy.request_timeout, client.clone()));
+
+        Ok(client)
+    }
+
+    // Logs the denied request against the module and returns the error code for the guest.
+    fn deny(&self, error_code: u32, reason: &str) -> u32 {
+        logging::log(
+            Level::Warn,
+            super::MODULE_NAME,
+            &format!(
+                "Denied outbound http request from module '{}': {reason}.",
+                self.module_name
+            ),
+            &[("module", &self.module_name)],
+        );
+
+        error_code
+    }
+
+    // Starts a request if the module is within its concurrency and requests per second limits, the request counts as
+    // in flight until the returned guard is dropped.
+    fn start_request(&self) -> Result<InFlight, u32> {
+        let mut requests = self.requests.lock().unwrap();
+        let now = Instant::now();
+
+        while let Some(oldest) = requests.recent.front() {
+            if now.duration_since(*oldest) < Duration::from_secs(1) {
+                break;
+            }
+            requests.recent.pop_front();
+        }
+
+        if requests.in_flight >= self.policy.max_concurrent_requests {
+            drop(requests);
+            return Err(self.deny(ERROR_TOO_MANY_SESSIONS, "too many concurrent requests"));
+        }
+        if matches!(self.policy.max_requests_per_second, Some(limit) if requests.recent.len() >= limit)
+        {
+            drop(requests);
+            return Err(self.deny(ERROR_TOO_MANY_SESSIONS, "request rate limit exceeded"));
+        }
+
+        requests.in_flight += 1;
+        requests.recent.push_back(now);
+
+        Ok(InFlight(self.requests.clone()))
+    }
+
+    // Traces the request as a client span when the module sends a 'traceparent' header, continuing its trace.
+    fn request(
+        &mut self,
+        url: &str,
+        method: &str,
+        headers: &str,
+        body: Vec<u8>,
+    ) -> Result<(u16, u32), u32> {
+        let mut headers = header_map(headers)?;
+        let parent = headers
+            .get(TRACEPARENT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(TraceContext::parse);
+
+        let mut span = Span::start(
+            &format!("HTTP {}", method.to_uppercase()),
+            SpanKind::Client,
+            &self.module_name,
+            parent.as_ref(),
+        );
+        span.set_attribute("http.request.method", &method.to_uppercase());
+        span.set_attribute("url.full", url);
+
+        // Header is rewritten so the receiver's spans are children of the client span.
+        if parent.is_some() {
+            if let Ok(traceparent) = HeaderValue::from_str(&span.context().traceparent()) {
+                headers.insert(TRACEPARENT_HEADER, traceparent);
+            }
+        }
+
+        let result = self.send(url, method, headers, body);
+
+        match &result {
+            Ok((status_code, _)) => {
+                span.set_attribute("http.response.status_code", &status_code.to_string())
+            }
+            Err(error_code) => {
+                span.set_error(&format!("request failed with error code {error_code}"))
+            }
+        }
+        span.end();
+
+        result
+    }
+
+    fn send(
+        &mut self,
+        url: &str,
+        method: &str,
+        headers: HeaderMap,
+        body: Vec<u8>,
+    ) -> Result<(u16, u32), u32> {
+        let url = Url::parse(url).map_err(|_| ERROR_INVALID_URL)?;
+        let method = Method::from_bytes(method.to_uppercase().as_bytes())
+            .map_err(|_| ERROR_INVALID_METHOD)?;
+
+        if !self
+            .policy
+            .allowed_hosts
+            .iter()
+            .any(|host| host.matches(&url))
+        {
+            return Err(self.deny(
+                ERROR_DESTINATION_NOT_ALLOWED,
+                &format!("destination '{url}' is not allowed"),
+            ));
+        }
+
+        if !self.policy.allowed_methods.is_empty()
+            && !self
+                .policy
+                .allowed_methods
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(method.as_str()))
+        {
+            return Err(self.deny(
+                ERROR_INVALID_METHOD,
+                &format!("method '{method}' is not allowed"),
+            ));
+        }
+
+        if matches!(self.policy.max_request_body_bytes, Some(max) if body.len() > max) {
+            return Err(self.deny(
+                ERROR_BUFFER_TOO_SMALL,
+                &format!("request body of {} bytes exceeds limit", body.len()),
+            ));
+        }
+
+        let client = self.client().map_err(|e| {
+            logging::log(
+                Level::Error,
+                super::MODULE_NAME,
+                &format!(
+                    "Failed to create http client for module '{}'; error = {e}",
+                    self.module_name
+                ),
+                &[("module", &self.module_name)],
+            );
+            ERROR_REQUEST
+        })?;
+        let in_flight = self.start_request()?;
+
+        let response = client
+            .request(method, url)
+            .headers(headers)
+            .body(body)
+            .send()
+            .map_err(|e| {
+                logging::log(
+                    Level::Error,
+                    super::MODULE_NAME,
+                    &format!(
+                        "Outbound http request from module '{}' failed: {e}.",
+                        self.module_name
+                    ),
+                    &[("module", &self.module_name)],
+                );
+                ERROR_REQUEST
+            })?;
+
+        let status_code = response.status().as_u16();
+        let headers = response.headers().clone();
+
+        // Read one byte more than allowed so oversized responses can be detected.
+        let mut body = vec![];
+        let read_limit = self
+            .policy
+            .max_response_body_bytes
+            .map_or(u64::MAX, |max| max as u64 + 1);
+        response
+            .take(read_limit)
+            .read_to_end(&mut body)
+            .map_err(|_| ERROR_REQUEST)?;
+
+        drop(in_flight);
+
+        if matches!(self.policy.max_response_body_bytes, Some(max) if body.len() > max) {
+            return Err(self.deny(ERROR_BUFFER_TOO_SMALL, "response body exceeds limit"));
+        }
+
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.responses.insert(
+            handle,
+            HttpResponse {
+                headers,
+                body,
+                body_read_position: 0,
+            },
+        );
+
+        Ok((status_code, handle))
+    }
+}
+
+// Parses headers serialised by the guest as 'name:value' lines.
+fn header_map(headers: &str) -> Result<HeaderMap, u32> {
+    let mut header_map = HeaderMap::new();
+
+    for line in headers.lines().filter(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or(ERROR_INVALID_ENCODING)?;
+        header_map.insert(
+            HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| ERROR_INVALID_ENCODING)?,
+            HeaderValue::from_str(value.trim()).map_err(|_| ERROR_INVALID_ENCODING)?,
+        );
+    }
+
+    Ok(header_map)
+}
+
+fn memory<T>(caller: &mut Caller<'_, T>) -> Result<Memory, u32> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or(ERROR_MEMORY_NOT_FOUND)
+}
+
+fn read_bytes<T>(caller: &mut Caller<'_, T>, ptr: u32, len: u32) -> Result<Vec<u8>, u32> {
+    let memory = memory(caller)?;
+    let mut buf = vec![0; len as usize];
+    memory
+        .read(&caller, ptr as usize, &mut buf)
+        .map_err(|_| ERROR_MEMORY_ACCESS)?;
+
+    Ok(buf)
+}
+
+fn read_string<T>(caller: &mut Caller<'_, T>, ptr: u32, len: u32) -> Result<String, u32> {
+    String::from_utf8(read_bytes(caller, ptr, len)?).map_err(|_| ERROR_UTF8)
+}
+
+fn write_bytes<T>(caller: &mut Caller<'_, T>, ptr: u32, bytes: &[u8]) -> Result<(), u32> {
+    let memory = memory(caller)?;
+    memory
+        .write(caller, ptr as usize, bytes)
+        .map_err(|_| ERROR_MEMORY_ACCESS)
+}
+
+// Copies bytes to guest buffer and writes the number of bytes written, fails if buffer is too small.
+fn write_buffer<T>(
+    caller: &mut Caller<'_, T>,
+    bytes: &[u8],
+    buf_ptr: u32,
+    buf_len: u32,
+    written_ptr: u32,
+) -> Result<(), u32> {
+    if bytes.len() > buf_len as usize {
+        return Err(ERROR_BUFFER_TOO_SMALL);
+    }
+
+    write_bytes(caller, buf_ptr, bytes)?;
+    write_bytes(caller, written_ptr, &(bytes.len() as u32).to_le_bytes())
+}
+
+fn to_error_code(result: Result<(), u32>) -> u32 {
+    result.err().unwrap_or(0)
+}
+
+pub fn add_to_linker<T>(
+    linker: &mut Linker<T>,
+    get_cx: impl Fn(&mut T) -> &mut OutboundHttp + Send + Sync + Copy + 'static,
+) -> Result<()> {
+    linker.func_wrap(
+        HTTP_MODULE,
+        "req",
+        move |mut caller: Caller<'_, T>,
+              url_ptr: u32,
+              url_len: u32,
+              method_ptr: u32,
+              method_len: u32,
+              req_headers_ptr: u32,
+              req_headers_len: u32,
+              req_body_ptr: u32,
+              req_body_len: u32,
+              status_code_ptr: u32,
+              res_handle_ptr: u32|
+              -> u32 {
+            to_error_code((|| {
+                let url = read_string(&mut caller, url_ptr, url_len)?;
+                let method = read_string(&mut caller, method_ptr, method_len)?;
+                let headers = read_string(&mut caller, req_headers_ptr, req_headers_len)?;
+                let body = read_bytes(&mut caller, req_body_ptr, req_body_len)?;
+
+                let (status_code, handle) =
+                    get_cx(caller.data_mut()).request(&url, &method, &headers, body)?;
+
+                write_bytes(&mut caller, status_code_ptr, &status_code.to_le_bytes())?;
+                write_bytes(&mut caller, res_handle_ptr, &handle.to_le_bytes())
+            })())
+        },
+    )?;
+
+    linker.func_wrap(
+        HTTP_MODULE,
+        "close",
+        move |mut caller: Caller<'_, T>, handle: u32| -> u32 {
+            match get_cx(caller.data_mut()).responses.remove(&handle) {
+                Some(_) => 0,
+                None => ERROR_INVALID_HANDLE,
+            }
+        },
+    )?;
+
+    linker.func_wrap(
+        HTTP_MODULE,
+        "header_get",
+        move |mut caller: Caller<'_, T>,
+              handle: u32,
+              name_ptr: u32,
+              name_len: u32,
+              value_ptr: u32,
+              value_len: u32,
+              value_written_ptr: u32|
+              -> u32 {
+            to_error_code((|| {
+                let name = read_string(&mut caller, name_ptr, name_len)?;
+                let value = get_cx(caller.data_mut())
+                    .responses
+                    .get(&handle)
+                    .ok_or(ERROR_INVALID_HANDLE)?
+                    .headers
+                    .get(name.as_str())
+                    .ok_or(ERROR_HEADER_NOT_FOUND)?
+                    .as_bytes()
+                    .to_vec();
+
+                write_buffer(&mut caller, &value, value_ptr, value_len, value_written_ptr)
+            })())
+        },
+    )?;
+
+    linker.func_wrap(
+        HTTP_MODULE,
+        "headers_get_all",
+        move |mut caller: Caller<'_, T>,
+              handle: u32,
+              buf_ptr: u32,
+              buf_len: u32,
+              buf_written_ptr: u32|
+              -> u32 {
+            to_error_code((|| {
+                let headers = get_cx(caller.data_mut())
+                    .responses
+                    .get(&handle)
+                    .ok_or(ERROR_INVALID_HANDLE)?
+                    .headers
+                    .iter()
+                    .map(|(name, value)| {
+                        format!("{}:{}\n", name, value.to_str().unwrap_or_default())
+                    })
+                    .collect::<String>();
+
+                write_buffer(
+                    &mut caller,
+                    headers.as_bytes(),
+                    buf_ptr,
+                    buf_len,
+                    buf_written_ptr,
+                )
+            })())
+        },
+    )?;
+
+    linker.func_wrap(
+        HTTP_MODULE,
+        "body_read",
+        move |mut caller: Caller<'_, T>,
+              handle: u32,
+              buf_ptr: u32,
+              buf_len: u32,
+              buf_read_ptr: u32|
+              -> u32 {
+            to_error_code((|| {
+                let response = get_cx(caller.data_mut())
+                    .responses
+                    .get_mut(&handle)
+                    .ok_or(ERROR_INVALID_HANDLE)?;
+
+                // Body is read in chunks of the guest's buffer size, zero bytes read signals the end.
+                let start = response.body_read_position;
+                let end = (start + buf_len as usize).min(response.body.len());
+                response.body_read_position = end;
+                let chunk = response.body[start..end].to_vec();
+
+                write_buffer(&mut caller, &chunk, buf_ptr, buf_len, buf_read_ptr)
+            })())
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allows(pattern: &str, url: &str) -> bool {
+        AllowedHost::parse(pattern)
+            .unwrap()
+            .matches(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn wildcard_matches_sub_domains_only() {
+        assert!(allows("*.example.com", "https://api.example.com/path"));
+        assert!(allows("*.example.com", "http://a.b.example.com"));
+        assert!(!allows("*.example.com", "https://example.com"));
+        assert!(!allows("*.example.com", "https://api.example.com.evil.net"));
+        assert!(!allows("*.example.com", "https://notexample.com"));
+        assert!(allows("*", "https://anything.net"));
+    }
+
+    #[test]
+    fn matches_scheme_and_host_case_insensitively() {
+        assert!(allows("HTTPS://API.Example.com", "https://api.example.com"));
+        assert!(!allows("https://api.example.com", "http://api.example.com"));
+        assert!(allows("api.example.com", "http://api.example.com"));
+    }
+
+    #[test]
+    fn port_defaults_to_scheme_port() {
+        assert!(allows(
+            "api.example.com:8443",
+            "https://api.example.com:8443"
+        ));
+        assert!(!allows("api.example.com:8443", "https://api.example.com"));
+        assert!(allows(
+            "https://api.example.com:443",
+            "https://api.example.com"
+        ));
+        assert!(allows("api.example.com", "https://api.example.com:8443"));
+    }
+
+    #[test]
+    fn parses_ipv6_literals() {
+        assert!(allows("[::1]:8080", "http://[::1]:8080/"));
+        assert!(!allows("[::1]:8080", "http://[::1]:9090/"));
+        assert!(allows("http://[::1]", "http://[::1]:9090/"));
+    }
+
+    #[test]
+    fn fails_on_invalid_port() {
+        assert!(AllowedHost::parse("api.example.com:https").is_err());
+        assert!(AllowedHost::parse("api.example.com:70000").is_err());
+        assert!(AllowedHost::parse("[::1]8080").is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::Result;
+use std::{
+    any::Any,
+    fs,
+    path::{Path, PathBuf},
+};
+use wasi_common::{
+    dir::{ReaddirCursor, ReaddirEntity, WasiDir},
+    file::{FdFlags, Filestat, OFlags, WasiFile},
+    Error, ErrorExt, SystemTimeSpec, WasiCtx,
+};
+use wasmtime_wasi::Dir;
+
+// Guest path where each module's private scratch directory is mounted.
+pub const SCRATCH_GUEST_PATH: &str = "/scratch";
+
+// Host directory made available to a module at a guest path.
+#[derive(Clone, Debug)]
+pub struct Preopen {
+    pub host_path: String,
+    pub guest_path: String,
+    pub read_only: bool,
+}
+
+// Declared preopens and the module's private scratch directory, opened once for all instances of the module.
+pub struct ModuleDirs {
+    dirs: Vec<OpenedDir>,
+}
+
+struct OpenedDir {
+    file: fs::File,
+    guest_path: String,
+    read_only: bool,
+}
+
+impl ModuleDirs {
+    pub fn open(preopens: &[Preopen], scratch_dir: &Path) -> Result<Self> {
+        let mut dirs = vec![];
+        for preopen in preopens {
+            dirs.push(OpenedDir {
+                file: fs::File::open(&preopen.host_path)?,
+                guest_path: preopen.guest_path.clone(),
+                read_only: preopen.read_only,
+            });
+        }
+
+        // Scratch directory is created by the host and is private to the module.
+        fs::create_dir_all(scratch_dir)?;
+        dirs.push(OpenedDir {
+            file: fs::File::open(scratch_dir)?,
+            guest_path: SCRATCH_GUEST_PATH.to_string(),
+            read_only: false,
+        });
+
+        Ok(Self { dirs })
+    }
+
+    // Adds the directories to the WASI context, nothing else is accessible. Each context gets its own handles, so
+    // directories are not opened again per instance.
+    pub fn preopen(&self, wasi_ctx: &mut WasiCtx) -> Result<()> {
+        for opened in &self.dirs {
+            let dir = Dir::from_std_file(opened.file.try_clone()?);
+            let dir: Box<dyn WasiDir> = Box::new(wasmtime_wasi::sync::dir::Dir::from_cap_std(dir));
+
+            let dir = if opened.read_only {
+                Box::new(ReadOnlyDir(dir))
+            } else {
+                dir
+            };
+
+            wasi_ctx.push_preopened_dir(dir, &opened.guest_path)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Wraps a directory, rejecting any operation which would modify it or its contents.
+pub struct ReadOnlyDir(Box<dyn WasiDir>);
+
+#[async_trait::async_trait]
+impl WasiDir for ReadOnlyDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        fdflags: FdFlags,
+    ) -> Result<Box<dyn WasiFile>, Error> {
+        if write
+            || oflags.contains(OFlags::CREATE)
+            || oflags.contains(OFlags::TRUNCATE)
+            || fdflags.contains(FdFlags::APPEND)
+        {
+            return Err(Error::perm());
+        }
+
+        self.0
+            .open_file(symlink_follow, path, oflags, read, false, fdflags)
+            .await
+    }
+
+    async fn open_dir(&self, symlink_follow: bool, path: &str) -> Result<Box<dyn WasiDir>, Error> {
+        // Sub directories of a read only directory are read only too.
+        let dir = self.0.open_dir(symlink_follow, path).await?;
+        Ok(Box::new(ReadOnlyDir(dir)))
+    }
+
+    async fn create_dir(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, Error>> + Send>, Error> {
+        self.0.readdir(cursor).await
+    }
+
+    async fn symlink(&self, _old_path: &str, _new_path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn remove_dir(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn unlink_file(&self, _path: &str) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn read_link(&self, path: &str) -> Result<PathBuf, Error> {
+        self.0.read_link(path).await
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, Error> {
+        self.0.get_filestat().await
+    }
+
+    async fn get_path_filestat(
+        &self,
+        path: &str,
+        follow_symlinks: bool,
+    ) -> Result<Filestat, Error> {
+        self.0.get_path_filestat(path, follow_symlinks).await
+    }
+
+    async fn rename(
+        &self,
+        _path: &str,
+        _dest_dir: &dyn WasiDir,
+        _dest_path: &str,
+    ) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn hard_link(
+        &self,
+        _path: &str,
+        _target_dir: &dyn WasiDir,
+        _target_path: &str,
+    ) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+
+    async fn set_times(
+        &self,
+        _path: &str,
+        _atime: Option<SystemTimeSpec>,
+        _mtime: Option<SystemTimeSpec>,
+        _follow_symlinks: bool,
+    ) -> Result<(), Error> {
+        Err(Error::perm())
+    }
+}
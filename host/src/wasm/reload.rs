@@ -0,0 +1,81 @@
+// Hot reload of a module's wasm binary and config file, the new version replaces the running instance
+// only once it has initialised, otherwise the running instance is kept.
+use crate::config::ModuleConfiguration;
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+// Changed files must be unchanged for this long before they are loaded, so partly written files are not loaded.
+const RELOAD_SETTLE_TIME: Duration = Duration::from_millis(500);
+
+// Polls modification times of a set of files.
+pub struct FileWatcher {
+    paths: Vec<PathBuf>,
+    loaded: Vec<Option<SystemTime>>,
+    // Modification times seen when a change was first noticed, and when.
+    pending: Option<(Vec<Option<SystemTime>>, Instant)>,
+}
+
+impl FileWatcher {
+    fn new(paths: Vec<PathBuf>) -> Self {
+        let loaded = modified(&paths);
+
+        Self {
+            paths,
+            loaded,
+            pending: None,
+        }
+    }
+
+    // Returns true once files have changed since last load and have settled.
+    pub fn changed(&mut self) -> bool {
+        let current = modified(&self.paths);
+
+        if current == self.loaded {
+            self.pending = None;
+            return false;
+        }
+
+        match &self.pending {
+            Some((pending, noticed_at))
+                if *pending == current && noticed_at.elapsed() >= RELOAD_SETTLE_TIME =>
+            {
+                true
+            }
+            Some((pending, _)) if *pending == current => false,
+            _ => {
+                self.pending = Some((current, Instant::now()));
+                false
+            }
+        }
+    }
+
+    // Marks current files as loaded, whether or not loading them succeeded, so a failed version is not retried.
+    pub fn mark_loaded(&mut self) {
+        self.loaded = modified(&self.paths);
+        self.pending = None;
+    }
+}
+
+fn modified(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+// Returns watcher for a module's wasm binary and config file, when hot reload is enabled for the module.
+pub fn watch(
+    module_config: &ModuleConfiguration,
+    wasm_path: &str,
+    wasm_config_path: &str,
+) -> Option<FileWatcher> {
+    module_config.hot_reload().then(|| {
+        let mut paths = vec![PathBuf::from(wasm_path)];
+        // Invalid preopens fail the module's start, the config file is not watched then.
+        paths.extend(module_config.host_path(wasm_config_path).ok().flatten());
+        FileWatcher::new(paths)
+    })
+}
@@ -0,0 +1,124 @@
+// Canary rollout of a second version of a module, run alongside the active version on the module's thread.
+// Ticks of the active version's timers are split between the two by weight, the canary is rolled back once it
+// traps or logs errors too often, and promoted to active once it has handled enough ticks. Each replica runs its own
+// canary instance, but ticks are counted and the decision taken per module in the registry, so replicas roll back
+// or promote together.
+use super::runner::Instance;
+use super::MODULE_NAME;
+use crate::logging::{self, Level};
+use crate::registry::{self, Role, Ticks};
+use rand::Rng;
+
+pub struct CanaryPolicy {
+    // Version label shown in the module registry.
+    pub version: String,
+    // Host path of the canary's wasm binary.
+    pub wasm_path: String,
+    // Guest path of the canary's config file, defaults to the active version's.
+    pub config_file_path: Option<String>,
+    // Percentage of ticks handled by the canary, 100 moves all work to it (blue/green).
+    pub weight_percent: u32,
+    // Canary is rolled back once it has trapped this many times.
+    pub max_traps: u64,
+    // Canary is rolled back once this percentage of its ticks trapped or logged an error...
+    pub max_error_rate_percent: u32,
+    // ...checked only after it has handled this many ticks, so one early failure does not roll it back.
+    pub min_ticks: u64,
+    // Canary becomes the active version after handling this many ticks, never when not configured.
+    pub promote_after_ticks: Option<u64>,
+}
+
+pub enum Verdict {
+    Continue,
+    RollBack(String),
+    Promote,
+}
+
+pub struct Canary {
+    pub policy: CanaryPolicy,
+    pub instance: Box<dyn Instance>,
+    module_name: String,
+    // Ticks handled by the canary across all of the module's replicas, as of this replica's last tick.
+    totals: Ticks,
+}
+
+impl Canary {
+    pub fn new(module_name: &str, policy: CanaryPolicy, instance: Box<dyn Instance>) -> Self {
+        registry::register_canary(
+            module_name,
+            &policy.version,
+            &policy.wasm_path,
+            policy.weight_percent,
+        );
+
+        Self {
+            policy,
+            instance,
+            module_name: module_name.to_string(),
+            totals: Ticks::default(),
+        }
+    }
+
+    // Picks whether the next tick goes to the canary, by its weight.
+    pub fn takes_tick(&self) -> bool {
+        rand::thread_rng().gen_range(0..100) < self.policy.weight_percent
+    }
+
+    // Calls the canary's tick export, failures count against the canary instead of stopping the module.
+    // Canary is given the active version's timer ids, so both versions must register their timers in the same order.
+    pub fn tick(&mut self, timer_id: u32) {
+        let error_logs = self.instance.error_logs();
+        let result = self.instance.on_tick(timer_id);
+
+        if let Err(e) = &result {
+            logging::log(
+                Level::Warn,
+                MODULE_NAME,
+                &format!(
+                    "Canary '{}' of module '{}' trapped; error = {e}",
+                    self.policy.version, self.module_name
+                ),
+                &[("module", &self.module_name)],
+            );
+        }
+        let failed = result.is_err() || self.instance.error_logs() > error_logs;
+
+        self.totals = registry::version_ticked(
+            &self.module_name,
+            &self.policy.version,
+            failed,
+            result.is_err(),
+        );
+    }
+
+    // Follows a decision already taken on another replica, otherwise checks the module wide totals.
+    pub fn verdict(&self) -> Verdict {
+        match registry::role(&self.module_name, &self.policy.version) {
+            Some(Role::RolledBack) => {
+                return Verdict::RollBack("rolled back on another replica".to_string())
+            }
+            Some(Role::Active) => return Verdict::Promote,
+            _ => {}
+        }
+
+        let totals = &self.totals;
+        if totals.traps >= self.policy.max_traps {
+            return Verdict::RollBack(format!("trapped {} times", totals.traps));
+        }
+
+        if totals.ticks >= self.policy.min_ticks.max(1) {
+            let error_rate_percent = totals.failed_ticks * 100 / totals.ticks;
+            if error_rate_percent > self.policy.max_error_rate_percent.into() {
+                return Verdict::RollBack(format!(
+                    "{error_rate_percent}% of {} ticks failed",
+                    totals.ticks
+                ));
+            }
+        }
+
+        match self.policy.promote_after_ticks {
+            Some(promote_after_ticks) if totals.ticks >= promote_after_ticks => Verdict::Promote,
+            _ => Verdict::Continue,
+        }
+    }
+}
@@ -0,0 +1,361 @@
+wit_bindgen_wasmtime::import!("../wits/wasmrpcfunctions.wit");
+
+// Direct calls between modules, brokered by the host without a round trip through pubsub server module. A module
+// serves the handlers listed in the rpc section of its host configuration through its 'handlecall' export, other
+// modules call them with host function 'callhandler'. Each call is handled in a new instance from the serving
+// module's instance pool, on its own thread so the caller can stop waiting once its timeout has passed.
+use super::preopens::ModuleDirs;
+use super::{HostEnvironment, Hostobservability, Runtime, MODULE_NAME};
+use crate::config::ModuleConfiguration;
+use crate::logging::{self, Level};
+use crate::metrics;
+use crate::shutdown::ShutdownSignal;
+use crate::tracing::{Span, SpanKind, TraceContext};
+use anyhow::{bail, Result};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, OnceLock, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use wasmrpcfunctions::{Wasmrpcfunctions, WasmrpcfunctionsData};
+
+// Allowed call which matches every handler of a module, as '<module>.*'.
+const ANY_HANDLER: &str = "*";
+// How often a module shutting down checks whether its in flight calls have finished.
+const IN_FLIGHT_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+// How often the engine's epoch advances, handlers are interrupted within this of their call's timeout.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+// Time in flight calls get beyond the longest timeout to finish once their module shuts down, covering host calls
+// the epoch deadline cannot interrupt.
+const IN_FLIGHT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+static SERVERS: OnceLock<RwLock<Vec<Arc<Server>>>> = OnceLock::new();
+
+pub struct RpcHandlers {
+    pub handlers: Vec<String>,
+    // Calls handled at once, further calls fail straight away.
+    pub max_concurrency: usize,
+    // Instances the pooling allocator reserves memory and tables for up front.
+    pub pool_size: u32,
+    // Longest a caller may wait for a call, also used for calls made without a timeout.
+    pub max_timeout: Duration,
+}
+
+// Replica of a module serving its handlers.
+struct Server {
+    module_config: ModuleConfiguration,
+    runtime: Runtime,
+    // Directories opened once for the module, each instance gets its own handles.
+    dirs: Option<ModuleDirs>,
+    environment: HostEnvironment,
+    shutdown: ShutdownSignal,
+    rpc: RpcHandlers,
+    in_flight: AtomicUsize,
+}
+
+// Handlers served by a replica, withdrawn when dropped once the replica's in flight calls have finished.
+pub struct Registration(Arc<Server>);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        // New calls fail from here as the handlers are no longer served, calls already made finish.
+        SERVERS
+            .get_or_init(Default::default)
+            .write()
+            .unwrap()
+            .retain(|server| !Arc::ptr_eq(server, &self.0));
+
+        // Handlers are interrupted at their timeout, calls still running past it are left behind.
+        let deadline = Instant::now() + self.0.rpc.max_timeout + IN_FLIGHT_GRACE_PERIOD;
+        while self.0.in_flight.load(Ordering::SeqCst) > 0 {
+            if Instant::now() >= deadline {
+                let module_name = self.0.module_config.module_name();
+                logging::log(
+                    Level::Warn,
+                    MODULE_NAME,
+                    &format!("Module '{module_name}' stopped serving rpc handlers with calls still in flight."),
+                    &[("module", &module_name)],
+                );
+                return;
+            }
+            thread::sleep(IN_FLIGHT_CHECK_INTERVAL);
+        }
+    }
+}
+
+// Serves the handlers of a module configured with an rpc section, until the returned registration is dropped.
+// Returns None for modules serving no handlers.
+pub fn serve(
+    wasm_path: &str,
+    module_config: &ModuleConfiguration,
+    environment: &HostEnvironment,
+    shutdown: &ShutdownSignal,
+) -> Result<Option<Registration>> {
+    let module_name = module_config.module_name();
+    let rpc = match module_config.rpc() {
+        Some(rpc) => rpc,
+        None => return Ok(None),
+    };
+
+    if super::is_component(wasm_path)? {
+        bail!("Module '{module_name}' is a component, only core modules can serve rpc handlers.");
+    }
+
+    // Module is compiled once, each call gets an instance allocated from the pool. Handlers still running at their
+    // call's timeout are interrupted through the engine's epoch, so they give back their slot.
+    let mut config = super::pooled_config(rpc.pool_size.max(rpc.max_concurrency as u32));
+    config.epoch_interruption(true);
+    let mut runtime = super::prepare(
+        wasm_path,
+        module_config,
+        config,
+        &environment.host_functions,
+    )?;
+    Wasmrpcfunctions::add_to_linker(&mut runtime.linker, |cx: &mut super::Context| {
+        cx.exports_mut()
+    })?;
+
+    logging::log(
+        Level::Info,
+        MODULE_NAME,
+        &format!(
+            "Serving rpc handlers '{}' of module '{module_name}'.",
+            rpc.handlers.join(" ")
+        ),
+        &[("module", &module_name)],
+    );
+
+    let server = Arc::new(Server {
+        module_config: module_config.clone(),
+        runtime,
+        dirs: super::module_dirs(module_config)?,
+        environment: environment.clone(),
+        shutdown: shutdown.clone(),
+        rpc,
+        in_flight: AtomicUsize::new(0),
+    });
+    SERVERS
+        .get_or_init(Default::default)
+        .write()
+        .unwrap()
+        .push(server.clone());
+
+    // Epoch advances until the registration is dropped and the module's last in flight call has finished.
+    let ticked = Arc::downgrade(&server);
+    thread::spawn(move || {
+        while let Some(server) = ticked.upgrade() {
+            server.runtime.engine.increment_epoch();
+            drop(server);
+            thread::sleep(EPOCH_TICK);
+        }
+    });
+
+    Ok(Some(Registration(server)))
+}
+
+// Calls a handler of another module on behalf of a module's 'callhandler' host function call. Fails when the caller
+// is not allowed to call the handler, no module serves it, the serving module is busy or the handler fails, or the
+// call does not finish within the timeout, capped by the serving module's maximum.
+pub fn call(
+    caller: &Hostobservability,
+    module_name: &str,
+    handler: &str,
+    payload: &str,
+    timeout_in_milliseconds: u64,
+    trace_context: TraceContext,
+) -> Result<String, String> {
+    let target = format!("{module_name}.{handler}");
+
+    if !is_call_allowed(&caller.allowed_calls, module_name, handler) {
+        let reason = format!("not authorised to call '{target}'");
+        logging::log(
+            Level::Warn,
+            MODULE_NAME,
+            &format!("Denied module '{}': {reason}.", caller.module_name),
+            &[("module", &caller.module_name), ("handler", &target)],
+        );
+        return Err(reason);
+    }
+
+    // Replicas serve the same handlers, calls go to the least busy replica.
+    let server = SERVERS
+        .get_or_init(Default::default)
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|server| {
+            server.module_config.module_name() == module_name
+                && server.rpc.handlers.iter().any(|served| served == handler)
+        })
+        .min_by_key(|server| server.in_flight.load(Ordering::SeqCst))
+        .cloned()
+        .ok_or_else(|| format!("no module serves '{target}'"))?;
+
+    let timeout = match timeout_in_milliseconds {
+        0 => server.rpc.max_timeout,
+        timeout => Duration::from_millis(timeout).min(server.rpc.max_timeout),
+    };
+
+    if server.in_flight.fetch_add(1, Ordering::SeqCst) >= server.rpc.max_concurrency {
+        server.in_flight.fetch_sub(1, Ordering::SeqCst);
+        metrics::counter_increment(
+            module_name,
+            "wasm_rpc_calls_total",
+            1.0,
+            &[("handler", handler), ("status", "busy")],
+        );
+        return Err(format!("module '{module_name}' is busy"));
+    }
+
+    let (sender, receiver) = mpsc::sync_channel(1);
+    let (caller_name, handler, payload) = (
+        caller.module_name.clone(),
+        handler.to_string(),
+        payload.to_string(),
+    );
+    thread::spawn(move || {
+        let result = server.handle(&caller_name, &handler, &payload, timeout, trace_context);
+        server.in_flight.fetch_sub(1, Ordering::SeqCst);
+        // Caller may have stopped waiting.
+        let _ = sender.send(result);
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => {
+            let reason = format!(
+                "call to '{target}' timed out after {} ms",
+                timeout.as_millis()
+            );
+            logging::log(
+                Level::Warn,
+                MODULE_NAME,
+                &format!("Module '{}': {reason}.", caller.module_name),
+                &[("module", &caller.module_name), ("handler", &target)],
+            );
+            Err(reason)
+        }
+        // Thread handling the call panicked.
+        Err(RecvTimeoutError::Disconnected) => {
+            Err(format!("module '{module_name}' failed to handle call"))
+        }
+    }
+}
+
+// Returns true if a handler is in the caller's allowed calls, by name or through '<module>.*'.
+fn is_call_allowed(allowed_calls: &[String], module_name: &str, handler: &str) -> bool {
+    allowed_calls
+        .iter()
+        .any(|allowed| match allowed.split_once('.') {
+            Some((allowed_module, allowed_handler)) => {
+                allowed_module == module_name
+                    && (allowed_handler == ANY_HANDLER || allowed_handler == handler)
+            }
+            None => false,
+        })
+}
+
+impl Server {
+    // Handles a call in a new instance of the module, traps are returned to the caller as errors. Handler is
+    // interrupted once the call's timeout has passed.
+    fn handle(
+        &self,
+        caller: &str,
+        handler: &str,
+        payload: &str,
+        timeout: Duration,
+        trace_context: TraceContext,
+    ) -> Result<String, String> {
+        let module_name = self.module_config.module_name();
+        let started = Instant::now();
+
+        let mut span = Span::start(
+            &format!("{module_name}.{handler}"),
+            SpanKind::Server,
+            &module_name,
+            Some(&trace_context),
+        );
+        span.set_attribute("rpc.service", &module_name);
+        span.set_attribute("rpc.method", handler);
+
+        // Host calls made by the handler are traced as part of the call's trace.
+        let mut host_observability = Hostobservability::new(
+            &self.module_config,
+            &self.environment,
+            self.shutdown.clone(),
+        );
+        host_observability.trace_context = Some(span.context());
+
+        let result = self
+            .call(handler, payload, timeout, host_observability)
+            .unwrap_or_else(|e| {
+                logging::log(
+                    Level::Warn,
+                    MODULE_NAME,
+                    &format!(
+                        "Module '{module_name}' failed to handle call to '{handler}'; error = {e}"
+                    ),
+                    &[("module", &module_name), ("caller", caller)],
+                );
+                Err(format!("module '{module_name}' failed to handle call"))
+            });
+
+        let status = match &result {
+            Ok(_) => "ok",
+            Err(e) => {
+                span.set_error(e);
+                "error"
+            }
+        };
+        span.end();
+
+        metrics::counter_increment(
+            &module_name,
+            "wasm_rpc_calls_total",
+            1.0,
+            &[("handler", handler), ("status", status)],
+        );
+        metrics::histogram_record(
+            &module_name,
+            "wasm_rpc_call_duration_seconds",
+            started.elapsed().as_secs_f64(),
+            &[("handler", handler)],
+        );
+
+        result
+    }
+
+    fn call(
+        &self,
+        handler: &str,
+        payload: &str,
+        timeout: Duration,
+        host_observability: Hostobservability,
+    ) -> Result<Result<String, String>> {
+        let mut store = super::new_store(
+            &self.runtime.engine,
+            super::instance_wasi(&self.module_config, self.dirs.as_ref())?,
+            &self.module_config,
+            None,
+            host_observability,
+            Box::<WasmrpcfunctionsData>::default(),
+            &self.environment.host_functions,
+        )?;
+        store.set_epoch_deadline(timeout.as_millis() as u64 / EPOCH_TICK.as_millis() as u64 + 1);
+
+        let instance = self
+            .runtime
+            .linker
+            .instantiate(&mut store, &self.runtime.module)?;
+        let exports = Wasmrpcfunctions::new(&mut store, &instance, |cx| cx.exports_mut())?;
+
+        let result = exports.handlecall(&mut store, handler, payload)?;
+        super::record_runtime_metrics(&mut store);
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,342 @@
+// Drives a module after init: calls its tick export as timers fire, hot reloads it and runs its canary, until
+// shutdown is requested.
+use super::rollout::{Canary, CanaryPolicy, Verdict};
+use super::scheduler::Scheduler;
+use super::{reload, Context, MODULE_NAME};
+use crate::config::ModuleConfiguration;
+use crate::logging::{self, Level};
+use crate::metrics;
+use crate::registry::{self, ReplicaStatus, Role};
+use crate::shutdown::ShutdownSignal;
+use anyhow::Result;
+use std::time::{Duration, Instant};
+use wit_bindgen_wasmtime::wasmtime::Store;
+
+// How often hot reload and canary thresholds are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+// Time a replaced or rolled back instance gets to run its shutdown export.
+const REPLACED_INSTANCE_SHUTDOWN_DEADLINE: Duration = Duration::from_secs(5);
+
+// Instance of a module the runner drives after init, a core module or a component.
+pub trait Instance {
+    // Timers the instance registered, the runner calls its tick export as they fire.
+    fn scheduler(&mut self) -> &mut Scheduler;
+
+    fn on_tick(&mut self, timer_id: u32) -> Result<()>;
+
+    fn shutdown(&mut self, deadline_in_milliseconds: u64) -> Result<()>;
+
+    // Error level logs written by the instance, counted against it during canary rollout.
+    fn error_logs(&self) -> u64;
+
+    fn record_runtime_metrics(&mut self);
+}
+
+// Functions a core module exports for the host to drive it, after init.
+pub struct Lifecycle<T> {
+    pub on_tick: fn(&T, &mut Store<Context>, u32) -> Result<()>,
+    pub shutdown: fn(&T, &mut Store<Context>, u64) -> Result<()>,
+}
+
+impl<T> Clone for Lifecycle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Lifecycle<T> {}
+
+// Core module instance, driven through the exports of its generated bindings.
+pub struct CoreInstance<T> {
+    exports: T,
+    store: Store<Context>,
+    lifecycle: Lifecycle<T>,
+}
+
+impl<T> CoreInstance<T> {
+    pub fn new(exports: T, store: Store<Context>, lifecycle: Lifecycle<T>) -> Self {
+        Self {
+            exports,
+            store,
+            lifecycle,
+        }
+    }
+}
+
+impl<T> Instance for CoreInstance<T> {
+    fn scheduler(&mut self) -> &mut Scheduler {
+        &mut self
+            .store
+            .data_mut()
+            .runtime_data
+            .as_mut()
+            .unwrap()
+            .scheduler
+    }
+
+    fn on_tick(&mut self, timer_id: u32) -> Result<()> {
+        (self.lifecycle.on_tick)(&self.exports, &mut self.store, timer_id)
+    }
+
+    fn shutdown(&mut self, deadline_in_milliseconds: u64) -> Result<()> {
+        (self.lifecycle.shutdown)(&self.exports, &mut self.store, deadline_in_milliseconds)
+    }
+
+    fn error_logs(&self) -> u64 {
+        self.store
+            .data()
+            .runtime_data
+            .as_ref()
+            .map_or(0, |runtime_data| runtime_data.error_logs)
+    }
+
+    fn record_runtime_metrics(&mut self) {
+        super::record_runtime_metrics(&mut self.store);
+    }
+}
+
+// Calls shutdown export of an instance which is no longer used, failures are only logged.
+fn stop(module_name: &str, instance: &mut dyn Instance) {
+    if let Err(e) = instance.shutdown(REPLACED_INSTANCE_SHUTDOWN_DEADLINE.as_millis() as u64) {
+        logging::log(
+            Level::Warn,
+            MODULE_NAME,
+            &format!(
+                "Replaced instance of module '{module_name}' failed to shut down; error = {e}"
+            ),
+            &[("module", module_name)],
+        );
+    }
+}
+
+// Runs a module started by 'start' (instantiate and init of the given wasm binary and config file) until shutdown
+// is requested. Module is replaced with a newly started instance whenever its wasm binary or config file changes
+// and hot reload is enabled, and its configured canary version is run alongside it.
+pub fn run(
+    module_config: &ModuleConfiguration,
+    wasm_path: &str,
+    wasm_config_path: &str,
+    shutdown: &ShutdownSignal,
+    mut start: impl FnMut(&str, &str) -> Result<Box<dyn Instance>>,
+) -> Result<()> {
+    let module_name = module_config.module_name();
+    let replica = module_config.replica();
+    let mut version = module_config.version();
+    let (mut wasm_path, mut wasm_config_path) =
+        (wasm_path.to_string(), wasm_config_path.to_string());
+
+    let mut instance = start(&wasm_path, &wasm_config_path)?;
+    registry::register(&module_name, &version, &wasm_path, Role::Active, 100);
+    registry::set_replica_status(&module_name, replica, ReplicaStatus::Running);
+
+    let mut canary = module_config
+        .canary()
+        .and_then(|policy| start_canary(&module_name, policy, &wasm_config_path, &mut start));
+    let mut watcher = reload::watch(module_config, &wasm_path, &wasm_config_path);
+
+    loop {
+        let until = Instant::now() + CHECK_INTERVAL;
+        super::run_timers(instance.as_mut(), shutdown, until, |instance, timer_id| {
+            registry::replica_ticked(&module_name, replica);
+
+            match canary.as_mut() {
+                Some(canary) if canary.takes_tick() => {
+                    canary.tick(timer_id);
+                    Ok(())
+                }
+                _ => instance.on_tick(timer_id),
+            }
+        })?;
+
+        if shutdown.is_requested() {
+            break;
+        }
+
+        match canary.as_ref().map(|canary| canary.verdict()) {
+            Some(Verdict::RollBack(reason)) => {
+                let mut rolled_back = canary.take().unwrap();
+                stop(&module_name, rolled_back.instance.as_mut());
+                // Rollout is counted once for the module, by the replica which decided it.
+                let decided = registry::set_role(
+                    &module_name,
+                    &rolled_back.policy.version,
+                    Role::RolledBack,
+                    0,
+                );
+
+                logging::log(
+                    Level::Error,
+                    MODULE_NAME,
+                    &format!(
+                        "Canary '{}' of module '{module_name}' rolled back: {reason}.",
+                        rolled_back.policy.version
+                    ),
+                    &[("module", &module_name)],
+                );
+                if decided {
+                    metrics::counter_increment(
+                        &module_name,
+                        "wasm_module_rollouts_total",
+                        1.0,
+                        &[("result", "rolled_back")],
+                    );
+                }
+            }
+            Some(Verdict::Promote) => {
+                let promoted = canary.take().unwrap();
+                let mut old_instance = std::mem::replace(&mut instance, promoted.instance);
+                stop(&module_name, old_instance.as_mut());
+
+                registry::set_role(&module_name, &version, Role::Retired, 0);
+                let decided =
+                    registry::set_role(&module_name, &promoted.policy.version, Role::Active, 100);
+                logging::log(
+                    Level::Info,
+                    MODULE_NAME,
+                    &format!(
+                        "Canary '{}' of module '{module_name}' promoted, replacing '{version}'.",
+                        promoted.policy.version
+                    ),
+                    &[("module", &module_name)],
+                );
+                if decided {
+                    metrics::counter_increment(
+                        &module_name,
+                        "wasm_module_rollouts_total",
+                        1.0,
+                        &[("result", "promoted")],
+                    );
+                }
+
+                // Promoted version is the one hot reloaded from now on.
+                version = promoted.policy.version;
+                wasm_path = promoted.policy.wasm_path;
+                if let Some(config_file_path) = promoted.policy.config_file_path {
+                    wasm_config_path = config_file_path;
+                }
+                watcher = reload::watch(module_config, &wasm_path, &wasm_config_path);
+                continue;
+            }
+            _ => {}
+        }
+
+        let watcher = match watcher.as_mut() {
+            Some(watcher) if watcher.changed() => watcher,
+            _ => continue,
+        };
+        watcher.mark_loaded();
+
+        logging::log(
+            Level::Info,
+            MODULE_NAME,
+            &format!("Module '{module_name}' changed, starting new version."),
+            &[("module", &module_name)],
+        );
+
+        match start(&wasm_path, &wasm_config_path) {
+            Ok(new_instance) => {
+                let mut old_instance = std::mem::replace(&mut instance, new_instance);
+                stop(&module_name, old_instance.as_mut());
+                registry::register(&module_name, &version, &wasm_path, Role::Active, 100);
+
+                logging::log(
+                    Level::Info,
+                    MODULE_NAME,
+                    &format!("Module '{module_name}' reloaded."),
+                    &[("module", &module_name)],
+                );
+                metrics::counter_increment(
+                    &module_name,
+                    "wasm_module_reloads_total",
+                    1.0,
+                    &[("result", "success")],
+                );
+            }
+            Err(e) => {
+                logging::log(
+                    Level::Error,
+                    MODULE_NAME,
+                    &format!("New version of module '{module_name}' failed to initialise, keeping running version; error = {e}"),
+                    &[("module", &module_name)],
+                );
+                metrics::counter_increment(
+                    &module_name,
+                    "wasm_module_reloads_total",
+                    1.0,
+                    &[("result", "rolled_back")],
+                );
+            }
+        }
+    }
+
+    // Module gets until its deadline to clean up, its canary is shut down first. Active version is shut down even if
+    // the canary fails to, so its queues are still drained, the canary's error is returned first.
+    let deadline = shutdown.deadline().unwrap_or_default();
+    let canary_result = match canary {
+        Some(mut canary) => canary
+            .instance
+            .shutdown(deadline.as_millis() as u64)
+            .map_err(|e| {
+                logging::log(
+                    Level::Error,
+                    MODULE_NAME,
+                    &format!("Canary of module '{module_name}' failed to shut down; error = {e}"),
+                    &[("module", &module_name)],
+                );
+                e
+            }),
+        None => Ok(()),
+    };
+    let result = instance.shutdown(deadline.as_millis() as u64);
+    instance.record_runtime_metrics();
+
+    canary_result.and(result)
+}
+
+// Starts the canary version of a module, the module keeps running its active version alone if this fails.
+fn start_canary(
+    module_name: &str,
+    policy: CanaryPolicy,
+    wasm_config_path: &str,
+    start: &mut impl FnMut(&str, &str) -> Result<Box<dyn Instance>>,
+) -> Option<Canary> {
+    let config_file_path = policy
+        .config_file_path
+        .as_deref()
+        .unwrap_or(wasm_config_path);
+
+    match start(&policy.wasm_path, config_file_path) {
+        Ok(instance) => {
+            logging::log(
+                Level::Info,
+                MODULE_NAME,
+                &format!(
+                    "Canary '{}' of module '{module_name}' started with {}% of ticks.",
+                    policy.version, policy.weight_percent
+                ),
+                &[("module", module_name)],
+            );
+            Some(Canary::new(module_name, policy, instance))
+        }
+        Err(e) => {
+            logging::log(
+                Level::Error,
+                MODULE_NAME,
+                &format!(
+                    "Canary '{}' of module '{module_name}' failed to initialise; error = {e}",
+                    policy.version
+                ),
+                &[("module", module_name)],
+            );
+            // Canaries already running on other replicas follow this and roll back too.
+            registry::register(
+                module_name,
+                &policy.version,
+                &policy.wasm_path,
+                Role::RolledBack,
+                0,
+            );
+            None
+        }
+    }
+}
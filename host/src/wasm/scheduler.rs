@@ -0,0 +1,371 @@
+// Host owned timers, modules register interval or cron schedules and the host calls the module's 'ontick' export.
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+// Ticks fired back to back by burst policy before remaining missed ticks are skipped.
+const MAX_BURST_TICKS: u32 = 10;
+// Shorter intervals are rounded up to this.
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+// What happens to ticks which were due while the module was still handling an earlier tick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissedTickPolicy {
+    // Missed ticks are dropped, next tick fires at the next scheduled time.
+    Skip,
+    // Missed ticks fire back to back until the timer has caught up.
+    Burst,
+    // Schedule restarts from when the last tick finished.
+    Delay,
+}
+
+enum Schedule {
+    Interval(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    // Returns first scheduled time after the given time.
+    fn next_after(&self, after: Instant) -> Instant {
+        match self {
+            Schedule::Interval(interval) => after + *interval,
+            Schedule::Cron(schedule) => match schedule.after(&to_utc(after)).next() {
+                Some(next) => to_instant(next),
+                // Schedule has no more occurrences e.g. a past year, timer never fires again.
+                None => after + Duration::from_secs(u32::MAX.into()),
+            },
+        }
+    }
+}
+
+// Cron schedules are in wall clock time, converted to and from Instant relative to now.
+fn to_utc(instant: Instant) -> DateTime<Utc> {
+    let (now, now_utc) = (Instant::now(), Utc::now());
+
+    if instant >= now {
+        now_utc
+            + chrono::Duration::from_std(instant - now).unwrap_or_else(|_| chrono::Duration::zero())
+    } else {
+        now_utc
+            - chrono::Duration::from_std(now - instant).unwrap_or_else(|_| chrono::Duration::zero())
+    }
+}
+
+fn to_instant(time: DateTime<Utc>) -> Instant {
+    Instant::now() + (time - Utc::now()).to_std().unwrap_or(Duration::ZERO)
+}
+
+struct Timer {
+    id: u32,
+    schedule: Schedule,
+    jitter: Duration,
+    missed_tick_policy: MissedTickPolicy,
+    // Unjittered time of the next tick, later ticks are scheduled from this so jitter does not accumulate.
+    scheduled_at: Instant,
+    due_at: Instant,
+    missed_ticks: u32,
+}
+
+impl Timer {
+    fn schedule_next(&mut self, tick_finished_at: Instant) {
+        let next = match self.missed_tick_policy {
+            MissedTickPolicy::Skip => {
+                let mut next = self.schedule.next_after(self.scheduled_at);
+                while next <= tick_finished_at {
+                    next = self.schedule.next_after(next);
+                }
+                next
+            }
+            MissedTickPolicy::Burst => {
+                let next = self.schedule.next_after(self.scheduled_at);
+                if next <= tick_finished_at && self.missed_ticks < MAX_BURST_TICKS {
+                    self.missed_ticks += 1;
+                    // Missed ticks are not jittered, they are already late.
+                    self.scheduled_at = next;
+                    self.due_at = next;
+                    return;
+                }
+                self.missed_ticks = 0;
+
+                let mut next = next;
+                while next <= tick_finished_at {
+                    next = self.schedule.next_after(next);
+                }
+                next
+            }
+            MissedTickPolicy::Delay => self.schedule.next_after(tick_finished_at),
+        };
+
+        self.scheduled_at = next;
+        self.due_at = next + random_jitter(self.jitter);
+    }
+}
+
+fn random_jitter(jitter: Duration) -> Duration {
+    if jitter.is_zero() {
+        return Duration::ZERO;
+    }
+
+    Duration::from_millis(rand::thread_rng().gen_range(0..=jitter.as_millis() as u64))
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+    timers: Vec<Timer>,
+    next_timer_id: u32,
+}
+
+impl Scheduler {
+    pub fn schedule_interval(
+        &mut self,
+        interval: Duration,
+        jitter: Duration,
+        missed_tick_policy: MissedTickPolicy,
+    ) -> u32 {
+        self.add(
+            Schedule::Interval(interval.max(MIN_INTERVAL)),
+            jitter,
+            missed_tick_policy,
+        )
+    }
+
+    // Cron expressions have seconds, e.g. '0 */5 * * * *' fires every five minutes.
+    pub fn schedule_cron(
+        &mut self,
+        expression: &str,
+        jitter: Duration,
+        missed_tick_policy: MissedTickPolicy,
+    ) -> Result<u32, String> {
+        let schedule = cron::Schedule::from_str(expression)
+            .map_err(|e| format!("invalid cron expression '{expression}': {e}"))?;
+
+        Ok(self.add(
+            Schedule::Cron(Box::new(schedule)),
+            jitter,
+            missed_tick_policy,
+        ))
+    }
+
+    pub fn cancel(&mut self, timer_id: u32) {
+        self.timers.retain(|timer| timer.id != timer_id);
+    }
+
+    // Returns id and due time of the timer which fires next.
+    pub fn next_due(&self) -> Option<(u32, Instant)> {
+        self.timers
+            .iter()
+            .min_by_key(|timer| timer.due_at)
+            .map(|timer| (timer.id, timer.due_at))
+    }
+
+    // Schedules the timer's next tick after a tick finished, timers cancelled during the tick are ignored.
+    pub fn ticked(&mut self, timer_id: u32, tick_finished_at: Instant) {
+        if let Some(timer) = self.timers.iter_mut().find(|timer| timer.id == timer_id) {
+            timer.schedule_next(tick_finished_at);
+        }
+    }
+
+    fn add(
+        &mut self,
+        schedule: Schedule,
+        jitter: Duration,
+        missed_tick_policy: MissedTickPolicy,
+    ) -> u32 {
+        self.next_timer_id += 1;
+
+        let scheduled_at = schedule.next_after(Instant::now());
+        self.timers.push(Timer {
+            id: self.next_timer_id,
+            schedule,
+            jitter,
+            missed_tick_policy,
+            scheduled_at,
+            due_at: scheduled_at + random_jitter(jitter),
+            missed_ticks: 0,
+        });
+
+        self.next_timer_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    fn timer(scheduler: &Scheduler, timer_id: u32) -> &Timer {
+        scheduler
+            .timers
+            .iter()
+            .find(|timer| timer.id == timer_id)
+            .unwrap()
+    }
+
+    #[test]
+    fn interval_timer_is_due_after_its_interval() {
+        let mut scheduler = Scheduler::default();
+        let before = Instant::now();
+        let timer_id =
+            scheduler.schedule_interval(INTERVAL, Duration::ZERO, MissedTickPolicy::Skip);
+        let after = Instant::now();
+
+        let (next_id, due_at) = scheduler.next_due().unwrap();
+        assert_eq!(next_id, timer_id);
+        assert!(due_at >= before + INTERVAL && due_at <= after + INTERVAL);
+    }
+
+    #[test]
+    fn jitter_delays_ticks_within_its_bound() {
+        let jitter = Duration::from_millis(20);
+
+        assert_eq!(random_jitter(Duration::ZERO), Duration::ZERO);
+        for _ in 0..1000 {
+            assert!(random_jitter(jitter) <= jitter);
+        }
+
+        let mut scheduler = Scheduler::default();
+        let timer_id = scheduler.schedule_interval(INTERVAL, jitter, MissedTickPolicy::Skip);
+        for _ in 0..100 {
+            let scheduled_at = timer(&scheduler, timer_id).scheduled_at;
+            scheduler.ticked(timer_id, scheduled_at);
+
+            let timer = timer(&scheduler, timer_id);
+            // Jitter does not move the schedule itself.
+            assert_eq!(timer.scheduled_at, scheduled_at + INTERVAL);
+            assert!(
+                timer.due_at >= timer.scheduled_at && timer.due_at <= timer.scheduled_at + jitter
+            );
+        }
+    }
+
+    #[test]
+    fn skip_policy_drops_missed_ticks() {
+        let mut scheduler = Scheduler::default();
+        let timer_id =
+            scheduler.schedule_interval(INTERVAL, Duration::ZERO, MissedTickPolicy::Skip);
+        let start = timer(&scheduler, timer_id).scheduled_at;
+
+        scheduler.ticked(timer_id, start + Duration::from_millis(350));
+
+        assert_eq!(
+            timer(&scheduler, timer_id).scheduled_at,
+            start + Duration::from_millis(400)
+        );
+    }
+
+    #[test]
+    fn burst_policy_fires_missed_ticks_back_to_back() {
+        let mut scheduler = Scheduler::default();
+        let timer_id =
+            scheduler.schedule_interval(INTERVAL, Duration::ZERO, MissedTickPolicy::Burst);
+        let start = timer(&scheduler, timer_id).scheduled_at;
+        let finished_at = start + Duration::from_millis(350);
+
+        for missed in 1..=3 {
+            scheduler.ticked(timer_id, finished_at);
+            let timer = timer(&scheduler, timer_id);
+            assert_eq!(timer.scheduled_at, start + INTERVAL * missed);
+            assert_eq!(timer.due_at, timer.scheduled_at);
+        }
+
+        // Caught up, next tick is on schedule.
+        scheduler.ticked(timer_id, finished_at);
+        let timer = timer(&scheduler, timer_id);
+        assert_eq!(timer.scheduled_at, start + Duration::from_millis(400));
+        assert_eq!(timer.missed_ticks, 0);
+    }
+
+    #[test]
+    fn burst_policy_skips_ticks_beyond_its_limit() {
+        let mut scheduler = Scheduler::default();
+        let timer_id =
+            scheduler.schedule_interval(INTERVAL, Duration::ZERO, MissedTickPolicy::Burst);
+        let start = timer(&scheduler, timer_id).scheduled_at;
+        let finished_at = start + INTERVAL * 50;
+
+        for _ in 0..MAX_BURST_TICKS {
+            scheduler.ticked(timer_id, finished_at);
+        }
+        assert_eq!(
+            timer(&scheduler, timer_id).scheduled_at,
+            start + INTERVAL * MAX_BURST_TICKS
+        );
+
+        scheduler.ticked(timer_id, finished_at);
+        assert_eq!(
+            timer(&scheduler, timer_id).scheduled_at,
+            start + INTERVAL * 51
+        );
+    }
+
+    #[test]
+    fn delay_policy_restarts_schedule_from_tick_finish() {
+        let mut scheduler = Scheduler::default();
+        let timer_id =
+            scheduler.schedule_interval(INTERVAL, Duration::ZERO, MissedTickPolicy::Delay);
+        let finished_at = timer(&scheduler, timer_id).scheduled_at + Duration::from_millis(350);
+
+        scheduler.ticked(timer_id, finished_at);
+
+        assert_eq!(
+            timer(&scheduler, timer_id).scheduled_at,
+            finished_at + INTERVAL
+        );
+    }
+
+    #[test]
+    fn cron_timer_is_due_at_next_occurrence() {
+        let mut scheduler = Scheduler::default();
+        let timer_id = scheduler
+            .schedule_cron("0 */5 * * * *", Duration::ZERO, MissedTickPolicy::Skip)
+            .unwrap();
+
+        let (next_id, due_at) = scheduler.next_due().unwrap();
+        assert_eq!(next_id, timer_id);
+        assert!(due_at <= Instant::now() + Duration::from_secs(5 * 60));
+    }
+
+    #[test]
+    fn cron_timer_without_further_occurrences_never_fires() {
+        let mut scheduler = Scheduler::default();
+        scheduler
+            .schedule_cron("0 0 0 1 1 * 2000", Duration::ZERO, MissedTickPolicy::Skip)
+            .unwrap();
+
+        let (_, due_at) = scheduler.next_due().unwrap();
+        assert!(due_at > Instant::now() + Duration::from_secs(365 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn invalid_cron_expression_is_rejected() {
+        let mut scheduler = Scheduler::default();
+
+        let error = scheduler
+            .schedule_cron("every five minutes", Duration::ZERO, MissedTickPolicy::Skip)
+            .unwrap_err();
+
+        assert!(error.contains("invalid cron expression 'every five minutes'"));
+        assert!(scheduler.next_due().is_none());
+    }
+
+    #[test]
+    fn next_due_is_earliest_timer_and_cancelled_timers_are_removed() {
+        let mut scheduler = Scheduler::default();
+        let slow =
+            scheduler.schedule_interval(INTERVAL * 10, Duration::ZERO, MissedTickPolicy::Skip);
+        let fast = scheduler.schedule_interval(INTERVAL, Duration::ZERO, MissedTickPolicy::Skip);
+
+        assert_eq!(scheduler.next_due().unwrap().0, fast);
+
+        scheduler.cancel(fast);
+        // Tick of a timer cancelled while it ran is ignored.
+        scheduler.ticked(fast, Instant::now());
+
+        assert_eq!(scheduler.next_due().unwrap().0, slow);
+        assert_eq!(scheduler.timers.len(), 1);
+    }
+}
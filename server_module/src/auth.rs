@@ -0,0 +1,148 @@
+// Topic name which grants access to all topics in an identity's topic list.
+const ALL_TOPICS: &str = "*";
+
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub name: String,
+    pub token: Option<String>,
+    pub password: Option<String>,
+    pub publish_topics: Vec<String>,
+    pub read_topics: Vec<String>,
+    // Whether messages read are prefixed with their trace context, for readers which continue the trace.
+    pub forward_trace_context: bool,
+}
+
+impl Identity {
+    pub fn can_publish(&self, topic: &str) -> bool {
+        allows(&self.publish_topics, topic)
+    }
+
+    pub fn can_read(&self, topic: &str) -> bool {
+        allows(&self.read_topics, topic)
+    }
+}
+
+pub struct AccessControl {
+    authentication_required: bool,
+    identities: Vec<Identity>,
+}
+
+impl AccessControl {
+    pub fn new(authentication_required: bool, identities: Vec<Identity>) -> Self {
+        Self {
+            authentication_required,
+            identities,
+        }
+    }
+
+    // Credentials are either '<token>' or '<username> <password>', as sent by 'auth' command.
+    pub fn authenticate(&self, credentials: &str) -> Option<&Identity> {
+        let credentials: Vec<&str> = credentials.split_whitespace().collect();
+
+        match credentials[..] {
+            [token] => self
+                .identities
+                .iter()
+                .find(|identity| matches(identity.token.as_deref(), token)),
+            [username, password] => self.identities.iter().find(|identity| {
+                identity.name == username && matches(identity.password.as_deref(), password)
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn can_publish(&self, identity: Option<&Identity>, topic: &str) -> bool {
+        match identity {
+            Some(identity) => identity.can_publish(topic),
+            None => !self.authentication_required,
+        }
+    }
+
+    pub fn can_read(&self, identity: Option<&Identity>, topic: &str) -> bool {
+        match identity {
+            Some(identity) => identity.can_read(topic),
+            None => !self.authentication_required,
+        }
+    }
+}
+
+// Compares a secret in constant time for secrets of the same length, so replies don't leak how much of it matched.
+fn matches(secret: Option<&str>, credential: &str) -> bool {
+    match secret {
+        Some(secret) if secret.len() == credential.len() => {
+            secret
+                .bytes()
+                .zip(credential.bytes())
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0
+        }
+        _ => false,
+    }
+}
+
+fn allows(topics: &[String], topic: &str) -> bool {
+    topics
+        .iter()
+        .any(|allowed| allowed == ALL_TOPICS || allowed == topic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(name: &str, token: Option<&str>, password: Option<&str>) -> Identity {
+        Identity {
+            name: name.to_string(),
+            token: token.map(str::to_string),
+            password: password.map(str::to_string),
+            publish_topics: vec!["telemetry".to_string()],
+            read_topics: vec![ALL_TOPICS.to_string()],
+            forward_trace_context: false,
+        }
+    }
+
+    fn access_control(authentication_required: bool) -> AccessControl {
+        AccessControl::new(
+            authentication_required,
+            vec![
+                identity("host", Some("host-token"), None),
+                identity("operator", None, Some("operator-password")),
+            ],
+        )
+    }
+
+    #[test]
+    fn authenticates_by_token_or_password() {
+        let access_control = access_control(true);
+
+        let host = access_control.authenticate("host-token").unwrap();
+        assert_eq!(host.name, "host");
+        let operator = access_control
+            .authenticate("operator operator-password")
+            .unwrap();
+        assert_eq!(operator.name, "operator");
+
+        assert!(access_control.authenticate("wrong-token").is_none());
+        assert!(access_control.authenticate("operator host-token").is_none());
+        assert!(access_control.authenticate("").is_none());
+        assert!(access_control.authenticate("host-toke").is_none());
+        assert!(access_control.authenticate("host-tokens").is_none());
+    }
+
+    #[test]
+    fn checks_identity_topics() {
+        let access_control = access_control(true);
+        let host = access_control.authenticate("host-token");
+
+        assert!(access_control.can_publish(host, "telemetry"));
+        assert!(!access_control.can_publish(host, "alert"));
+        assert!(access_control.can_read(host, "alert"));
+    }
+
+    #[test]
+    fn anonymous_connections_need_no_authentication_to_be_required() {
+        assert!(!access_control(true).can_read(None, "telemetry"));
+        assert!(access_control(false).can_read(None, "telemetry"));
+        assert!(access_control(false).can_publish(None, "telemetry"));
+    }
+}
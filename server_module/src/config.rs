@@ -1,44 +1,187 @@
-use toml::Value;
+use crate::auth::Identity;
+use anyhow::{bail, Result};
+use guest_sdk::Config;
+use std::time::Duration;
+
+// Credentials in the shipped config start with this prefix until replaced.
+const PLACEHOLDER_PREFIX: &str = "change-me";
+// Commands and the error reply prefix, a topic of the same name would be read as a command or its replies as errors.
+const RESERVED_TOPICS: &[&str] = &["read", "auth", "error"];
 
-// TODO: Add anyhow Result
 pub struct Configuration {
-    config_value: Value,
+    // Buffer size when data is read from incoming stream
+    pub data_read_buffer_size: u32,
+    // Interval at which delayed messages are made visible, expired ones removed and topic sizes reported
+    pub receiver_loop_interval: Duration,
+    // Topic expired messages are moved to, None when expired messages are dropped
+    pub deadletter_topic: Option<String>,
+    // Directory queued messages are written to on shutdown and restored from on start, None when they are dropped
+    pub queue_persistence_directory: Option<String>,
+    pub topics: Vec<String>,
+    // Whether connections must authenticate before publishing or reading
+    pub authentication_required: bool,
+    // Identities allowed to connect, with their credentials and topic permissions
+    pub identities: Vec<Identity>,
 }
 
 impl Configuration {
-    pub fn new(configfilecontents: String) -> Self {
-        Self {
-            config_value: configfilecontents.parse::<Value>().unwrap(),
+    pub fn load(config_file_path: &str) -> Result<Self> {
+        Self::new(&Config::load(config_file_path)?)
+    }
+
+    pub fn new(config: &Config) -> Result<Self> {
+        // Expired messages are moved to dead letter topic unless configured to be dropped.
+        let deadletter_topic = match config.get::<String>("expired_message_action")?.as_str() {
+            "drop" => None,
+            _ => Some(config.get("deadletter_topic")?),
+        };
+
+        let mut identities: Vec<Identity> = vec![];
+        for (name, identity) in config.sections("identities") {
+            identities.push(Identity {
+                name,
+                token: identity.get_optional("token")?,
+                password: identity.get_optional("password")?,
+                publish_topics: identity.list("publish_topics")?,
+                read_topics: identity.list("read_topics")?,
+                forward_trace_context: identity.get_or("forward_trace_context", false)?,
+            });
+        }
+
+        // Shipped config has placeholder credentials, refuse to start with them rather than accept well known secrets.
+        let authentication_required = config.get("authentication_required")?;
+        if authentication_required {
+            for identity in &identities {
+                let credentials = [&identity.token, &identity.password];
+                if credentials
+                    .iter()
+                    .any(|credential| credential.as_deref().is_some_and(is_placeholder))
+                {
+                    bail!(
+                        "Identity '{}' has a placeholder credential, set its token or password before requiring authentication.",
+                        identity.name
+                    );
+                }
+            }
+        }
+
+        let topics = config.list("topics")?;
+        if let Some(topic) = topics
+            .iter()
+            .find(|topic| RESERVED_TOPICS.contains(&topic.as_str()))
+        {
+            bail!("Topic name '{topic}' is reserved.");
         }
+
+        Ok(Self {
+            data_read_buffer_size: config.get("data_read_buffer_size")?,
+            receiver_loop_interval: Duration::from_millis(
+                config.get("receiver_loop_interval_in_milliseconds")?,
+            ),
+            deadletter_topic,
+            queue_persistence_directory: config.get_optional("queue_persistence_directory")?,
+            topics,
+            authentication_required,
+            identities,
+        })
     }
+}
+
+fn is_placeholder(credential: &str) -> bool {
+    credential.starts_with(PLACEHOLDER_PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "data_read_buffer_size = '1024'
+receiver_loop_interval_in_milliseconds = '500'
+topics = 'alert telemetry'
+expired_message_action = 'drop'
+queue_persistence_directory = '/scratch/queues'
+authentication_required = 'true'
 
-    // Returns buffer size when data is read from incoming stream
-    pub fn data_read_buffer_size(&self) -> u32 {
-        self.config_value["data_read_buffer_size"]
-            .as_str()
-            .unwrap()
-            .parse::<u32>()
-            .unwrap()
+[identities.host]
+token = 'host-token'
+publish_topics = 'alert telemetry'
+read_topics = 'telemetry'
+forward_trace_context = 'true'
+
+[identities.operator]
+password = 'operator-password'
+";
+
+    #[test]
+    fn reads_configuration() {
+        let config = Configuration::new(&Config::parse(CONFIG).unwrap()).unwrap();
+
+        assert_eq!(config.data_read_buffer_size, 1024);
+        assert_eq!(config.receiver_loop_interval, Duration::from_millis(500));
+        assert_eq!(config.topics, ["alert", "telemetry"]);
+        assert_eq!(config.deadletter_topic, None);
+        assert_eq!(
+            config.queue_persistence_directory.as_deref(),
+            Some("/scratch/queues")
+        );
+        assert!(config.authentication_required);
+
+        assert_eq!(config.identities.len(), 2);
+        let host = &config.identities[0];
+        assert_eq!(host.name, "host");
+        assert_eq!(host.token.as_deref(), Some("host-token"));
+        assert_eq!(host.password, None);
+        assert_eq!(host.publish_topics, ["alert", "telemetry"]);
+        assert_eq!(host.read_topics, ["telemetry"]);
+        assert!(host.forward_trace_context);
+        let operator = &config.identities[1];
+        assert_eq!(operator.password.as_deref(), Some("operator-password"));
+        assert!(operator.publish_topics.is_empty());
+        assert!(!operator.forward_trace_context);
     }
 
-    // Returns delay in milliseconds for message receiver/read loop
-    pub fn receiver_loop_interval_in_milliseconds(&self) -> u64 {
-        self.config_value["data_read_buffer_size"]
-            .as_str()
-            .unwrap()
-            .parse::<u64>()
-            .unwrap()
+    #[test]
+    fn dead_letters_expired_messages_unless_dropped() {
+        let config = CONFIG.replace(
+            "expired_message_action = 'drop'",
+            "expired_message_action = 'deadletter'\ndeadletter_topic = 'deadletter'",
+        );
+        let config = Configuration::new(&Config::parse(&config).unwrap()).unwrap();
+
+        assert_eq!(config.deadletter_topic.as_deref(), Some("deadletter"));
     }
 
-    pub fn topics(&self) -> Vec<String> {
-        let mut topics: Vec<String> = vec![];
+    #[test]
+    fn queued_messages_are_not_persisted_without_directory() {
+        let config = CONFIG.replace("queue_persistence_directory = '/scratch/queues'\n", "");
+        let config = Configuration::new(&Config::parse(&config).unwrap()).unwrap();
 
-        let topics_string = self.config_value["topics"].as_str().unwrap();
+        assert_eq!(config.queue_persistence_directory, None);
+    }
 
-        for topic in topics_string.split_whitespace() {
-            topics.push(topic.to_string());
-        }
+    #[test]
+    fn fails_on_invalid_value() {
+        let config = CONFIG.replace("'1024'", "'large'");
+
+        assert!(Configuration::new(&Config::parse(&config).unwrap()).is_err());
+    }
+
+    #[test]
+    fn fails_on_reserved_topic_name() {
+        let config = CONFIG.replace("'alert telemetry'\nexpired", "'alert error'\nexpired");
+
+        assert!(Configuration::new(&Config::parse(&config).unwrap()).is_err());
+    }
+
+    #[test]
+    fn fails_on_placeholder_credentials_when_authentication_is_required() {
+        let config = CONFIG.replace("'operator-password'", "'change-me-operator-password'");
+        assert!(Configuration::new(&Config::parse(&config).unwrap()).is_err());
 
-        topics
+        let config = config.replace(
+            "authentication_required = 'true'",
+            "authentication_required = 'false'",
+        );
+        assert!(Configuration::new(&Config::parse(&config).unwrap()).is_ok());
     }
 }
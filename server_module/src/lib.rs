@@ -1,58 +1,131 @@
-// Export WIT bindings in Rust lang from wasm module to be used by host code.
-wit_bindgen_rust::import!("../wits/hostobservability.wit");
-wit_bindgen_rust::export!("../wits/wasmserverfunctions.wit");
-
+mod auth;
 mod config;
-
-use anyhow::Result;
-use queues::*;
+mod message;
+mod persistence;
+mod topic;
+
+use anyhow::{Context, Result};
+use auth::{AccessControl, Identity};
+use guest_sdk::{debug, error, info, metrics, runtime, warn, Lifecycle};
+use message::Message;
 use std::collections::HashMap;
-use std::fs;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::broadcast::{self};
 use tokio::sync::mpsc::{self, UnboundedSender};
-use tokio::time::sleep;
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+use topic::Topic;
+
+guest_sdk::export_module!(ServerModule);
 
-const MODULE_NAME: &str = "Psuedo Pub-Sub Messaging";
+// Reply to a read of a topic with no visible message.
+const EMPTY_REPLY: &str = "empty";
+// Longest wait for a command, so shutdown requests and housekeeping are not held up by an idle server.
+const COMMAND_WAIT_TIMEOUT: Duration = Duration::from_millis(100);
 
 #[derive(Debug)]
 enum Command {
-    Get { key: String },
-    Set { key: String, val: String },
+    // Reply goes back to the connection which sent the read only.
+    Get {
+        key: String,
+        reply: oneshot::Sender<Option<Message>>,
+    },
+    Set {
+        key: String,
+        message: Message,
+    },
 }
 
-struct Wasmserverfunctions;
-
-impl wasmserverfunctions::Wasmserverfunctions for Wasmserverfunctions {
-    // Initialise module with required configuration.
-    fn init(config_file_path: String, preopened_socket_fd: u32) {
-        let config_file_content = fs::read_to_string(config_file_path).unwrap();
-        let server_config = config::Configuration::new(config_file_content);
-        let receiver_loop_interval_in_milliseconds =
-            server_config.receiver_loop_interval_in_milliseconds();
-        let data_read_buffer_size = server_config.data_read_buffer_size();
+// Topics left once the server has drained on shutdown, written to persistence directory by shutdown if configured.
+struct ServerModule {
+    queue_persistence_directory: Option<String>,
+    drained_topics: Option<HashMap<String, Topic>>,
+}
 
-        hostobservability::loginfo(
-            MODULE_NAME,
-            &format!("Initialising module with: file descriptor: '{preopened_socket_fd}', read buffer size: '{data_read_buffer_size}', topic queues: '{:?}'", server_config.topics()));
+impl Lifecycle for ServerModule {
+    // Initialise module with required configuration, serving on the first socket pre-opened by the host.
+    fn init(config_file_path: &str, socket_fds: &[u32]) -> Result<Self> {
+        let preopened_socket_fd = *socket_fds
+            .first()
+            .context("Server module requires a pre-opened socket.")?;
+        let server_config = config::Configuration::load(config_file_path)?;
+
+        info!(
+            "Initialising module.";
+            "file_descriptor" => preopened_socket_fd,
+            "read_buffer_size" => server_config.data_read_buffer_size,
+            "topic_queues" => server_config.topics.join(" "),
+        );
 
         // Pre-create topics here from configuration for now, and make it dynamic later.
-        let mut topics: HashMap<String, Queue<String>> = HashMap::new();
-        for topic in server_config.topics() {
-            let queue: Queue<String> = queue![];
-            topics.insert(topic, queue);
+        let mut topics: HashMap<String, Topic> = HashMap::new();
+        for topic in &server_config.topics {
+            topics.insert(topic.to_string(), Topic::new());
+        }
+
+        // Messages still queued at last shutdown are delivered before new ones.
+        let queue_persistence_directory = server_config.queue_persistence_directory;
+        if let Some(directory) = &queue_persistence_directory {
+            match persistence::restore(directory, &mut topics) {
+                Ok(0) => {}
+                Ok(restored) => info!("Restored queued messages."; "message_count" => restored),
+                Err(e) => warn!("Failed to restore queued messages; error = {e}"),
+            }
         }
 
-        // Starts server on the pre-opened socket provided by WASI
-        run_server(
+        let access_control = AccessControl::new(
+            server_config.authentication_required,
+            server_config.identities,
+        );
+
+        // Starts server on the pre-opened socket provided by WASI, returns once drained after shutdown is requested.
+        let topics = run_server(
             preopened_socket_fd,
-            data_read_buffer_size,
-            receiver_loop_interval_in_milliseconds,
+            server_config.data_read_buffer_size,
+            server_config.receiver_loop_interval,
             topics,
-        )
-        .unwrap();
+            server_config.deadletter_topic,
+            access_control,
+        )?;
+
+        Ok(Self {
+            queue_persistence_directory,
+            drained_topics: Some(topics),
+        })
+    }
+
+    fn shutdown(&mut self, deadline: Duration) {
+        let mut topics = match self.drained_topics.take() {
+            Some(topics) => topics,
+            None => return,
+        };
+
+        // Without a persistence directory the server only drains, queued messages are dropped.
+        let directory = match &self.queue_persistence_directory {
+            Some(directory) => directory,
+            None => {
+                let dropped: usize = topics.values_mut().map(|topic| topic.drain().len()).sum();
+                info!(
+                    "No queue persistence directory configured, queued messages dropped, shutting down.";
+                    "message_count" => dropped,
+                );
+                return;
+            }
+        };
+
+        match persistence::persist(directory, &mut topics, deadline) {
+            Ok(skipped) if skipped.is_empty() => info!(
+                "Queued messages written, shutting down.";
+                "directory" => directory,
+            ),
+            Ok(skipped) => error!(
+                "Queued messages of some topics were not written before the shutdown deadline or failed to be written, they were dropped.";
+                "topics" => skipped.join(" "),
+            ),
+            Err(e) => error!("Failed to write queued messages; error = {e}"),
+        }
     }
 }
 
@@ -60,105 +133,177 @@ impl wasmserverfunctions::Wasmserverfunctions for Wasmserverfunctions {
 async fn run_server(
     fd: u32,
     data_read_buffer_size: u32,
-    receiver_loop_interval_in_milliseconds: u64,
-    mut topics: HashMap<String, Queue<String>>,
-) -> Result<()> {
+    receiver_loop_interval: Duration,
+    mut topics: HashMap<String, Topic>,
+    deadletter_topic: Option<String>,
+    access_control: AccessControl,
+) -> Result<HashMap<String, Topic>> {
+    let access_control = Arc::new(access_control);
     let listener = get_tcplistener(fd).await?;
 
     let (cmd_sender, mut cmd_receiver) = mpsc::unbounded_channel::<Command>();
-    // Create a broadcast channel (1:many) which can serve as pipe for sending susbcription cmd response to connections.
-    let (cmd_response_sender, _cmd_response_receiver) = broadcast::channel::<(String, String)>(2);
-    let cmd_response_sender_clone = cmd_response_sender.clone();
 
-    // Get/Set command receive task loop.
-    tokio::task::spawn(async move {
+    // Get/Set command receive task loop, returns topics once shutdown is requested.
+    let mut cmd_task = tokio::task::spawn(async move {
+        let mut next_housekeeping = Instant::now();
+
         loop {
-            for topic in &topics {
-                hostobservability::loginfo(
-                    MODULE_NAME,
-                    &format!("Topic: {0}, Size: {1}", topic.0, topic.1.size()),
-                );
+            // Commands are served as they arrive, housekeeping runs once per receiver loop interval.
+            if Instant::now() >= next_housekeeping {
+                housekeeping(&mut topics, deadletter_topic.as_deref());
+                next_housekeeping = Instant::now() + receiver_loop_interval;
             }
 
-            while let Ok(cmd) = cmd_receiver.try_recv() {
-                match cmd {
-                    Command::Set { key, val } => {
-                        if topics.contains_key(&key) {
-                            // Store items in topic's queue
-                            topics.get_mut(&key).unwrap().add(val.to_string()).unwrap();
-                        }
-                    }
-                    Command::Get { key } => {
-                        if topics.contains_key(&key) {
-                            // Remove items from topic's queue
-                            let queue = topics.get_mut(&key).unwrap();
-
-                            if queue.size() > 0 {
-                                let val = topics.get_mut(&key).unwrap().remove().unwrap();
-                                cmd_response_sender_clone
-                                    .send((key, val.to_string()))
-                                    .unwrap();
-                                // hostobservability::loginfo(MODULE_NAME,  &format!("Cmd response sent with value {}", val));
-                            } else {
-                                hostobservability::loginfo(MODULE_NAME,  &format!("Cannot retrieve element from empty queue '{key}', returning 'empty'."));
-                                cmd_response_sender_clone
-                                    .send((key, "empty".to_string()))
-                                    .unwrap();
-                            }
-                        }
-                    }
-                }
+            // Waiting times out so shutdown requests are noticed while no commands arrive.
+            if let Ok(Some(cmd)) = timeout(COMMAND_WAIT_TIMEOUT, cmd_receiver.recv()).await {
+                handle_command(&mut topics, deadletter_topic.as_deref(), cmd);
             }
 
-            // Wait for configured time after each receive loop iteration
-            sleep(Duration::from_millis(
-                receiver_loop_interval_in_milliseconds,
-            ))
-            .await;
+            // Commands received before shutdown was requested are applied, nothing more is accepted.
+            if runtime::shutdown_requested() {
+                while let Ok(cmd) = cmd_receiver.try_recv() {
+                    handle_command(&mut topics, deadletter_topic.as_deref(), cmd);
+                }
+
+                info!("Shutdown requested, server drained.");
+                return topics;
+            }
         }
     });
 
     // Connection receive task loop.
     loop {
-        // Asynchronously wait for an inbound connection.
-        let stream_res = listener.accept().await;
+        // Asynchronously wait for an inbound connection, until the command task has drained.
+        let stream_res = tokio::select! {
+            stream_res = listener.accept() => stream_res,
+            topics = &mut cmd_task => return Ok(topics?),
+        };
 
         if let Err(e) = stream_res {
-            hostobservability::loginfo(
-                MODULE_NAME,
-                &format!("Failed to accept connection; error = {}", e),
-            );
+            error!("Failed to accept connection; error = {}", e);
             continue;
         }
 
         let (stream, _addr) = stream_res?;
 
-        // hostobservability::loginfo(MODULE_NAME, &format!("Connection received on a preopened socket address {0:?}", addr));
+        // debug!("Connection received on a preopened socket address {0:?}", addr);
 
         // Clone sender so it can be used by a separate task.
         let cmd_sender_clone = cmd_sender.clone();
-        let cmd_response_subscriber = cmd_response_sender.subscribe();
+        let access_control_clone = access_control.clone();
 
         tokio::task::spawn(async move {
             if let Err(e) = process(
                 stream,
                 data_read_buffer_size,
                 cmd_sender_clone,
-                cmd_response_subscriber,
+                access_control_clone,
             )
             .await
             {
-                hostobservability::loginfo(
-                    MODULE_NAME,
-                    &format!("failed to process connection; error = {}", e),
-                );
+                error!("failed to process connection; error = {}", e);
             }
         });
     }
 }
 
+// Makes delayed messages visible, removes expired ones and reports topic sizes.
+fn housekeeping(topics: &mut HashMap<String, Topic>, deadletter_topic: Option<&str>) {
+    let now = Instant::now();
+    let mut expired: Vec<(String, Message)> = vec![];
+
+    for (name, topic) in topics.iter_mut() {
+        topic.promote_visible(now);
+        expired.extend(
+            topic
+                .remove_expired(now)
+                .into_iter()
+                .map(|message| (name.to_string(), message)),
+        );
+    }
+
+    handle_expired(topics, deadletter_topic, expired);
+
+    for topic in topics.iter() {
+        debug!("Topic size."; "topic" => topic.0, "size" => topic.1.size());
+        metrics::gauge_set(
+            "pubsub_topic_size",
+            topic.1.size() as f64,
+            &[("topic", topic.0)],
+        );
+    }
+}
+
+fn handle_command(
+    topics: &mut HashMap<String, Topic>,
+    deadletter_topic: Option<&str>,
+    cmd: Command,
+) {
+    match cmd {
+        Command::Set { key, message } => {
+            if topics.contains_key(&key) {
+                debug!(
+                    "Message published to topic '{key}' by '{}'.",
+                    message.source.as_deref().unwrap_or("unknown")
+                );
+
+                // Store items in topic's queue
+                topics.get_mut(&key).unwrap().add(message);
+            }
+        }
+        Command::Get { key, reply } => {
+            if topics.contains_key(&key) {
+                // Remove items from topic's queue, skipping any which expired since last housekeeping.
+                let (message, expired) = topics.get_mut(&key).unwrap().next(Instant::now());
+
+                handle_expired(
+                    topics,
+                    deadletter_topic,
+                    expired.into_iter().map(|m| (key.to_string(), m)).collect(),
+                );
+
+                if message.is_none() {
+                    debug!("Cannot retrieve element from empty queue '{key}', returning 'empty'.");
+                }
+                // Connection may have dropped while waiting.
+                let _ = reply.send(message);
+            }
+        }
+    }
+}
+
+// Moves expired messages to dead letter topic, or drops them when no dead letter topic is configured.
+fn handle_expired(
+    topics: &mut HashMap<String, Topic>,
+    deadletter_topic: Option<&str>,
+    expired: Vec<(String, Message)>,
+) {
+    for (topic, message) in expired {
+        match deadletter_topic {
+            // Messages expiring on the dead letter topic itself are dropped.
+            Some(deadletter_topic)
+                if deadletter_topic != topic && topics.contains_key(deadletter_topic) =>
+            {
+                warn!("Message expired on topic '{topic}', moving it to '{deadletter_topic}'.");
+
+                topics.get_mut(deadletter_topic).unwrap().add(Message {
+                    value: message.value,
+                    source: message.source,
+                    traceparent: message.traceparent,
+                    expires_at: None,
+                    visible_at: None,
+                });
+            }
+            _ => {
+                warn!("Message expired on topic '{topic}', dropping it.");
+            }
+        }
+    }
+}
+
 async fn get_tcplistener(fd: u32) -> Result<TcpListener> {
-    use std::os::wasi::io::FromRawFd;
+    // Raw file descriptors are shared by wasi and unix, so the server also runs natively in tests.
+    use std::os::fd::FromRawFd;
     // Use file descriptor passed in for the preopened socket, this must match in the calling host's WASI configuration.
     let stdlistener = unsafe { std::net::TcpListener::from_raw_fd(fd.try_into()?) };
     stdlistener.set_nonblocking(true)?;
@@ -170,57 +315,363 @@ async fn process(
     mut stream: TcpStream,
     data_read_buffer_size: u32,
     cmd_sender: UnboundedSender<Command>,
-    mut cmd_response_subscriber: broadcast::Receiver<(String, String)>,
+    access_control: Arc<AccessControl>,
 ) -> Result<()> {
+    // Identity authenticated on this connection, if any.
+    let mut identity: Option<Identity> = None;
+
     loop {
         let mut buf = vec![0; data_read_buffer_size.try_into()?];
         let n = stream.read(&mut buf).await?;
         buf.truncate(n); // truncate any additional bytes from buffer vector.
 
         if n == 0 {
-            hostobservability::loginfo(MODULE_NAME, "Connection dropped.");
+            debug!("Connection dropped.");
             return Ok(());
         }
 
-        let mut buf_str = std::str::from_utf8(&buf)?.to_string();
+        let buf_str = std::str::from_utf8(&buf)?;
         // Split only first word as command or topic name and rest as value for that.
-        let split_index = buf_str.find(' ').unwrap();
-        let split_buf_str = buf_str.split_at_mut(split_index);
+        let (cmd_topic, cmd_topic_value) = match buf_str.split_once(' ') {
+            Some((cmd_topic, cmd_topic_value)) => (cmd_topic.trim(), cmd_topic_value.trim()),
+            None => {
+                reject(&mut stream, identity.as_ref(), "malformed command").await?;
+                continue;
+            }
+        };
 
-        let cmd_topic = split_buf_str.0.trim();
-        let cmd_topic_value = split_buf_str.1.trim();
+        if "auth".eq(cmd_topic) {
+            match access_control.authenticate(cmd_topic_value) {
+                Some(authenticated) => {
+                    info!("Connection authenticated as '{}'.", authenticated.name);
 
-        if "read".eq(cmd_topic) {
-            hostobservability::loginfo(
-                MODULE_NAME,
-                &format!("Cmd '{cmd_topic} {cmd_topic_value}' received by connection task."),
-            );
+                    identity = Some(authenticated.clone());
+                    stream.write_all(b"ok").await?;
+                    stream.flush().await?;
+                }
+                None => reject(&mut stream, identity.as_ref(), "authentication failed").await?,
+            }
+        } else if "read".eq(cmd_topic) {
+            debug!("Cmd '{cmd_topic} {cmd_topic_value}' received by connection task.");
+
+            if !access_control.can_read(identity.as_ref(), cmd_topic_value) {
+                reject(
+                    &mut stream,
+                    identity.as_ref(),
+                    &format!("not authorised to read topic '{cmd_topic_value}'"),
+                )
+                .await?;
+                continue;
+            }
 
+            let (reply, reply_receiver) = oneshot::channel();
             cmd_sender.send(Command::Get {
                 key: cmd_topic_value.to_string(),
+                reply,
             })?;
 
-            // Wait for response to be received.
-            // This can be optimised as it takes 4-5 secs to respond here.
-            let (key, val) = cmd_response_subscriber.recv().await?;
+            // Command task drops reads of topics the server does not have without replying.
+            let key = cmd_topic_value;
+            let message = match reply_receiver.await {
+                Ok(message) => message,
+                Err(_) => {
+                    reject(
+                        &mut stream,
+                        identity.as_ref(),
+                        &format!("unknown topic '{key}'"),
+                    )
+                    .await?;
+                    continue;
+                }
+            };
+
+            // Trace context is only handed to identities which continue traces, e.g. the host, other readers get the
+            // message value alone.
+            let val = match message {
+                Some(message)
+                    if identity
+                        .as_ref()
+                        .is_some_and(|identity| identity.forward_trace_context) =>
+                {
+                    message.payload()
+                }
+                Some(message) => message.value,
+                None => EMPTY_REPLY.to_string(),
+            };
 
             // Write back read command response.
             let formatted_response = format!("{key} {val}");
             stream.write_all(formatted_response.as_bytes()).await?;
             stream.flush().await?;
 
-            hostobservability::loginfo(
-                MODULE_NAME,
-                &format!(
-                    "Cmd response received by connection task: '{:?}'",
-                    formatted_response
-                ),
+            debug!(
+                "Cmd response received by connection task: '{:?}'",
+                formatted_response
             );
+        } else if !access_control.can_publish(identity.as_ref(), cmd_topic) {
+            reject(
+                &mut stream,
+                identity.as_ref(),
+                &format!("not authorised to publish to topic '{cmd_topic}'"),
+            )
+            .await?;
         } else {
+            // Message value may be prefixed with '@ttl=<ms>' and '@delay=<ms>' options.
             cmd_sender.send(Command::Set {
                 key: cmd_topic.to_string(),
-                val: cmd_topic_value.to_string(),
+                message: Message::parse(cmd_topic_value),
             })?;
         }
     }
 }
+
+// Logs the rejected command and sends error reply back on the connection.
+async fn reject(stream: &mut TcpStream, identity: Option<&Identity>, reason: &str) -> Result<()> {
+    let identity_name = identity.map_or("anonymous", |identity| identity.name.as_str());
+
+    warn!("Rejected cmd from '{identity_name}': {reason}.");
+
+    stream
+        .write_all(format!("error {reason}").as_bytes())
+        .await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guest_sdk::logging::Level;
+    use guest_sdk::testing::FakeHost;
+    use std::io::{Read, Write};
+    use std::os::fd::IntoRawFd;
+    use std::path::{Path, PathBuf};
+    use std::{fs, net, thread};
+
+    const TOKEN: &str = "test-token";
+    const READER_TOKEN: &str = "reader-token";
+    // Longest wait for the server to apply a publish, which it does not reply to.
+    const PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
+    const PUBLISH_CHECK_INTERVAL: Duration = Duration::from_millis(5);
+
+    // Writes a config file to a directory of the test's own.
+    fn write_config(test_name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("server_module_{test_name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+
+        let config = format!(
+            "data_read_buffer_size = '256'
+receiver_loop_interval_in_milliseconds = '50'
+topics = 'telemetry deadletter'
+expired_message_action = 'deadletter'
+deadletter_topic = 'deadletter'
+queue_persistence_directory = '{}'
+authentication_required = 'true'
+
+[identities.host]
+token = '{TOKEN}'
+publish_topics = 'telemetry'
+read_topics = 'telemetry'
+forward_trace_context = 'true'
+
+[identities.reader]
+token = '{READER_TOKEN}'
+read_topics = 'telemetry'
+",
+            directory.join("queues").display()
+        );
+        fs::write(directory.join("config.toml"), config).unwrap();
+
+        directory
+    }
+
+    // Runs the server on a listener standing in for the host's pre-opened socket, until shutdown is requested.
+    fn start(directory: &Path) -> (net::TcpStream, thread::JoinHandle<ServerModule>) {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let fd = listener.into_raw_fd() as u32;
+        let config_file_path = directory.join("config.toml").display().to_string();
+
+        let server = thread::spawn(move || ServerModule::init(&config_file_path, &[fd]).unwrap());
+
+        (net::TcpStream::connect(address).unwrap(), server)
+    }
+
+    fn send(stream: &mut net::TcpStream, command: &str) -> String {
+        stream.write_all(command.as_bytes()).unwrap();
+
+        let mut reply = vec![0; 256];
+        let n = stream.read(&mut reply).unwrap();
+        String::from_utf8(reply[..n].to_vec()).unwrap()
+    }
+
+    // Commands have no framing, so a publish is only followed by the next command once the server has logged it as
+    // applied, otherwise both could be read together.
+    fn publish(host: &FakeHost, stream: &mut net::TcpStream, topic: &str, message: &str) {
+        let published = |host: &FakeHost| {
+            host.logs()
+                .iter()
+                .filter(|record| record.message.starts_with("Message published to topic"))
+                .count()
+        };
+        let before = published(host);
+
+        stream
+            .write_all(format!("{topic} {message}").as_bytes())
+            .unwrap();
+
+        let deadline = Instant::now() + PUBLISH_TIMEOUT;
+        while published(host) == before {
+            assert!(
+                Instant::now() < deadline,
+                "publish to '{topic}' was not applied"
+            );
+            thread::sleep(PUBLISH_CHECK_INTERVAL);
+        }
+    }
+
+    #[test]
+    fn authenticated_connection_publishes_and_reads() {
+        let host = FakeHost::start();
+        let (mut stream, server) = start(&write_config("publish_read"));
+
+        assert_eq!(send(&mut stream, &format!("auth {TOKEN}")), "ok");
+        publish(&host, &mut stream, "telemetry", "temperature=21");
+        assert_eq!(
+            send(&mut stream, "read telemetry"),
+            "telemetry @@ temperature=21"
+        );
+        assert_eq!(send(&mut stream, "read telemetry"), "telemetry empty");
+
+        host.request_shutdown();
+        server.join().unwrap();
+
+        assert!(host.logged(Level::Info, "Connection authenticated as 'host'."));
+        assert!(host.logged(Level::Info, "Shutdown requested, server drained."));
+    }
+
+    #[test]
+    fn trace_context_is_only_forwarded_to_identities_continuing_traces() {
+        let host = FakeHost::start();
+        let (mut stream, server) = start(&write_config("trace_context"));
+
+        send(&mut stream, &format!("auth {TOKEN}"));
+        publish(
+            &host,
+            &mut stream,
+            "telemetry",
+            "@traceparent=00-abc @@ temperature=21",
+        );
+        publish(
+            &host,
+            &mut stream,
+            "telemetry",
+            "@traceparent=00-def @@ temperature=22",
+        );
+        assert_eq!(
+            send(&mut stream, "read telemetry"),
+            "telemetry @traceparent=00-abc @@ temperature=21"
+        );
+
+        let mut reader = net::TcpStream::connect(stream.peer_addr().unwrap()).unwrap();
+        assert_eq!(send(&mut reader, &format!("auth {READER_TOKEN}")), "ok");
+        assert_eq!(
+            send(&mut reader, "read telemetry"),
+            "telemetry temperature=22"
+        );
+
+        host.request_shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn unauthenticated_connection_is_rejected() {
+        let host = FakeHost::start();
+        let (mut stream, server) = start(&write_config("rejected"));
+
+        assert_eq!(
+            send(&mut stream, "read telemetry"),
+            "error not authorised to read topic 'telemetry'"
+        );
+        assert_eq!(
+            send(&mut stream, "auth wrong-token"),
+            "error authentication failed"
+        );
+
+        host.request_shutdown();
+        server.join().unwrap();
+
+        assert!(host.logged(Level::Warn, "Rejected cmd from 'anonymous'"));
+    }
+
+    #[test]
+    fn malformed_command_is_rejected() {
+        let host = FakeHost::start();
+        let (mut stream, server) = start(&write_config("malformed"));
+
+        assert_eq!(send(&mut stream, "auth"), "error malformed command");
+        // Connection is still served.
+        assert_eq!(send(&mut stream, &format!("auth {TOKEN}")), "ok");
+        assert_eq!(
+            send(&mut stream, "read alert"),
+            "error not authorised to read topic 'alert'"
+        );
+
+        host.request_shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn read_of_unknown_topic_is_rejected() {
+        let host = FakeHost::start();
+        let directory = write_config("unknown_topic");
+        let config_file_path = directory.join("config.toml");
+        let config = fs::read_to_string(&config_file_path).unwrap().replace(
+            "read_topics = 'telemetry'\nforward",
+            "read_topics = 'telemetry alert'\nforward",
+        );
+        fs::write(&config_file_path, config).unwrap();
+        let (mut stream, server) = start(&directory);
+
+        send(&mut stream, &format!("auth {TOKEN}"));
+        assert_eq!(
+            send(&mut stream, "read alert"),
+            "error unknown topic 'alert'"
+        );
+
+        host.request_shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn queued_messages_are_restored_after_shutdown() {
+        let host = FakeHost::start();
+        let directory = write_config("persistence");
+
+        let (mut stream, server) = start(&directory);
+        send(&mut stream, &format!("auth {TOKEN}"));
+        publish(&host, &mut stream, "telemetry", "temperature=21");
+        host.request_shutdown();
+        server.join().unwrap().shutdown(Duration::from_secs(1));
+
+        assert!(host.logged(Level::Info, "Queued messages written, shutting down."));
+
+        // Fake host is reset for the restarted server.
+        drop(host);
+        let host = FakeHost::start();
+        let (mut stream, server) = start(&directory);
+        send(&mut stream, &format!("auth {TOKEN}"));
+        assert_eq!(
+            send(&mut stream, "read telemetry"),
+            "telemetry @@ temperature=21"
+        );
+
+        host.request_shutdown();
+        server.join().unwrap();
+
+        assert!(host.logged(Level::Info, "Restored queued messages."));
+    }
+}
@@ -0,0 +1,206 @@
+use std::time::{Duration, Instant};
+
+// Prefix used by publishers to attach options to a message e.g. 'telemetry @ttl=60000 @delay=500 {...}'.
+const OPTION_PREFIX: char = '@';
+const TTL_OPTION: &str = "ttl";
+const DELAY_OPTION: &str = "delay";
+const SOURCE_OPTION: &str = "source";
+const TRACEPARENT_OPTION: &str = "traceparent";
+// Ends the options explicitly, so a value which itself starts with '@name=value' is not read as options e.g.
+// 'telemetry @ttl=60000 @@ @ttl=5 is part of the value'. Host always ends the options it stamps with it.
+const END_OF_OPTIONS: &str = "@@";
+
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub value: String,
+    // Name of the publishing module as stamped by the host.
+    pub source: Option<String>,
+    // W3C trace context stamped by the host at publish, handed back to readers with the message.
+    pub traceparent: Option<String>,
+    // Message is dropped or dead-lettered once this time has passed.
+    pub expires_at: Option<Instant>,
+    // Message is not delivered to readers before this time.
+    pub visible_at: Option<Instant>,
+}
+
+impl Message {
+    // Parses leading '@name=value' options from the payload, rest of the payload becomes the message value.
+    pub fn parse(payload: &str) -> Self {
+        let now = Instant::now();
+        let mut expires_at = None;
+        let mut visible_at = None;
+        let mut source = None;
+        let mut traceparent = None;
+        let mut rest = payload.trim_start();
+
+        while rest.starts_with(OPTION_PREFIX) {
+            let (token, remaining) = rest.split_at(rest.find(' ').unwrap_or(rest.len()));
+
+            if token == END_OF_OPTIONS {
+                rest = remaining.strip_prefix(' ').unwrap_or(remaining);
+                break;
+            }
+
+            let (name, value) = match token[1..].split_once('=') {
+                Some(option) => option,
+                None => break,
+            };
+            let milliseconds = value.parse::<u64>().ok().map(Duration::from_millis);
+
+            match (name, milliseconds) {
+                (TTL_OPTION, Some(ttl)) => expires_at = Some(now + ttl),
+                (DELAY_OPTION, Some(delay)) => visible_at = Some(now + delay),
+                // Host stamps source and trace context first, later options cannot override them.
+                (SOURCE_OPTION, _) => {
+                    source.get_or_insert_with(|| value.to_string());
+                }
+                (TRACEPARENT_OPTION, _) => {
+                    traceparent.get_or_insert_with(|| value.to_string());
+                }
+                // Not a recognised option, treat it as part of the message value.
+                _ => break,
+            }
+
+            rest = remaining.trim_start();
+        }
+
+        Self {
+            value: rest.to_string(),
+            source,
+            traceparent,
+            expires_at,
+            visible_at,
+        }
+    }
+
+    // Returns message as sent to readers which continue its trace, prefixed with its source and trace context and
+    // always ended with '@@', so the value is never read as options.
+    pub fn payload(&self) -> String {
+        let mut options = self.stamped_options();
+        options.push(END_OF_OPTIONS.to_string());
+        options.push(self.value.to_string());
+        options.join(" ")
+    }
+
+    // Encodes message with its options, remaining ttl and delay are relative to now, parse reverses this.
+    pub fn encode(&self, now: Instant) -> String {
+        let mut options = self.stamped_options();
+
+        if let Some(expires_at) = self.expires_at {
+            let ttl = expires_at.saturating_duration_since(now).as_millis();
+            options.push(format!("{OPTION_PREFIX}{TTL_OPTION}={ttl}"));
+        }
+
+        if let Some(visible_at) = self.visible_at {
+            let delay = visible_at.saturating_duration_since(now).as_millis();
+            options.push(format!("{OPTION_PREFIX}{DELAY_OPTION}={delay}"));
+        }
+
+        options.push(END_OF_OPTIONS.to_string());
+        options.push(self.value.to_string());
+        options.join(" ")
+    }
+
+    // Options stamped by the host at publish.
+    fn stamped_options(&self) -> Vec<String> {
+        let mut options = vec![];
+
+        if let Some(source) = &self.source {
+            options.push(format!("{OPTION_PREFIX}{SOURCE_OPTION}={source}"));
+        }
+
+        if let Some(traceparent) = &self.traceparent {
+            options.push(format!("{OPTION_PREFIX}{TRACEPARENT_OPTION}={traceparent}"));
+        }
+
+        options
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+
+    pub fn is_visible(&self, now: Instant) -> bool {
+        !matches!(self.visible_at, Some(visible_at) if visible_at > now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_options_before_value() {
+        let message =
+            Message::parse("@source=telemetry @traceparent=00-abc @ttl=1000 @delay=500 reading");
+
+        assert_eq!(message.value, "reading");
+        assert_eq!(message.source.as_deref(), Some("telemetry"));
+        assert_eq!(message.traceparent.as_deref(), Some("00-abc"));
+        assert!(message.expires_at.is_some());
+        assert!(message.visible_at.is_some());
+    }
+
+    #[test]
+    fn first_source_and_trace_context_win() {
+        let message = Message::parse(
+            "@source=telemetry @source=gateway @traceparent=00-abc @traceparent=00-def x",
+        );
+
+        assert_eq!(message.source.as_deref(), Some("telemetry"));
+        assert_eq!(message.traceparent.as_deref(), Some("00-abc"));
+    }
+
+    #[test]
+    fn unrecognised_option_starts_value() {
+        let message = Message::parse("@ttl=1000 @unit=celsius 21");
+
+        assert_eq!(message.value, "@unit=celsius 21");
+        assert!(message.expires_at.is_some());
+    }
+
+    #[test]
+    fn end_of_options_keeps_option_like_value() {
+        let message = Message::parse("@source=telemetry @@ @ttl=5 is part of the value");
+
+        assert_eq!(message.value, "@ttl=5 is part of the value");
+        assert_eq!(message.expires_at, None);
+    }
+
+    #[test]
+    fn payload_ends_options_before_option_like_value() {
+        let message =
+            Message::parse("@source=telemetry @traceparent=00-abc @ttl=60000 @@ @ttl=5 x");
+        let parsed = Message::parse(&message.payload());
+
+        assert_eq!(
+            message.payload(),
+            "@source=telemetry @traceparent=00-abc @@ @ttl=5 x"
+        );
+        assert_eq!(parsed.value, "@ttl=5 x");
+        assert_eq!(parsed.expires_at, None);
+    }
+
+    #[test]
+    fn encode_round_trips_through_parse() {
+        let now = Instant::now();
+        let message = Message::parse("@source=telemetry @ttl=60000 @@ @delay=5 value");
+        let parsed = Message::parse(&message.encode(now));
+
+        assert_eq!(parsed.value, "@delay=5 value");
+        assert_eq!(parsed.source.as_deref(), Some("telemetry"));
+        assert!(parsed.expires_at.is_some());
+        assert_eq!(parsed.visible_at, None);
+    }
+
+    #[test]
+    fn expires_and_becomes_visible_relative_to_now() {
+        let now = Instant::now();
+        let message = Message::parse("@ttl=1000 @delay=500 value");
+
+        assert!(!message.is_expired(now));
+        assert!(message.is_expired(now + Duration::from_millis(1500)));
+        assert!(!message.is_visible(now));
+        assert!(message.is_visible(now + Duration::from_millis(600)));
+    }
+}
@@ -0,0 +1,177 @@
+use crate::message::Message;
+use crate::topic::Topic;
+use anyhow::Result;
+use guest_sdk::error;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// Queued messages of each topic are kept in '<directory>/<topic>.queue', one message per line with line breaks and
+// backslashes in it escaped.
+const QUEUE_FILE_EXTENSION: &str = "queue";
+
+// Writes queued messages of each topic to the persistence directory, topics left when deadline passes are not written.
+// A topic which fails to be written does not stop the others. Returns names of topics which were not written.
+pub fn persist(
+    directory: &str,
+    topics: &mut HashMap<String, Topic>,
+    deadline: Duration,
+) -> Result<Vec<String>> {
+    let started = Instant::now();
+    let now = Instant::now();
+    let mut skipped = vec![];
+
+    fs::create_dir_all(directory)?;
+
+    for (name, topic) in topics.iter_mut() {
+        if started.elapsed() >= deadline {
+            skipped.push(name.to_string());
+            continue;
+        }
+
+        let lines: Vec<String> = topic
+            .drain()
+            .iter()
+            .filter(|message| !message.is_expired(now))
+            .map(|message| escape(&message.encode(now)))
+            .collect();
+
+        if let Err(e) = fs::write(queue_file(directory, name), lines.join("\n")) {
+            error!("Failed to write queued messages of topic '{name}'; error = {e}");
+            skipped.push(name.to_string());
+        }
+    }
+
+    Ok(skipped)
+}
+
+// Adds messages persisted at last shutdown to their topics, queue files are removed once read.
+pub fn restore(directory: &str, topics: &mut HashMap<String, Topic>) -> Result<usize> {
+    let mut restored = 0;
+
+    for (name, topic) in topics.iter_mut() {
+        let path = queue_file(directory, name);
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => continue,
+        };
+
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            topic.add(Message::parse(&unescape(line)));
+            restored += 1;
+        }
+
+        fs::remove_file(&path)?;
+    }
+
+    Ok(restored)
+}
+
+fn queue_file(directory: &str, topic: &str) -> std::path::PathBuf {
+    Path::new(directory).join(format!("{topic}.{QUEUE_FILE_EXTENSION}"))
+}
+
+// Escapes backslashes and line breaks so a message is written on a single line.
+fn escape(record: &str) -> String {
+    let mut escaped = String::with_capacity(record.len());
+
+    for c in record.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// Reverses escape, unknown escapes are kept as they are.
+fn unescape(line: &str) -> String {
+    let mut record = String::with_capacity(line.len());
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            record.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => record.push('\n'),
+            Some('r') => record.push('\r'),
+            Some('\\') => record.push('\\'),
+            Some(other) => {
+                record.push('\\');
+                record.push(other);
+            }
+            None => record.push('\\'),
+        }
+    }
+
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaped_record_round_trips_on_single_line() {
+        let record = "@@ first line\nsecond line\r\nC:\\queues\\n";
+        let escaped = escape(record);
+
+        assert_eq!(escaped.lines().count(), 1);
+        assert_eq!(unescape(&escaped), record);
+    }
+
+    #[test]
+    fn multi_line_messages_are_restored() {
+        let directory = std::env::temp_dir().join(format!("persistence_{}", std::process::id()));
+        let directory = directory.display().to_string();
+        let mut topics = HashMap::from([("telemetry".to_string(), Topic::new())]);
+        let topic = topics.get_mut("telemetry").unwrap();
+        topic.add(Message::parse("first\nsecond"));
+        topic.add(Message::parse("third"));
+
+        persist(&directory, &mut topics, Duration::from_secs(1)).unwrap();
+        assert_eq!(restore(&directory, &mut topics).unwrap(), 2);
+
+        let topic = topics.get_mut("telemetry").unwrap();
+        let values: Vec<String> = topic.drain().into_iter().map(|m| m.value).collect();
+        assert_eq!(values, ["first\nsecond", "third"]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn topics_are_written_when_another_fails() {
+        let directory =
+            std::env::temp_dir().join(format!("persistence_failure_{}", std::process::id()));
+        // Directory in place of the alert topic's queue file fails its write.
+        fs::create_dir_all(directory.join("alert.queue")).unwrap();
+        let directory = directory.display().to_string();
+        let mut topics = HashMap::from([
+            ("alert".to_string(), Topic::new()),
+            ("telemetry".to_string(), Topic::new()),
+        ]);
+        topics.get_mut("alert").unwrap().add(Message::parse("lost"));
+        topics
+            .get_mut("telemetry")
+            .unwrap()
+            .add(Message::parse("kept"));
+
+        let skipped = persist(&directory, &mut topics, Duration::from_secs(1)).unwrap();
+
+        assert_eq!(skipped, ["alert"]);
+        assert_eq!(
+            fs::read_to_string(queue_file(&directory, "telemetry")).unwrap(),
+            "@@ kept"
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+use crate::message::Message;
+use queues::*;
+use std::time::Instant;
+
+// Holds messages for a single topic, delayed messages are kept aside until they become visible.
+pub struct Topic {
+    ready: Queue<Message>,
+    delayed: Vec<Message>,
+}
+
+impl Topic {
+    pub fn new() -> Self {
+        Self {
+            ready: queue![],
+            delayed: vec![],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.ready.size() + self.delayed.len()
+    }
+
+    pub fn add(&mut self, message: Message) {
+        if message.is_visible(Instant::now()) {
+            self.ready.add(message).unwrap();
+        } else {
+            self.delayed.push(message);
+        }
+    }
+
+    // Moves delayed messages which are now visible to the ready queue, in the order they were published.
+    pub fn promote_visible(&mut self, now: Instant) {
+        let (visible, delayed): (Vec<Message>, Vec<Message>) = self
+            .delayed
+            .drain(..)
+            .partition(|message| message.is_visible(now));
+
+        self.delayed = delayed;
+
+        for message in visible {
+            self.ready.add(message).unwrap();
+        }
+    }
+
+    // Removes and returns all expired messages from the topic.
+    pub fn remove_expired(&mut self, now: Instant) -> Vec<Message> {
+        let mut expired = vec![];
+        let mut ready: Queue<Message> = queue![];
+
+        while let Ok(message) = self.ready.remove() {
+            if message.is_expired(now) {
+                expired.push(message);
+            } else {
+                ready.add(message).unwrap();
+            }
+        }
+
+        self.ready = ready;
+
+        let (delayed_expired, delayed): (Vec<Message>, Vec<Message>) = self
+            .delayed
+            .drain(..)
+            .partition(|message| message.is_expired(now));
+
+        self.delayed = delayed;
+        expired.extend(delayed_expired);
+
+        expired
+    }
+
+    // Removes and returns all messages, ready messages first in the order they are delivered.
+    pub fn drain(&mut self) -> Vec<Message> {
+        let mut messages = vec![];
+
+        while let Ok(message) = self.ready.remove() {
+            messages.push(message);
+        }
+        messages.append(&mut self.delayed);
+
+        messages
+    }
+
+    // Returns next visible message, expired messages at the head of the queue are returned separately.
+    pub fn next(&mut self, now: Instant) -> (Option<Message>, Vec<Message>) {
+        self.promote_visible(now);
+
+        let mut expired = vec![];
+
+        while let Ok(message) = self.ready.remove() {
+            if message.is_expired(now) {
+                expired.push(message);
+            } else {
+                return (Some(message), expired);
+            }
+        }
+
+        (None, expired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn values(messages: &[Message]) -> Vec<&str> {
+        messages
+            .iter()
+            .map(|message| message.value.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn delivers_in_publish_order() {
+        let mut topic = Topic::new();
+        topic.add(Message::parse("first"));
+        topic.add(Message::parse("second"));
+
+        assert_eq!(topic.next(Instant::now()).0.unwrap().value, "first");
+        assert_eq!(topic.next(Instant::now()).0.unwrap().value, "second");
+        assert!(topic.next(Instant::now()).0.is_none());
+    }
+
+    #[test]
+    fn delayed_message_is_delivered_once_visible() {
+        let mut topic = Topic::new();
+        topic.add(Message::parse("@delay=500 delayed"));
+        topic.add(Message::parse("ready"));
+        let now = Instant::now();
+
+        assert_eq!(topic.size(), 2);
+        assert_eq!(topic.next(now).0.unwrap().value, "ready");
+        assert!(topic.next(now).0.is_none());
+        assert_eq!(
+            topic
+                .next(now + Duration::from_millis(600))
+                .0
+                .unwrap()
+                .value,
+            "delayed"
+        );
+    }
+
+    #[test]
+    fn expired_messages_are_returned_instead_of_delivered() {
+        let mut topic = Topic::new();
+        topic.add(Message::parse("@ttl=100 stale"));
+        topic.add(Message::parse("fresh"));
+        let later = Instant::now() + Duration::from_millis(200);
+
+        let (message, expired) = topic.next(later);
+
+        assert_eq!(message.unwrap().value, "fresh");
+        assert_eq!(values(&expired), ["stale"]);
+    }
+
+    #[test]
+    fn removes_expired_ready_and_delayed_messages() {
+        let mut topic = Topic::new();
+        topic.add(Message::parse("@ttl=100 stale"));
+        topic.add(Message::parse("@ttl=100 @delay=50 stale delayed"));
+        topic.add(Message::parse("fresh"));
+        let later = Instant::now() + Duration::from_millis(200);
+
+        let expired = topic.remove_expired(later);
+
+        assert_eq!(values(&expired), ["stale", "stale delayed"]);
+        assert_eq!(values(&topic.drain()), ["fresh"]);
+    }
+}
@@ -1,23 +1,29 @@
-use toml::Value;
+use anyhow::Result;
+use guest_sdk::Config;
+use std::time::Duration;
 
-// TODO: Add anyhow Result
 pub struct Configuration {
-    config_value: Value,
+    pub telemetry_interval: Duration,
+    // Maximum random delay added to each telemetry tick, spreads load when many devices share an interval.
+    pub telemetry_jitter: Duration,
+    // Time to live for published telemetry, stale readings are not delivered after this.
+    pub telemetry_ttl: Duration,
 }
 
 impl Configuration {
-    pub fn new(configfilecontents: String) -> Self {
-        Self {
-            config_value: configfilecontents.parse::<Value>().unwrap(),
-        }
+    pub fn load(config_file_path: &str) -> Result<Self> {
+        Self::new(&Config::load(config_file_path)?)
     }
 
-    // Returns telemetry interval in milliseconds
-    pub fn telemetry_interval_in_milliseconds(&self) -> u32 {
-        self.config_value["telemetry_interval_in_milliseconds"]
-            .as_str()
-            .unwrap()
-            .parse::<u32>()
-            .unwrap()
+    pub fn new(config: &Config) -> Result<Self> {
+        Ok(Self {
+            telemetry_interval: Duration::from_millis(
+                config.get("telemetry_interval_in_milliseconds")?,
+            ),
+            telemetry_jitter: Duration::from_millis(
+                config.get_or("telemetry_jitter_in_milliseconds", 0)?,
+            ),
+            telemetry_ttl: Duration::from_millis(config.get("telemetry_ttl_in_milliseconds")?),
+        })
     }
 }
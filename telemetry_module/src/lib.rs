@@ -1,50 +1,111 @@
-wit_bindgen_rust::import!("../wits/hostobservability.wit");
-wit_bindgen_rust::export!("../wits/wasmtelemetryfunctions.wit");
-
+// Telemetry module is built as a component (wasm32-wasip2), the SDK exports its lifecycle.
 mod config;
 
+use anyhow::Result;
+use guest_sdk::{
+    info, metrics,
+    pubsub::{self, PublishOptions},
+    timers::{self, MissedTickPolicy},
+    Lifecycle,
+};
 use rand::Rng;
-use std::fs;
-use std::thread::sleep;
 use std::time::Duration;
 
-const MODULE_NAME: &str = "Simulated Telemetry";
+guest_sdk::export_module!(TelemetryModule);
 
-struct Wasmtelemetryfunctions;
+struct TelemetryModule {
+    config: config::Configuration,
+}
 
-impl wasmtelemetryfunctions::Wasmtelemetryfunctions for Wasmtelemetryfunctions {
-    // Initialise module with required configuration.
-    #[tokio::main(flavor = "current_thread")]
-    async fn init(config_file_path: String) {
-        let configfilecontents = fs::read_to_string(config_file_path).unwrap();
+impl Lifecycle for TelemetryModule {
+    // Initialise module with required configuration, module schedules its work on timers.
+    fn init(config_file_path: &str, _socket_fds: &[u32]) -> Result<Self> {
+        let config = config::Configuration::load(config_file_path)?;
 
-        let telemetry_config = config::Configuration::new(configfilecontents);
-        let telemetry_interval_in_milliseconds =
-            telemetry_config.telemetry_interval_in_milliseconds();
+        info!(
+            "Initialising module.";
+            "telemetry_interval_in_milliseconds" => config.telemetry_interval.as_millis(),
+        );
 
-        hostobservability::loginfo(
-            MODULE_NAME,
-            &format!(
-                "Initialising module with telemetry interval of '{}' ms",
-                telemetry_interval_in_milliseconds
-            ),
+        // Host calls on_tick at the configured interval, readings missed while busy are not sent late.
+        timers::interval(
+            config.telemetry_interval,
+            config.telemetry_jitter,
+            MissedTickPolicy::Skip,
         );
 
+        Ok(Self { config })
+    }
+
+    fn on_tick(&mut self, _timer_id: u32) {
         // Generate temperature and pressure values randomly for simulation
         let mut random_number = rand::thread_rng();
+        let random_temp = random_number.gen_range(0.0..100.0);
+        let random_pressure = random_number.gen_range(0.0..50.0);
+
+        let telemetry_message = format!("{{\"device Id\" : \"001\", \"temperature\" : {random_temp:.2}, \"pressure\":{random_pressure:.2}}}");
+
+        // Readings older than the configured ttl are not forwarded by pubsub server module.
+        pubsub::publish_with_options(
+            "telemetry",
+            &telemetry_message,
+            PublishOptions {
+                ttl: Some(self.config.telemetry_ttl),
+                delay: None,
+            },
+        );
+        metrics::counter_increment("telemetry_readings_published_total", 1, &[]);
+    }
 
-        loop {
-            let random_temp = random_number.gen_range(0.0..100.0);
-            let random_pressure = random_number.gen_range(0.0..50.0);
+    // Host stops calling on_tick before shutdown, there is nothing buffered to flush.
+    fn shutdown(&mut self, _deadline: Duration) {
+        info!("Shutting down module.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guest_sdk::logging::Level;
+    use guest_sdk::testing::{FakeHost, MetricKind, Schedule};
+
+    const CONFIG_FILE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/config.toml");
+
+    #[test]
+    fn init_schedules_readings() {
+        let host = FakeHost::start();
+
+        TelemetryModule::init(CONFIG_FILE_PATH, &[]).unwrap();
+
+        let timers = host.timers();
+        assert_eq!(timers.len(), 1);
+        assert_eq!(
+            timers[0].schedule,
+            Schedule::Interval(Duration::from_millis(10))
+        );
+        assert_eq!(timers[0].jitter, Duration::from_millis(2));
+        assert_eq!(timers[0].policy, MissedTickPolicy::Skip);
+        assert!(host.logged(Level::Info, "Initialising module."));
+    }
 
-            let telemetry_message = format!("{{\"device Id\" : \"001\", \"temperature\" : {random_temp:.2}, \"pressure\":{random_pressure:.2}}}");
+    #[test]
+    fn tick_publishes_reading_with_ttl() {
+        let host = FakeHost::start();
+        let mut module = TelemetryModule::init(CONFIG_FILE_PATH, &[]).unwrap();
 
-            hostobservability::publish("telemetry", &telemetry_message);
+        module.on_tick(1);
 
-            // Wait for configured time.
-            sleep(Duration::from_millis(
-                telemetry_interval_in_milliseconds.into(),
-            ));
-        }
+        let published = host.published();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].topic, "telemetry");
+        assert!(published[0].payload.contains("\"temperature\""));
+        assert_eq!(published[0].ttl, Some(Duration::from_millis(60000)));
+        assert_eq!(published[0].delay, None);
+        assert!(host
+            .metrics()
+            .iter()
+            .any(|metric| metric.kind == MetricKind::Counter
+                && metric.name == "telemetry_readings_published_total"
+                && metric.value == 1.0));
     }
 }